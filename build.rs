@@ -1,4 +1,3 @@
-use hex;
 use serde::Deserialize;
 use std::{collections::HashMap, env, fs::File, io::Write, path::PathBuf, process::Command};
 
@@ -40,6 +39,7 @@ fn main() {
     // Tell Cargo that if the given file changes, to rerun this build script.
     println!("cargo::rerun-if-changed=contracts/executor.sol");
     println!("cargo::rerun-if-changed=contracts/proxy.sol");
+    println!("cargo::rerun-if-changed=contracts/mocks.sol");
 
     // Get the OUT_DIR environment variable
     let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR environment variable not set"));
@@ -89,4 +89,23 @@ fn main() {
         &hex::decode(proxy_binruntime).expect("failed to decode hex binary from solc output"),
     )
     .unwrap();
+
+    // Test mocks: only the init code is needed, they are deployed by the tests
+    let mocks_file = "contracts/mocks.sol";
+    let mocks_solc = build_get_json(mocks_file);
+    for (contract, bin_name) in [
+        ("MockWETH", "mock_weth.bin"),
+        ("MockMorpho", "mock_morpho.bin"),
+        ("MockAavePool", "mock_aave_pool.bin"),
+    ] {
+        let mock_outs = mocks_solc
+            .contracts
+            .get(&format!("{mocks_file}:{contract}"))
+            .expect("solc output didn't generate the mock contracts");
+        let mut file = File::create(out_dir.join(bin_name)).unwrap();
+        file.write_all(
+            &hex::decode(&mock_outs.bin).expect("failed to decode hex binary from solc output"),
+        )
+        .unwrap();
+    }
 }
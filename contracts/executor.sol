@@ -1,6 +1,12 @@
 // SPDX-License-Identifier: MIT
 pragma solidity ^0.8.0;
 
+/// @notice Subset of ERC-20 used by TOKENBALANCE, SNAPSHOT, CHECKPROFIT and SWEEP
+interface IERC20Balance {
+    function balanceOf(address account) external view returns (uint256);
+    function transfer(address to, uint256 amount) external returns (bool);
+}
+
 /**
  * @title Executor Contract
  * @notice A flexible contract that can execute a series of actions including flash loans
@@ -40,9 +46,59 @@ contract executor {
         DELEGATECALL,   // Perform delegate call
         SETCALLBACK,    // Set callback address
         SETFAIL,        // Enable revert on call failure
-        CLEARFAIL       // Disable revert on call failure
+        CLEARFAIL,      // Disable revert on call failure
+        COPYRETURN,     // Copy last call's return data into txData
+        SELFBALANCE,    // Write this contract's ETH balance into txData
+        TOKENBALANCE,   // Write an ERC-20 balance into txData
+        JUMP,           // Continue at another offset of the action stream
+        JUMPIFFAILED,   // Jump if the last call or create failed
+        JUMPIFWORD,     // Jump if a txData word compares true against a constant
+        STATICCALL,     // Perform read-only call
+        CREATE2,        // Deploy new contract at a salt-determined address
+        ARITH,          // Checked arithmetic on two txData words
+        ARITHI,         // Checked arithmetic on a txData word and a constant
+        SNAPSHOT,       // Record this contract's balance of a token (or ETH)
+        CHECKPROFIT,    // Revert unless a snapshotted balance grew by a minimum
+        SETGAS,         // Cap the gas of the next call
+        PAYCOINBASE,    // Send ETH to block.coinbase
+        MAXBLOCK,       // Revert after a given block number
+        DEADLINE,       // Revert after a given block timestamp
+        MAXBASEFEE,     // Revert above a given base fee
+        LOG,            // Emit a Checkpoint event with a slice of txData
+        SWEEP,          // Send this contract's whole balance of a token (or ETH)
+        STOREREG,       // Copy a txData word into a register
+        LOADREG,        // Copy a register into a txData word
+        SETVALUEBAL,    // Set ETH value to this contract's balance minus a reserve
+        SETVALUEDATA,   // Set ETH value to a txData word
+        CLEARDATA32,    // CLEARDATA with a uint32 size
+        SETDATA32,      // SETDATA with a uint32 offset and size
+        EXTCODECOPY32   // EXTCODECOPY with uint32 offsets and size
     }
 
+    /**
+     * @notice Raised when a CALL, DELEGATECALL or STATICCALL fails with the fail flag set,
+     *         or when a PAYCOINBASE or SWEEP transfer fails
+     * @param offset Byte offset of the failed action in its action stream
+     * @param reason Revert data of the failed call
+     */
+    error ActionFailed(uint256 offset, bytes reason);
+
+    /**
+     * @notice Emitted by LOG to mark a point reached by a flow
+     * @param tag Caller-chosen tag, e.g. a short right-padded string
+     * @param data Copy of the txData slice named by the action
+     */
+    event Checkpoint(bytes32 indexed tag, bytes data);
+
+    /**
+     * @notice Raised by CHECKPROFIT when the flow did not make enough profit
+     * @param token The token checked, address(0) for ETH
+     * @param balanceBefore Balance recorded by the latest SNAPSHOT of the token
+     * @param balanceAfter Balance at the time of the check
+     * @param minProfit Required increase over balanceBefore
+     */
+    error InsufficientProfit(address token, uint256 balanceBefore, uint256 balanceAfter, uint256 minProfit);
+
     /**
      * @dev Internal callback handler for flash loan protocols
      * @param calldata_offset The offset in calldata where execution instructions begin
@@ -137,28 +193,35 @@ contract executor {
         uint256 offset = 0;
         address target;        // Target address for calls
         uint256 value;        // ETH value for calls
+        uint256 gas_limit;    // Gas cap for the next call, 0 forwards all gas
         bool fail = false;    // Fail flag for call operations
+        bool success = true;  // Outcome of the last call or create
         bytes memory txData;  // Transaction data buffer
+        bytes memory snapshots; // SNAPSHOT records: abi.encode(token, balance) each
+        uint256[16] memory registers; // STOREREG/LOADREG values, zero at start
 
         // --- Action Loop ---
         unchecked{
             while (offset < data.length) {
+                uint256 op_offset = offset;
                 Action op = Action(uint8(data[offset]));
                 offset += 1;
 
                 if (op == Action.EOF) {
                     break;
                 }
-                else if (op == Action.CLEARDATA) {
+                else if (op == Action.CLEARDATA || op == Action.CLEARDATA32) {
                     uint256 size;
-                    (size, offset) = _parseUint16(data, offset);
+                    (size, offset) = _parseSize(data, offset, op == Action.CLEARDATA32);
                     txData = new bytes(size);
                 } 
-                else if (op == Action.SETDATA) {
+                else if (op == Action.SETDATA || op == Action.SETDATA32) {
+                    bool wide = op == Action.SETDATA32;
                     uint256 data_offset;
                     uint256 data_size;
-                    (data_offset, offset) = _parseUint16(data, offset);
-                    (data_size, offset) = _parseUint16(data, offset);               
+                    (data_offset, offset) = _parseSize(data, offset, wide);
+                    (data_size, offset) = _parseSize(data, offset, wide);
+                    _checkRange(txData, data_offset, data_size);
                     uint256 i;
                     // First loop: Copy full 32-byte words efficiently using assembly
                     for (i = 0; i < data_size/32; i++) {
@@ -182,7 +245,28 @@ contract executor {
                     (target, offset) = _parseAddress(data, offset);
                 } else if (op == Action.SETVALUE) {
                     (value, offset) =  _parseUint256(data, offset);
-                } else if (op == Action.EXTCODECOPY) {
+                } else if (op == Action.SETVALUEBAL) {
+                    // Sends everything but `reserve`, e.g. ETH just received
+                    // from an unwrap, without guessing the amount
+                    uint256 reserve;
+                    (reserve, offset) = _parseUint256(data, offset);
+                    uint256 balance = address(this).balance;
+                    require(balance >= reserve, "BALANCE_BELOW_RESERVE");
+                    value = balance - reserve;
+                } else if (op == Action.SETVALUEDATA) {
+                    // Reads the value from the txData word at data_offset, e.g. an
+                    // amount copied from a call's return data
+                    uint256 data_offset;
+                    (data_offset, offset) = _parseUint16(data, offset);
+                    _checkRange(txData, data_offset, 32);
+                    assembly {
+                        value := mload(add(add(txData, 0x20), data_offset))
+                    }
+                } else if (op == Action.SETGAS) {
+                    // Caps the gas of the next CALL, DELEGATECALL or STATICCALL,
+                    // so a griefing target cannot burn the rest of the flow's gas
+                    (gas_limit, offset) = _parseUint32(data, offset);
+                } else if (op == Action.EXTCODECOPY || op == Action.EXTCODECOPY32) {
                     // Parameters for extcodecopy:
                     // 1. address: 20-byte address of the contract to query
                     // 2. destOffset: memory position where code will be copied
@@ -192,10 +276,12 @@ contract executor {
                     uint256 data_offset;
                     uint256 code_offset;
                     uint256 size;
+                    bool wide = op == Action.EXTCODECOPY32;
                     (code_contract, offset) = _parseAddress(data, offset);
-                    (data_offset, offset) = _parseUint16(data, offset);
-                    (code_offset, offset) = _parseUint16(data, offset);
-                    (size, offset) = _parseUint16(data, offset);
+                    (data_offset, offset) = _parseSize(data, offset, wide);
+                    (code_offset, offset) = _parseSize(data, offset, wide);
+                    (size, offset) = _parseSize(data, offset, wide);
+                    _checkRange(txData, data_offset, size);
                     assembly {
                         // Memory layout for destination:
                         // txData   : array pointer
@@ -213,12 +299,13 @@ contract executor {
                     // txData contains the complete calldata including:
                     // - function selector (4 bytes)
                     // - encoded parameters (remaining bytes)
-                    bool success;
-                    (success, ) = target.call{value: value}(txData);
-                    if (fail) {
-                        require(success, "CALL_FAILED");
+                    // The EVM forwards at most 63/64 of gasleft() either way
+                    (success, ) = target.call{value: value, gas: gas_limit == 0 ? gasleft() : gas_limit}(txData);
+                    if (fail && !success) {
+                        _revertActionFailed(op_offset);
                     }
                     value = 0; // Reset value for safety
+                    gas_limit = 0; // Reset gas cap for safety
                 } else if (op == Action.CREATE) {                    
                     assembly {
                         // Memory layout for contract creation:
@@ -236,6 +323,29 @@ contract executor {
                             mload(txData)          // Size of initialization code
                         )
                     }
+                    success = target != address(0);
+                    if (fail && !success) {
+                        _revertActionFailed(op_offset);
+                    }
+                    value = 0; // Reset value after use
+                } else if (op == Action.CREATE2) {
+                    // Like CREATE, with the address derived from this contract's
+                    // address, the salt and keccak256(txData):
+                    // keccak256(0xff ++ address(this) ++ salt ++ keccak256(initcode))[12:]
+                    uint256 salt;
+                    (salt, offset) = _parseUint256(data, offset);
+                    assembly {
+                        target := create2(
+                            value,                  // ETH value for new contract
+                            add(txData, 0x20),     // Skip array length word
+                            mload(txData),         // Size of initialization code
+                            salt                   // Salt for the address
+                        )
+                    }
+                    success = target != address(0);
+                    if (fail && !success) {
+                        _revertActionFailed(op_offset);
+                    }
                     value = 0; // Reset value after use
                 } else if (op == Action.DELEGATECALL) {
                     // Perform delegatecall using current txData buffer
@@ -243,23 +353,370 @@ contract executor {
                     // - uses this contract's storage
                     // - uses this contract's ETH balance
                     // - msg.sender remains the original caller
-                    bool success;
-                    (success, ) = target.delegatecall(txData);
-                    if (fail) {
-                        require(success, "DELCALL_FAILED");
+                    (success, ) = target.delegatecall{gas: gas_limit == 0 ? gasleft() : gas_limit}(txData);
+                    if (fail && !success) {
+                        _revertActionFailed(op_offset);
+                    }
+                    gas_limit = 0;
+                } else if (op == Action.STATICCALL) {
+                    // Perform read-only call using current txData buffer
+                    // Any state change by the target (or a re-entry into this
+                    // contract) makes the call fail; value is left untouched
+                    (success, ) = target.staticcall{gas: gas_limit == 0 ? gasleft() : gas_limit}(txData);
+                    if (fail && !success) {
+                        _revertActionFailed(op_offset);
                     }
+                    gas_limit = 0;
                 } else if (op == Action.SETCALLBACK) {
                     (callbackAddress, offset) = _parseAddress(data, offset);
                 } else if (op == Action.SETFAIL) {
                     fail = true;
                 } else if (op == Action.CLEARFAIL) {
                     fail = false;
+                } else if (op == Action.COPYRETURN) {
+                    // Copies a slice of the most recent call or create
+                    // return data into txData, so outputs can feed later calldata.
+                    // 1. retOffset: position in the return data
+                    // 2. destOffset: position in txData
+                    // 3. size: number of bytes
+                    uint256 ret_offset;
+                    uint256 data_offset;
+                    uint256 size;
+                    (ret_offset, offset) = _parseUint16(data, offset);
+                    (data_offset, offset) = _parseUint16(data, offset);
+                    (size, offset) = _parseUint16(data, offset);
+                    uint256 ret_size;
+                    assembly {
+                        ret_size := returndatasize()
+                    }
+                    // returndatacopy past the end would consume all gas
+                    require(ret_offset + size <= ret_size, "RETURNDATA_OOB");
+                    _checkRange(txData, data_offset, size);
+                    assembly {
+                        returndatacopy(
+                            add(txData, add(data_offset, 0x20)), // destination in memory
+                            ret_offset,                          // start position in return data
+                            size                                 // number of bytes
+                        )
+                    }
+                } else if (op == Action.SELFBALANCE) {
+                    // Writes address(this).balance as a 32-byte word at destOffset
+                    uint256 data_offset;
+                    (data_offset, offset) = _parseUint16(data, offset);
+                    _checkRange(txData, data_offset, 32);
+                    uint256 balance = address(this).balance;
+                    assembly {
+                        mstore(add(add(txData, 0x20), data_offset), balance)
+                    }
+                } else if (op == Action.TOKENBALANCE) {
+                    // Writes token.balanceOf(holder) as a 32-byte word at destOffset
+                    // Note: this is a call, so it replaces the data seen by COPYRETURN
+                    address token;
+                    address holder;
+                    uint256 data_offset;
+                    (token, offset) = _parseAddress(data, offset);
+                    (holder, offset) = _parseAddress(data, offset);
+                    (data_offset, offset) = _parseUint16(data, offset);
+                    // Checked before the call, so a bad offset costs no balanceOf
+                    _checkRange(txData, data_offset, 32);
+                    uint256 balance = IERC20Balance(token).balanceOf(holder);
+                    assembly {
+                        mstore(add(add(txData, 0x20), data_offset), balance)
+                    }
+                } else if (op == Action.JUMP) {
+                    // Destinations are offsets into this action stream; jumping
+                    // to its length (or beyond) ends the flow
+                    (offset, ) = _parseUint16(data, offset);
+                } else if (op == Action.JUMPIFFAILED) {
+                    // Only meaningful after a call made with the fail flag cleared
+                    uint256 dest;
+                    (dest, offset) = _parseUint16(data, offset);
+                    if (!success) {
+                        offset = dest;
+                    }
+                } else if (op == Action.JUMPIFWORD) {
+                    // Parameters:
+                    // 1. destOffset: position of the word in txData
+                    // 2. cmp: 0 EQ, 1 NE, 2 LT, 3 GT, 4 LE, 5 GE (word cmp operand)
+                    // 3. operand: 32-byte constant
+                    // 4. dest: jump destination
+                    uint256 data_offset;
+                    uint256 cmp;
+                    uint256 operand;
+                    uint256 dest;
+                    (data_offset, offset) = _parseUint16(data, offset);
+                    cmp = uint8(data[offset]);
+                    offset += 1;
+                    (operand, offset) = _parseUint256(data, offset);
+                    (dest, offset) = _parseUint16(data, offset);
+                    _checkRange(txData, data_offset, 32);
+                    uint256 word;
+                    assembly {
+                        word := mload(add(add(txData, 0x20), data_offset))
+                    }
+                    bool taken;
+                    if (cmp == 0) {
+                        taken = word == operand;
+                    } else if (cmp == 1) {
+                        taken = word != operand;
+                    } else if (cmp == 2) {
+                        taken = word < operand;
+                    } else if (cmp == 3) {
+                        taken = word > operand;
+                    } else if (cmp == 4) {
+                        taken = word <= operand;
+                    } else if (cmp == 5) {
+                        taken = word >= operand;
+                    } else {
+                        revert("BAD_CMP");
+                    }
+                    if (taken) {
+                        offset = dest;
+                    }
+                } else if (op == Action.ARITH || op == Action.ARITHI) {
+                    // Parameters:
+                    // 1. arithOp: 0 ADD, 1 SUB, 2 MUL, 3 DIV
+                    // 2. destOffset: position of the result word in txData
+                    // 3. lhsOffset: position of the left operand word in txData
+                    // 4. ARITH: rhsOffset (uint16) of the right operand word
+                    //    ARITHI: the right operand as a 32-byte constant
+                    uint256 arith_op = uint8(data[offset]);
+                    offset += 1;
+                    uint256 data_offset;
+                    uint256 lhs_offset;
+                    uint256 lhs;
+                    uint256 rhs;
+                    (data_offset, offset) = _parseUint16(data, offset);
+                    (lhs_offset, offset) = _parseUint16(data, offset);
+                    if (op == Action.ARITH) {
+                        uint256 rhs_offset;
+                        (rhs_offset, offset) = _parseUint16(data, offset);
+                        _checkRange(txData, rhs_offset, 32);
+                        assembly {
+                            rhs := mload(add(add(txData, 0x20), rhs_offset))
+                        }
+                    } else {
+                        (rhs, offset) = _parseUint256(data, offset);
+                    }
+                    _checkRange(txData, lhs_offset, 32);
+                    assembly {
+                        lhs := mload(add(add(txData, 0x20), lhs_offset))
+                    }
+                    uint256 result = _arith(arith_op, lhs, rhs);
+                    _checkRange(txData, data_offset, 32);
+                    assembly {
+                        mstore(add(add(txData, 0x20), data_offset), result)
+                    }
+                } else if (op == Action.PAYCOINBASE) {
+                    // Parameters:
+                    // 1. mode: 0 pays amount wei, 1 pays amount basis points
+                    //    of this contract's ETH balance
+                    // 2. amount: 32-byte amount or share
+                    // The transfer ignores target, value and the fail flag, and
+                    // reverts the flow if it fails
+                    uint256 mode = uint8(data[offset]);
+                    offset += 1;
+                    uint256 amount;
+                    (amount, offset) = _parseUint256(data, offset);
+                    require(mode == 0 || (mode == 1 && amount <= 10000), "BAD_PAYMENT");
+                    if (mode == 1) {
+                        amount = address(this).balance * amount / 10000;
+                    }
+                    (bool paid, ) = block.coinbase.call{value: amount}("");
+                    if (!paid) {
+                        _revertActionFailed(op_offset);
+                    }
+                } else if (op == Action.SNAPSHOT) {
+                    // Records this contract's balance of token (address(0) for
+                    // ETH) for a later CHECKPROFIT in the same flow
+                    address token;
+                    (token, offset) = _parseAddress(data, offset);
+                    snapshots = bytes.concat(snapshots, abi.encode(token, _selfBalance(token)));
+                } else if (op == Action.CHECKPROFIT) {
+                    // Reverts with InsufficientProfit unless the balance of token
+                    // grew by at least minProfit since its latest SNAPSHOT
+                    address token;
+                    uint256 min_profit;
+                    (token, offset) = _parseAddress(data, offset);
+                    (min_profit, offset) = _parseUint256(data, offset);
+                    bool found;
+                    uint256 balance_before;
+                    for (uint256 i = snapshots.length; i != 0 && !found; i -= 64) {
+                        address snapshot_token;
+                        assembly {
+                            // Records are (token, balance) word pairs; the one
+                            // ending at byte i of snapshots starts at i - 64
+                            snapshot_token := mload(add(snapshots, sub(i, 0x20)))
+                            balance_before := mload(add(snapshots, i))
+                        }
+                        found = snapshot_token == token;
+                    }
+                    require(found, "NO_SNAPSHOT");
+                    uint256 balance_after = _selfBalance(token);
+                    if (balance_after < balance_before || balance_after - balance_before < min_profit) {
+                        revert InsufficientProfit(token, balance_before, balance_after, min_profit);
+                    }
+                } else if (op == Action.MAXBLOCK) {
+                    // Reverts once block.number is past the given block, so a
+                    // flow built for one block cannot land much later
+                    uint256 max_block;
+                    (max_block, offset) = _parseUint256(data, offset);
+                    require(block.number <= max_block, "BLOCK_EXPIRED");
+                } else if (op == Action.DEADLINE) {
+                    // Reverts once block.timestamp is past the deadline
+                    uint256 deadline;
+                    (deadline, offset) = _parseUint256(data, offset);
+                    require(block.timestamp <= deadline, "DEADLINE_EXPIRED");
+                } else if (op == Action.MAXBASEFEE) {
+                    // Reverts when block.basefee is above the given fee
+                    uint256 max_basefee;
+                    (max_basefee, offset) = _parseUint256(data, offset);
+                    require(block.basefee <= max_basefee, "BASEFEE_TOO_HIGH");
+                } else if (op == Action.LOG) {
+                    // Parameters:
+                    // 1. tag: 32-byte event topic
+                    // 2. data_offset: start of the slice in txData
+                    // 3. size: length of the slice
+                    uint256 tag;
+                    uint256 data_offset;
+                    uint256 size;
+                    (tag, offset) = _parseUint256(data, offset);
+                    (data_offset, offset) = _parseUint16(data, offset);
+                    (size, offset) = _parseUint16(data, offset);
+                    _checkRange(txData, data_offset, size);
+                    bytes memory slice = new bytes(size);
+                    for (uint256 i = 0; i < size; i += 32) {
+                        assembly {
+                            // The allocation is word-aligned, so the last word
+                            // fits; the encoder zeroes the bytes past size
+                            mstore(
+                                add(add(slice, 0x20), i),
+                                mload(add(add(txData, 0x20), add(data_offset, i)))
+                            )
+                        }
+                    }
+                    emit Checkpoint(bytes32(tag), slice);
+                } else if (op == Action.SWEEP) {
+                    // Sends this contract's whole balance of token (address(0)
+                    // for ETH) to recipient, read when the action runs. Like
+                    // PAYCOINBASE it ignores target, value and the fail flag,
+                    // and reverts the flow if the transfer fails
+                    address token;
+                    address recipient;
+                    (token, offset) = _parseAddress(data, offset);
+                    (recipient, offset) = _parseAddress(data, offset);
+                    uint256 amount = _selfBalance(token);
+                    if (amount != 0) {
+                        bool sent;
+                        if (token == address(0)) {
+                            (sent, ) = recipient.call{value: amount}("");
+                        } else {
+                            bytes memory returned;
+                            (sent, returned) = token.call(
+                                abi.encodeWithSelector(IERC20Balance.transfer.selector, recipient, amount)
+                            );
+                            // Tokens that return nothing, like USDT, succeed
+                            sent = sent && (returned.length == 0 || abi.decode(returned, (bool)));
+                        }
+                        if (!sent) {
+                            _revertActionFailed(op_offset);
+                        }
+                    }
+                } else if (op == Action.STOREREG || op == Action.LOADREG) {
+                    // Parameters:
+                    // 1. reg: register index, out of range panics
+                    // 2. data_offset: offset of the txData word
+                    // Registers outlive CLEARDATA, so a value can be kept
+                    // across calls; each callback flow starts with its own
+                    // zeroed registers
+                    uint256 reg = uint8(data[offset]);
+                    offset += 1;
+                    uint256 data_offset;
+                    (data_offset, offset) = _parseUint16(data, offset);
+                    _checkRange(txData, data_offset, 32);
+                    if (op == Action.STOREREG) {
+                        uint256 word;
+                        assembly {
+                            word := mload(add(add(txData, 0x20), data_offset))
+                        }
+                        registers[reg] = word;
+                    } else {
+                        uint256 word = registers[reg];
+                        assembly {
+                            mstore(add(add(txData, 0x20), data_offset), word)
+                        }
+                    }
                 }
 
             }
         }
     }
 
+    /**
+     * @notice Revert with ActionFailed, bubbling up the last call's revert data
+     * @dev Only copies the return data once a call has failed, so successful
+     *      calls pay nothing for it
+     * @param actionOffset Byte offset of the failed action
+     */
+    function _revertActionFailed(uint256 actionOffset) internal pure {
+        bytes memory reason;
+        assembly {
+            reason := mload(0x40)
+            mstore(reason, returndatasize())
+            returndatacopy(add(reason, 0x20), 0, returndatasize())
+            // Bump the free memory pointer past the copy, word-aligned
+            mstore(0x40, add(reason, and(add(returndatasize(), 0x3f), not(0x1f))))
+        }
+        revert ActionFailed(actionOffset, reason);
+    }
+
+    /**
+     * @notice This contract's balance of a token
+     * @param token The ERC-20 token, or address(0) for ETH
+     * @return uint256 The balance
+     */
+    function _selfBalance(address token) internal view returns (uint256) {
+        if (token == address(0)) {
+            return address(this).balance;
+        }
+        return IERC20Balance(token).balanceOf(address(this));
+    }
+
+    /**
+     * @notice Apply a word arithmetic operation
+     * @dev Lives outside the action loop's unchecked block, so overflow,
+     *      underflow and division by zero revert with a Solidity panic
+     * @param arithOp 0 ADD, 1 SUB, 2 MUL, 3 DIV
+     * @param lhs Left operand
+     * @param rhs Right operand
+     * @return uint256 The result
+     */
+    function _arith(uint256 arithOp, uint256 lhs, uint256 rhs) internal pure returns (uint256) {
+        if (arithOp == 0) {
+            return lhs + rhs;
+        } else if (arithOp == 1) {
+            return lhs - rhs;
+        } else if (arithOp == 2) {
+            return lhs * rhs;
+        } else if (arithOp == 3) {
+            return lhs / rhs;
+        }
+        revert("BAD_ARITH");
+    }
+
+    /**
+     * @notice Revert unless a slice lies within txData
+     * @dev Guards every action that reads or writes txData: mload and mstore
+     *      past the end would touch whatever memory follows the buffer
+     *      instead of failing
+     * @param txData The transaction data buffer
+     * @param offset Start of the slice
+     * @param size Length of the slice
+     */
+    function _checkRange(bytes memory txData, uint256 offset, uint256 size) internal pure {
+        require(offset + size <= txData.length, "TXDATA_OOB");
+    }
+
     /**
      * @notice Parse a function selector from byte array
      * @dev Memory layout for bytes array:
@@ -338,4 +795,32 @@ contract executor {
         uint256 value = uint256(uint8(data[offset])) << 8 | uint256(uint8(data[offset + 1]));
         return (value, offset + 2);
     }
+
+    /**
+     * @notice Parse a uint32 from byte array
+     * @dev Combines four bytes into a uint32
+     * @param data Source byte array
+     * @param offset Starting position in the array
+     * @return uint256 The parsed value
+     * @return uint256 The new offset after parsing
+     */
+    function _parseUint32(bytes memory data, uint256 offset) internal pure returns (uint256, uint256) {
+        uint256 value = uint256(uint8(data[offset])) << 24 | uint256(uint8(data[offset + 1])) << 16
+            | uint256(uint8(data[offset + 2])) << 8 | uint256(uint8(data[offset + 3]));
+        return (value, offset + 4);
+    }
+
+    /**
+     * @notice Parse a size or offset of CLEARDATA, SETDATA or EXTCODECOPY
+     * @dev The 32-bit variants of these opcodes lift the 64 KiB limit of
+     *      the uint16 operands, e.g. for large init code
+     * @param data Source byte array
+     * @param offset Starting position in the array
+     * @param wide Whether the operand is a uint32 rather than a uint16
+     * @return uint256 The parsed value
+     * @return uint256 The new offset after parsing
+     */
+    function _parseSize(bytes memory data, uint256 offset, bool wide) internal pure returns (uint256, uint256) {
+        return wide ? _parseUint32(data, offset) : _parseUint16(data, offset);
+    }
 }
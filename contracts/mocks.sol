@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.0;
+
+/**
+ * @title Test Mocks
+ * @notice Minimal stand-ins for WETH9, Morpho Blue and the Aave V3 pool
+ * @dev Only used by the test suite so flash loan flows can run against a plain
+ *      local chain. They implement just enough of each protocol for the
+ *      executor: the callback selectors and calldata layouts match mainnet.
+ */
+
+interface IMockERC20 {
+    function transfer(address to, uint256 value) external returns (bool);
+    function transferFrom(address from, address to, uint256 value) external returns (bool);
+}
+
+interface IMorphoFlashLoanCallback {
+    function onMorphoFlashLoan(uint256 assets, bytes calldata data) external;
+}
+
+interface IFlashLoanSimpleReceiver {
+    function executeOperation(
+        address asset,
+        uint256 amount,
+        uint256 premium,
+        address initiator,
+        bytes calldata params
+    ) external returns (bool);
+}
+
+/**
+ * @notice WETH9-compatible wrapped ether
+ */
+contract MockWETH {
+    mapping(address => uint256) public balanceOf;
+    mapping(address => mapping(address => uint256)) public allowance;
+
+    receive() external payable {
+        deposit();
+    }
+
+    function deposit() public payable {
+        balanceOf[msg.sender] += msg.value;
+    }
+
+    function withdraw(uint256 wad) external {
+        require(balanceOf[msg.sender] >= wad, "WETH_BALANCE");
+        balanceOf[msg.sender] -= wad;
+        (bool success,) = msg.sender.call{value: wad}("");
+        require(success, "WETH_WITHDRAW_FAILED");
+    }
+
+    function totalSupply() external view returns (uint256) {
+        return address(this).balance;
+    }
+
+    function approve(address spender, uint256 wad) external returns (bool) {
+        allowance[msg.sender][spender] = wad;
+        return true;
+    }
+
+    function transfer(address dst, uint256 wad) external returns (bool) {
+        return transferFrom(msg.sender, dst, wad);
+    }
+
+    function transferFrom(address src, address dst, uint256 wad) public returns (bool) {
+        require(balanceOf[src] >= wad, "WETH_BALANCE");
+        if (src != msg.sender && allowance[src][msg.sender] != type(uint256).max) {
+            require(allowance[src][msg.sender] >= wad, "WETH_ALLOWANCE");
+            allowance[src][msg.sender] -= wad;
+        }
+        balanceOf[src] -= wad;
+        balanceOf[dst] += wad;
+        return true;
+    }
+}
+
+/**
+ * @notice Fee-free flash lender with Morpho Blue's `flashLoan` interface
+ */
+contract MockMorpho {
+    function flashLoan(address token, uint256 assets, bytes calldata data) external {
+        require(assets != 0, "ZERO_ASSETS");
+        IMockERC20(token).transfer(msg.sender, assets);
+        IMorphoFlashLoanCallback(msg.sender).onMorphoFlashLoan(assets, data);
+        require(IMockERC20(token).transferFrom(msg.sender, address(this), assets), "REPAY_FAILED");
+    }
+}
+
+/**
+ * @notice Flash lender with the Aave V3 pool's `flashLoanSimple` interface
+ * @dev Charges the mainnet premium of 0.05%
+ */
+contract MockAavePool {
+    uint256 public constant FLASHLOAN_PREMIUM_TOTAL = 5; // in basis points
+
+    function flashLoanSimple(
+        address receiverAddress,
+        address asset,
+        uint256 amount,
+        bytes calldata params,
+        uint16 /* referralCode */
+    ) external {
+        uint256 premium = amount * FLASHLOAN_PREMIUM_TOTAL / 10000;
+        IMockERC20(asset).transfer(receiverAddress, amount);
+        require(
+            IFlashLoanSimpleReceiver(receiverAddress).executeOperation(asset, amount, premium, msg.sender, params),
+            "INVALID_FLASHLOAN_EXECUTOR_RETURN"
+        );
+        require(IMockERC20(asset).transferFrom(receiverAddress, address(this), amount + premium), "REPAY_FAILED");
+    }
+}
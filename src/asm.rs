@@ -0,0 +1,748 @@
+//! Human-readable assembly syntax for executor flows.
+//!
+//! A flow is written as one statement per line (or `;`-separated), using the
+//! opcode names from the README table:
+//!
+//! ```text
+//! SETFAIL
+//! SETADDR 0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2
+//! SETVALUE 1e18
+//! CLEARDATA 68
+//! SETDATA 0 0xa9059cbb...
+//! CALL   # comments run to the end of the line
+//! ```
+//!
+//! Sizes and offsets accept decimal or `0x` hex. Those of `CLEARDATA`,
+//! `SETDATA` and `EXTCODECOPY` go up to `u32::MAX`, assembling to the 32-bit
+//! opcode variants past `u16::MAX`; `CLEARDATA32`, `SETDATA32` and
+//! `EXTCODECOPY32` always assemble to them. `SETVALUE` and `SETGAS`
+//! additionally accept scientific notation such as `1e18` or `2.5e17`.
+//! `CREATE` and `CREATE2 <salt>` take the optional expected address used by
+//! the optimizer.
+//!
+//! A `SETDATA` payload is a sequence of hex literals and `{ ... }` blocks. A
+//! block is assembled with [`build_raw`](FlowBuilder::build_raw) and spliced
+//! in place, which is how nested callback flows (e.g. the `data` argument of
+//! a flash loan) are written. [`print`] emits such blocks whenever a payload
+//! contains an ABI `bytes` argument that decodes to a flow, so printing and
+//! re-assembling always yields identical bytecode.
+//!
+//! A line of the form `name:` defines a label. Jumps take a label name or a
+//! raw byte offset into the action stream; `JUMPIFWORD` also takes the buffer
+//! offset of the word, a comparison (`EQ`, `NE`, `LT`, `GT`, `LE`, `GE`) and a
+//! value:
+//!
+//! ```text
+//! CLEARFAIL
+//! CALL
+//! JUMPIFFAILED fallback
+//! JUMPIFWORD 0 LT 1e18 fallback
+//! JUMP done
+//! fallback:
+//! ...
+//! done:
+//! ```
+//!
+//! Raw offsets that land on an action are printed as synthesized `L<offset>`
+//! labels.
+//!
+//! `ARITH <op> <dst> <lhs> <rhs>` and `ARITHI <op> <dst> <lhs> <value>` take
+//! the operator as `ADD`, `SUB`, `MUL` or `DIV`. `SNAPSHOT <token>` and
+//! `CHECKPROFIT <token> <min profit>` take the zero address for ETH.
+//! `PAYCOINBASE` takes a wei amount (`PAYCOINBASE 1e16`) or a share of the
+//! ETH balance in basis points (`PAYCOINBASE 500 BPS`). `MAXBLOCK <number>`,
+//! `DEADLINE <timestamp>` and `MAXBASEFEE <wei>` take the inclusive bound.
+//! `LOG <tag> <offset> <size>` takes the tag as 32 bytes of hex, and
+//! `SWEEP <token> <recipient>` the zero address as the token for ETH.
+//! `STOREREG <register> <offset>` and `LOADREG <register> <offset>` name the
+//! register as `R0` to `R15`. `SETVALUEBAL <reserve>` takes the wei to leave
+//! behind, in the notation of `SETVALUE`, and `SETVALUEDATA <offset>` the
+//! buffer offset of the word.
+
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+use alloy_primitives::{hex, Address, B256, U256};
+
+use crate::decoder::decode_raw;
+use crate::flow_builder::{
+    encode_actions, Action, ArithOp, Cmp, CoinbasePayment, FlowBuilder, JumpDest,
+};
+
+/// Error returned when assembly text cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// 1-based line number where the error was found.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Parses assembly text into a `FlowBuilder`.
+pub fn parse(src: &str) -> Result<FlowBuilder, AsmError> {
+    let mut parser = Parser {
+        tokens: tokenize(src),
+        pos: 0,
+    };
+    Ok(FlowBuilder::from(parser.block(false)?))
+}
+
+/// Renders a `FlowBuilder` as assembly text, one statement per line.
+pub fn print(fb: &FlowBuilder) -> String {
+    let mut out = String::new();
+    print_actions(&mut out, fb.actions(), 0);
+    out
+}
+
+impl fmt::Display for FlowBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&print(self))
+    }
+}
+
+impl FromStr for FlowBuilder {
+    type Err = AsmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Printer
+// ---------------------------------------------------------------------------
+
+fn print_actions(out: &mut String, actions: &[Action], depth: usize) {
+    // Byte offset of each action, plus the end of the stream.
+    let mut boundaries = vec![0usize];
+    for action in actions {
+        let len = action.encode_with(&mut |_| 0).len();
+        boundaries.push(boundaries.last().unwrap() + len);
+    }
+    let synthesized: BTreeSet<u16> = actions
+        .iter()
+        .filter_map(|a| match a.jump_dest() {
+            Some(JumpDest::Offset(offset)) => Some(*offset),
+            _ => None,
+        })
+        .filter(|offset| boundaries.contains(&(*offset as usize)))
+        .collect();
+    let dest = |dest: &JumpDest| match dest {
+        JumpDest::Label(name) => name.clone(),
+        JumpDest::Offset(offset) if synthesized.contains(offset) => format!("L{offset}"),
+        JumpDest::Offset(offset) => offset.to_string(),
+    };
+    let mut printed = BTreeSet::new();
+    let mut print_label = |out: &mut String, at: usize| {
+        if let Ok(at) = u16::try_from(at) {
+            if synthesized.contains(&at) && printed.insert(at) {
+                out.push_str(&format!("{}L{at}:\n", "    ".repeat(depth)));
+            }
+        }
+    };
+
+    for (action, offset) in actions.iter().zip(&boundaries) {
+        print_label(out, *offset);
+        out.push_str(&"    ".repeat(depth));
+        match action {
+            Action::ClearData { size } => out.push_str(&format!("CLEARDATA {size}")),
+            Action::ClearData32 { size } => out.push_str(&format!("CLEARDATA32 {size}")),
+            Action::SetData { offset, data } => {
+                out.push_str(&format!("SETDATA {offset}"));
+                print_payload(out, data, depth);
+            }
+            Action::SetData32 { offset, data } => {
+                out.push_str(&format!("SETDATA32 {offset}"));
+                print_payload(out, data, depth);
+            }
+            Action::SetAddr { addr } => out.push_str(&format!("SETADDR {addr}")),
+            Action::SetValue { value } => {
+                out.push_str(&format!("SETVALUE {}", format_value(*value)))
+            }
+            Action::SetValueFromBalance { reserve } => {
+                out.push_str(&format!("SETVALUEBAL {}", format_value(*reserve)))
+            }
+            Action::SetValueFromData { data_offset } => {
+                out.push_str(&format!("SETVALUEDATA {data_offset}"))
+            }
+            Action::SetGas { gas } => out.push_str(&format!("SETGAS {gas}")),
+            Action::ExtCodeCopy {
+                source,
+                data_offset,
+                code_offset,
+                size,
+            } => out.push_str(&format!(
+                "EXTCODECOPY {source} {data_offset} {code_offset} {size}"
+            )),
+            Action::ExtCodeCopy32 {
+                source,
+                data_offset,
+                code_offset,
+                size,
+            } => out.push_str(&format!(
+                "EXTCODECOPY32 {source} {data_offset} {code_offset} {size}"
+            )),
+            Action::Call => out.push_str("CALL"),
+            Action::Create {
+                created_address: Some(addr),
+            } => out.push_str(&format!("CREATE {addr}")),
+            Action::Create {
+                created_address: None,
+            } => out.push_str("CREATE"),
+            Action::Create2 {
+                salt,
+                created_address: Some(addr),
+            } => out.push_str(&format!("CREATE2 {salt} {addr}")),
+            Action::Create2 {
+                salt,
+                created_address: None,
+            } => out.push_str(&format!("CREATE2 {salt}")),
+            Action::DelegateCall => out.push_str("DELEGATECALL"),
+            Action::StaticCall => out.push_str("STATICCALL"),
+            Action::SetCallback { callback_address } => {
+                out.push_str(&format!("SETCALLBACK {callback_address}"))
+            }
+            Action::SetFail => out.push_str("SETFAIL"),
+            Action::ClearFail => out.push_str("CLEARFAIL"),
+            Action::CopyReturn {
+                ret_offset,
+                data_offset,
+                size,
+            } => out.push_str(&format!("COPYRETURN {ret_offset} {data_offset} {size}")),
+            Action::SelfBalance { data_offset } => {
+                out.push_str(&format!("SELFBALANCE {data_offset}"))
+            }
+            Action::TokenBalance {
+                token,
+                holder,
+                data_offset,
+            } => out.push_str(&format!("TOKENBALANCE {token} {holder} {data_offset}")),
+            Action::Label { name } => out.push_str(&format!("{name}:")),
+            Action::Jump { dest: d } => out.push_str(&format!("JUMP {}", dest(d))),
+            Action::JumpIfFailed { dest: d } => out.push_str(&format!("JUMPIFFAILED {}", dest(d))),
+            Action::JumpIfWord {
+                data_offset,
+                cmp,
+                value,
+                dest: d,
+            } => out.push_str(&format!(
+                "JUMPIFWORD {data_offset} {} {} {}",
+                cmp.mnemonic(),
+                format_value(*value),
+                dest(d)
+            )),
+            Action::Arith { op, dst, lhs, rhs } => {
+                out.push_str(&format!("ARITH {} {dst} {lhs} {rhs}", op.mnemonic()))
+            }
+            Action::ArithImm {
+                op,
+                dst,
+                lhs,
+                value,
+            } => out.push_str(&format!(
+                "ARITHI {} {dst} {lhs} {}",
+                op.mnemonic(),
+                format_value(*value)
+            )),
+            Action::PayCoinbase {
+                payment: CoinbasePayment::Amount(amount),
+            } => out.push_str(&format!("PAYCOINBASE {}", format_value(*amount))),
+            Action::PayCoinbase {
+                payment: CoinbasePayment::BalanceBps(bps),
+            } => out.push_str(&format!("PAYCOINBASE {bps} BPS")),
+            Action::Snapshot { token } => out.push_str(&format!("SNAPSHOT {token}")),
+            Action::CheckProfit { token, min_profit } => out.push_str(&format!(
+                "CHECKPROFIT {token} {}",
+                format_value(*min_profit)
+            )),
+            Action::MaxBlock { number } => out.push_str(&format!("MAXBLOCK {number}")),
+            Action::Deadline { timestamp } => out.push_str(&format!("DEADLINE {timestamp}")),
+            Action::Log {
+                tag,
+                data_offset,
+                size,
+            } => out.push_str(&format!("LOG {tag} {data_offset} {size}")),
+            Action::StoreReg {
+                register,
+                data_offset,
+            } => out.push_str(&format!("STOREREG R{register} {data_offset}")),
+            Action::LoadReg {
+                register,
+                data_offset,
+            } => out.push_str(&format!("LOADREG R{register} {data_offset}")),
+            Action::Sweep { token, recipient } => {
+                out.push_str(&format!("SWEEP {token} {recipient}"))
+            }
+            Action::MaxBaseFee { fee } => {
+                out.push_str(&format!("MAXBASEFEE {}", format_value(*fee)))
+            }
+        }
+        out.push('\n');
+    }
+    print_label(out, *boundaries.last().unwrap());
+}
+
+/// Prints a `SETDATA` payload, expanding embedded flows into `{ ... }` blocks.
+fn print_payload(out: &mut String, data: &[u8], depth: usize) {
+    let mut printed = 0;
+    for (start, len, actions) in find_nested_flows(data) {
+        out.push_str(&format!(" 0x{} {{\n", hex::encode(&data[printed..start])));
+        print_actions(out, &actions, depth + 1);
+        out.push_str(&"    ".repeat(depth));
+        out.push('}');
+        printed = start + len;
+    }
+    if printed == 0 || printed < data.len() {
+        out.push_str(&format!(" 0x{}", hex::encode(&data[printed..])));
+    }
+}
+
+/// Looks for ABI-encoded `bytes` values inside calldata whose contents decode
+/// to a non-empty flow that re-encodes to exactly the same bytes.
+///
+/// Returns the start and length of each flow and its decoded actions.
+fn find_nested_flows(data: &[u8]) -> Vec<(usize, usize, Vec<Action>)> {
+    let mut flows = Vec::new();
+    // Calldata words start after the 4-byte selector.
+    let mut word = 4;
+    while word + 32 <= data.len() {
+        let start = word + 32;
+        let len = usize::try_from(U256::from_be_slice(&data[word..start]))
+            .ok()
+            .filter(|len| *len > 0 && *len <= data.len() - start);
+        if let Some(len) = len {
+            let bytes = &data[start..start + len];
+            if let Ok(actions) = decode_raw(bytes) {
+                if actions
+                    .iter()
+                    .flat_map(|a| a.encode())
+                    .eq(bytes.iter().copied())
+                {
+                    flows.push((start, len, actions));
+                    word = start + len.div_ceil(32) * 32;
+                    continue;
+                }
+            }
+        }
+        word += 32;
+    }
+    flows
+}
+
+/// Formats a value in scientific notation when it has many trailing zeros
+/// (e.g. `1e18`, `2.5e17`), and in plain decimal otherwise.
+fn format_value(value: U256) -> String {
+    let digits = value.to_string();
+    let zeros = digits.len() - digits.trim_end_matches('0').len();
+    if value.is_zero() || zeros < 9 {
+        return digits;
+    }
+    let significant = digits.trim_end_matches('0');
+    let exp = digits.len() - 1;
+    if significant.len() == 1 {
+        format!("{significant}e{exp}")
+    } else {
+        format!("{}.{}e{exp}", &significant[..1], &significant[1..])
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tokenizer
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    /// Statement separator: newline or `;`.
+    Sep,
+    Open,
+    Close,
+}
+
+fn tokenize(src: &str) -> Vec<(Token, usize)> {
+    let mut tokens = Vec::new();
+    for (idx, line) in src.lines().enumerate() {
+        let line_no = idx + 1;
+        let code = line.split('#').next().unwrap_or_default();
+        let mut word = String::new();
+        for c in code.chars() {
+            let token = match c {
+                ';' => Some(Token::Sep),
+                '{' => Some(Token::Open),
+                '}' => Some(Token::Close),
+                c if c.is_whitespace() => None,
+                c => {
+                    word.push(c);
+                    continue;
+                }
+            };
+            if !word.is_empty() {
+                tokens.push((Token::Word(std::mem::take(&mut word)), line_no));
+            }
+            if let Some(token) = token {
+                tokens.push((token, line_no));
+            }
+        }
+        if !word.is_empty() {
+            tokens.push((Token::Word(word), line_no));
+        }
+        tokens.push((Token::Sep, line_no));
+    }
+    tokens
+}
+
+// ---------------------------------------------------------------------------
+// Parser
+// ---------------------------------------------------------------------------
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            line: self.line(),
+            message: message.into(),
+        }
+    }
+
+    fn word(&mut self, what: &str) -> Result<String, AsmError> {
+        match self.peek() {
+            Some(Token::Word(w)) => {
+                let w = w.clone();
+                self.pos += 1;
+                Ok(w)
+            }
+            _ => Err(self.error(format!("expected {what}"))),
+        }
+    }
+
+    /// Parses statements until end of input, or until the closing `}` when
+    /// `nested` is set.
+    fn block(&mut self, nested: bool) -> Result<Vec<Action>, AsmError> {
+        let mut actions = Vec::new();
+        loop {
+            match self.peek() {
+                None if nested => return Err(self.error("unclosed `{`")),
+                None => return Ok(actions),
+                Some(Token::Sep) => self.pos += 1,
+                Some(Token::Close) if nested => {
+                    self.pos += 1;
+                    return Ok(actions);
+                }
+                Some(Token::Close) => return Err(self.error("unexpected `}`")),
+                Some(Token::Open) => return Err(self.error("unexpected `{`")),
+                Some(Token::Word(_)) => {
+                    actions.push(self.statement()?);
+                    match self.peek() {
+                        None | Some(Token::Sep) | Some(Token::Close) => {}
+                        _ => return Err(self.error("expected end of statement")),
+                    }
+                }
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<Action, AsmError> {
+        let mnemonic = self.word("mnemonic")?;
+        if let Some(name) = mnemonic.strip_suffix(':') {
+            if !is_label(name) {
+                return Err(self.error(format!("invalid label `{name}`")));
+            }
+            return Ok(Action::Label {
+                name: name.to_owned(),
+            });
+        }
+        let action = match mnemonic.to_ascii_uppercase().as_str() {
+            "CLEARDATA" => Action::clear_data(self.u32("size")?),
+            "CLEARDATA32" => Action::ClearData32 {
+                size: self.u32("size")?,
+            },
+            "SETDATA" => Action::set_data(self.u32("offset")?, self.payload()?),
+            "SETDATA32" => Action::SetData32 {
+                offset: self.u32("offset")?,
+                data: self.payload()?,
+            },
+            "SETADDR" => Action::SetAddr {
+                addr: self.address()?,
+            },
+            "SETVALUE" => Action::SetValue {
+                value: self.value()?,
+            },
+            "SETVALUEBAL" => Action::SetValueFromBalance {
+                reserve: self.value()?,
+            },
+            "SETVALUEDATA" => Action::SetValueFromData {
+                data_offset: self.u16("data offset")?,
+            },
+            "SETGAS" => Action::SetGas { gas: self.gas()? },
+            "EXTCODECOPY" => Action::extcodecopy(
+                self.address()?,
+                self.u32("data offset")?,
+                self.u32("code offset")?,
+                self.u32("size")?,
+            ),
+            "EXTCODECOPY32" => Action::ExtCodeCopy32 {
+                source: self.address()?,
+                data_offset: self.u32("data offset")?,
+                code_offset: self.u32("code offset")?,
+                size: self.u32("size")?,
+            },
+            "CALL" => Action::Call,
+            "CREATE" => Action::Create {
+                created_address: match self.peek() {
+                    Some(Token::Word(_)) => Some(self.address()?),
+                    _ => None,
+                },
+            },
+            "CREATE2" => Action::Create2 {
+                salt: self.b256("salt")?,
+                created_address: match self.peek() {
+                    Some(Token::Word(_)) => Some(self.address()?),
+                    _ => None,
+                },
+            },
+            "DELEGATECALL" => Action::DelegateCall,
+            "STATICCALL" => Action::StaticCall,
+            "SETCALLBACK" => Action::SetCallback {
+                callback_address: self.address()?,
+            },
+            "SETFAIL" => Action::SetFail,
+            "CLEARFAIL" => Action::ClearFail,
+            "COPYRETURN" => Action::CopyReturn {
+                ret_offset: self.u16("return data offset")?,
+                data_offset: self.u16("data offset")?,
+                size: self.u16("size")?,
+            },
+            "SELFBALANCE" => Action::SelfBalance {
+                data_offset: self.u16("data offset")?,
+            },
+            "TOKENBALANCE" => Action::TokenBalance {
+                token: self.address()?,
+                holder: self.address()?,
+                data_offset: self.u16("data offset")?,
+            },
+            "JUMP" => Action::Jump { dest: self.dest()? },
+            "JUMPIFFAILED" => Action::JumpIfFailed { dest: self.dest()? },
+            "JUMPIFWORD" => Action::JumpIfWord {
+                data_offset: self.u16("data offset")?,
+                cmp: self.cmp()?,
+                value: self.value()?,
+                dest: self.dest()?,
+            },
+            "ARITH" => Action::Arith {
+                op: self.arith_op()?,
+                dst: self.u16("destination offset")?,
+                lhs: self.u16("operand offset")?,
+                rhs: self.u16("operand offset")?,
+            },
+            "ARITHI" => Action::ArithImm {
+                op: self.arith_op()?,
+                dst: self.u16("destination offset")?,
+                lhs: self.u16("operand offset")?,
+                value: self.value()?,
+            },
+            "PAYCOINBASE" => Action::PayCoinbase {
+                payment: self.payment()?,
+            },
+            "SNAPSHOT" => Action::Snapshot {
+                token: self.address()?,
+            },
+            "CHECKPROFIT" => Action::CheckProfit {
+                token: self.address()?,
+                min_profit: self.value()?,
+            },
+            "MAXBLOCK" => Action::MaxBlock {
+                number: self.value()?,
+            },
+            "DEADLINE" => Action::Deadline {
+                timestamp: self.value()?,
+            },
+            "MAXBASEFEE" => Action::MaxBaseFee { fee: self.value()? },
+            "STOREREG" => Action::StoreReg {
+                register: self.register()?,
+                data_offset: self.u16("data offset")?,
+            },
+            "LOADREG" => Action::LoadReg {
+                register: self.register()?,
+                data_offset: self.u16("data offset")?,
+            },
+            "SWEEP" => Action::Sweep {
+                token: self.address()?,
+                recipient: self.address()?,
+            },
+            "LOG" => Action::Log {
+                tag: self.b256("tag")?,
+                data_offset: self.u16("data offset")?,
+                size: self.u16("size")?,
+            },
+            _ => return Err(self.error(format!("unknown mnemonic `{mnemonic}`"))),
+        };
+        Ok(action)
+    }
+
+    /// Parses a `SETDATA` payload: hex literals and nested `{ ... }` flows.
+    fn payload(&mut self) -> Result<Vec<u8>, AsmError> {
+        let mut data = Vec::new();
+        let mut segments = 0;
+        loop {
+            match self.peek() {
+                Some(Token::Word(w)) => {
+                    let bytes = w
+                        .strip_prefix("0x")
+                        .and_then(|h| hex::decode(h).ok())
+                        .ok_or_else(|| self.error(format!("invalid hex data `{w}`")))?;
+                    data.extend(bytes);
+                    self.pos += 1;
+                }
+                Some(Token::Open) => {
+                    self.pos += 1;
+                    let actions = self.block(true)?;
+                    let line = self.line();
+                    data.extend(encode_actions(&actions).map_err(|e| AsmError {
+                        line,
+                        message: e.to_string(),
+                    })?);
+                }
+                _ if segments == 0 => return Err(self.error("expected data")),
+                _ => return Ok(data),
+            }
+            segments += 1;
+        }
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16, AsmError> {
+        let w = self.word(what)?;
+        let parsed = match w.strip_prefix("0x") {
+            Some(h) => u16::from_str_radix(h, 16),
+            None => w.parse(),
+        };
+        parsed.map_err(|_| self.error(format!("invalid {what} `{w}`")))
+    }
+
+    /// Parses a size or offset of `CLEARDATA`, `SETDATA` or `EXTCODECOPY`,
+    /// which take 32 bits.
+    fn u32(&mut self, what: &str) -> Result<u32, AsmError> {
+        let w = self.word(what)?;
+        let parsed = match w.strip_prefix("0x") {
+            Some(h) => u32::from_str_radix(h, 16),
+            None => w.parse(),
+        };
+        parsed.map_err(|_| self.error(format!("invalid {what} `{w}`")))
+    }
+
+    /// Parses a jump destination: a label name or a raw byte offset.
+    fn dest(&mut self) -> Result<JumpDest, AsmError> {
+        let w = self.word("jump destination")?;
+        if is_label(&w) {
+            return Ok(JumpDest::Label(w));
+        }
+        let parsed = match w.strip_prefix("0x") {
+            Some(h) => u16::from_str_radix(h, 16),
+            None => w.parse(),
+        };
+        parsed
+            .map(JumpDest::Offset)
+            .map_err(|_| self.error(format!("invalid jump destination `{w}`")))
+    }
+
+    fn cmp(&mut self) -> Result<Cmp, AsmError> {
+        let w = self.word("comparison")?;
+        Cmp::ALL
+            .into_iter()
+            .find(|cmp| w.eq_ignore_ascii_case(cmp.mnemonic()))
+            .ok_or_else(|| self.error(format!("invalid comparison `{w}`")))
+    }
+
+    fn arith_op(&mut self) -> Result<ArithOp, AsmError> {
+        let w = self.word("arithmetic operator")?;
+        ArithOp::ALL
+            .into_iter()
+            .find(|op| w.eq_ignore_ascii_case(op.mnemonic()))
+            .ok_or_else(|| self.error(format!("invalid arithmetic operator `{w}`")))
+    }
+
+    fn address(&mut self) -> Result<Address, AsmError> {
+        let w = self.word("address")?;
+        Address::from_str(&w).map_err(|_| self.error(format!("invalid address `{w}`")))
+    }
+
+    fn b256(&mut self, what: &str) -> Result<B256, AsmError> {
+        let w = self.word(what)?;
+        B256::from_str(&w).map_err(|_| self.error(format!("invalid {what} `{w}`")))
+    }
+
+    fn value(&mut self) -> Result<U256, AsmError> {
+        let w = self.word("value")?;
+        parse_value(&w).ok_or_else(|| self.error(format!("invalid value `{w}`")))
+    }
+
+    /// Parses a `PAYCOINBASE` payment: a wei amount, or a share of the
+    /// balance followed by `BPS`.
+    fn payment(&mut self) -> Result<CoinbasePayment, AsmError> {
+        let amount = self.value()?;
+        if !matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case("BPS")) {
+            return Ok(CoinbasePayment::Amount(amount));
+        }
+        self.pos += 1;
+        CoinbasePayment::from_parts(1, amount)
+            .ok_or_else(|| self.error(format!("invalid share `{amount}` bps")))
+    }
+
+    /// Parses a register name, `R0` to `R255`.
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let w = self.word("register")?;
+        w.strip_prefix('R')
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| self.error(format!("invalid register `{w}`")))
+    }
+
+    /// Parses a `uint32` gas amount, in any notation [`value`](Self::value)
+    /// accepts.
+    fn gas(&mut self) -> Result<u32, AsmError> {
+        let w = self.word("gas")?;
+        parse_value(&w)
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| self.error(format!("invalid gas `{w}`")))
+    }
+}
+
+/// Whether `name` is a valid label: an identifier not starting with a digit.
+fn is_label(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parses a decimal, `0x` hex or scientific (`1e18`, `2.5e17`) integer.
+fn parse_value(w: &str) -> Option<U256> {
+    if let Some(h) = w.strip_prefix("0x") {
+        return U256::from_str_radix(h, 16).ok();
+    }
+    let Some((mantissa, exp)) = w.split_once(['e', 'E']) else {
+        return U256::from_str_radix(w, 10).ok();
+    };
+    let exp: usize = exp.parse().ok()?;
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let exp = exp.checked_sub(frac.len())?;
+    let digits = U256::from_str_radix(&format!("{int}{frac}"), 10).ok()?;
+    digits.checked_mul(U256::from(10).checked_pow(U256::from(exp))?)
+}
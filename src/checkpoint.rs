@@ -0,0 +1,72 @@
+//! Decoding of the executor's `Checkpoint` events.
+//!
+//! `LOG` emits `Checkpoint(bytes32 indexed tag, bytes data)` with a slice of
+//! the flow's data buffer, so progress markers and the amounts seen along
+//! the way can be read back from a receipt alone. [`decode_checkpoints`]
+//! picks the executor's events out of a receipt's logs, in emission order.
+//! Events emitted by a flow that later reverted are not part of any receipt.
+
+use alloy_primitives::{keccak256, Address, Log, B256, U256};
+
+use crate::revert::dynamic_bytes;
+
+/// A `Checkpoint` event emitted by `LOG`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub tag: B256,
+    /// The logged slice of the data buffer.
+    pub data: Vec<u8>,
+}
+
+impl Checkpoint {
+    /// The `topic0` of `Checkpoint` events.
+    pub fn topic() -> B256 {
+        keccak256("Checkpoint(bytes32,bytes)")
+    }
+
+    /// Decodes a single log, or returns `None` if it is not a well-formed
+    /// `Checkpoint` event. The emitting address is not checked.
+    pub fn from_log(log: &Log) -> Option<Checkpoint> {
+        let [topic, tag] = log.topics() else {
+            return None;
+        };
+        if *topic != Self::topic() {
+            return None;
+        }
+        let body = &log.data.data;
+        let data = dynamic_bytes(body, U256::from_be_slice(body.get(..32)?))?;
+        Some(Checkpoint {
+            tag: *tag,
+            data: data.to_vec(),
+        })
+    }
+
+    /// The tag as text, if it is UTF-8 right-padded with zero bytes, as
+    /// produced by `B256::right_padding_from(b"leg 1")`.
+    pub fn tag_str(&self) -> Option<&str> {
+        let len = self.tag.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+        std::str::from_utf8(&self.tag[..len]).ok()
+    }
+
+    /// The `index`-th 32-byte word of the data, e.g. a logged balance.
+    pub fn word(&self, index: usize) -> Option<U256> {
+        let start = index.checked_mul(32)?;
+        self.data
+            .get(start..start.checked_add(32)?)
+            .map(U256::from_be_slice)
+    }
+}
+
+/// Decodes the `Checkpoint` events emitted by `executor` from a receipt's
+/// logs, skipping every other log.
+///
+/// With `alloy`, pass `receipt.inner.logs().iter().map(|log| &log.inner)`.
+pub fn decode_checkpoints<'a>(
+    executor: Address,
+    logs: impl IntoIterator<Item = &'a Log>,
+) -> Vec<Checkpoint> {
+    logs.into_iter()
+        .filter(|log| log.address == executor)
+        .filter_map(Checkpoint::from_log)
+        .collect()
+}
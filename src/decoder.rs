@@ -0,0 +1,309 @@
+//! Decoder for executor bytecode.
+//!
+//! The inverse of [`FlowBuilder::build`](crate::FlowBuilder::build): parses an
+//! action stream back into a list of [`Action`]s, mirroring how the on-chain
+//! `_executeActions` loop reads its operands.
+
+use std::fmt;
+
+use alloy_primitives::{Address, B256, U256};
+
+use crate::flow_builder::{
+    Action, ArithOp, Cmp, CoinbasePayment, JumpDest, EXECUTE_ACTIONS_SELECTOR,
+};
+use crate::opcodes::*;
+
+/// Error returned when executor bytecode cannot be decoded.
+///
+/// Offsets are relative to the start of the action stream, i.e. after the
+/// `executeActions()` selector if one was present.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The byte at `offset` is not a known opcode.
+    UnknownOpcode { offset: usize, opcode: u8 },
+    /// An operand of `opcode` starting at `offset` runs past the end of the
+    /// input.
+    Truncated { offset: usize, opcode: u8 },
+    /// An operand of `opcode` starting at `offset` holds a value the
+    /// executor rejects, e.g. an unknown `JUMPIFWORD` comparison, `ARITH`
+    /// operator or `PAYCOINBASE` mode.
+    InvalidOperand { offset: usize, opcode: u8 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode { offset, opcode } => {
+                write!(f, "unknown opcode 0x{opcode:02x} at offset {offset}")
+            }
+            DecodeError::Truncated { offset, opcode } => {
+                write!(
+                    f,
+                    "truncated operand for opcode 0x{opcode:02x} at offset {offset}"
+                )
+            }
+            DecodeError::InvalidOperand { offset, opcode } => {
+                write!(
+                    f,
+                    "invalid operand for opcode 0x{opcode:02x} at offset {offset}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decodes `build()` or `build_raw()` output into a list of actions.
+///
+/// A leading `executeActions()` selector is stripped if present. No opcode
+/// shares the selector's first byte, so raw streams are never misread.
+pub fn decode(bytecode: &[u8]) -> Result<Vec<Action>, DecodeError> {
+    decode_raw(
+        bytecode
+            .strip_prefix(&EXECUTE_ACTIONS_SELECTOR[..])
+            .unwrap_or(bytecode),
+    )
+}
+
+/// Decodes a raw action stream (no function selector) into a list of actions.
+///
+/// Decoding stops at the first `OP_EOF` or at the end of the input, exactly
+/// like the executor; any bytes after `OP_EOF` are ignored. Jump
+/// destinations decode to [`JumpDest::Offset`]s. The 32-bit variants of
+/// `CLEARDATA`, `SETDATA` and `EXTCODECOPY` always decode to the `*32`
+/// actions, even when their operands would fit 16 bits, so every action
+/// re-encodes to the bytes it came from and raw jump offsets stay valid.
+pub fn decode_raw(bytecode: &[u8]) -> Result<Vec<Action>, DecodeError> {
+    let mut reader = Reader {
+        bytes: bytecode,
+        pos: 0,
+        opcode: OP_EOF,
+    };
+    let mut actions = Vec::new();
+
+    while reader.pos < bytecode.len() {
+        let offset = reader.pos;
+        let opcode = bytecode[offset];
+        reader.pos += 1;
+        reader.opcode = opcode;
+
+        let action = match opcode {
+            OP_EOF => break,
+            OP_CLEARDATA => Action::ClearData {
+                size: reader.u16()?,
+            },
+            OP_CLEARDATA32 => Action::ClearData32 {
+                size: reader.u32()?,
+            },
+            OP_SETDATA => {
+                let offset = reader.u16()?;
+                let size = reader.u16()?;
+                Action::SetData {
+                    offset,
+                    data: reader.take(size as usize)?.to_vec(),
+                }
+            }
+            OP_SETDATA32 => {
+                let offset = reader.u32()?;
+                let size = reader.u32()?;
+                Action::SetData32 {
+                    offset,
+                    data: reader.take(size as usize)?.to_vec(),
+                }
+            }
+            OP_SETADDR => Action::SetAddr {
+                addr: reader.address()?,
+            },
+            OP_SETVALUE => Action::SetValue {
+                value: reader.u256()?,
+            },
+            OP_SETVALUEBAL => Action::SetValueFromBalance {
+                reserve: reader.u256()?,
+            },
+            OP_SETVALUEDATA => Action::SetValueFromData {
+                data_offset: reader.u16()?,
+            },
+            OP_SETGAS => Action::SetGas { gas: reader.u32()? },
+            OP_EXTCODECOPY => Action::ExtCodeCopy {
+                source: reader.address()?,
+                data_offset: reader.u16()?,
+                code_offset: reader.u16()?,
+                size: reader.u16()?,
+            },
+            OP_EXTCODECOPY32 => Action::ExtCodeCopy32 {
+                source: reader.address()?,
+                data_offset: reader.u32()?,
+                code_offset: reader.u32()?,
+                size: reader.u32()?,
+            },
+            OP_CALL => Action::Call,
+            OP_CREATE => Action::Create {
+                created_address: None,
+            },
+            OP_CREATE2 => Action::Create2 {
+                salt: reader.b256()?,
+                created_address: None,
+            },
+            OP_DELEGATECALL => Action::DelegateCall,
+            OP_STATICCALL => Action::StaticCall,
+            OP_SETCALLBACK => Action::SetCallback {
+                callback_address: reader.address()?,
+            },
+            OP_SETFAIL => Action::SetFail,
+            OP_CLEARFAIL => Action::ClearFail,
+            OP_COPYRETURN => Action::CopyReturn {
+                ret_offset: reader.u16()?,
+                data_offset: reader.u16()?,
+                size: reader.u16()?,
+            },
+            OP_SELFBALANCE => Action::SelfBalance {
+                data_offset: reader.u16()?,
+            },
+            OP_TOKENBALANCE => Action::TokenBalance {
+                token: reader.address()?,
+                holder: reader.address()?,
+                data_offset: reader.u16()?,
+            },
+            OP_JUMP => Action::Jump {
+                dest: JumpDest::Offset(reader.u16()?),
+            },
+            OP_JUMPIFFAILED => Action::JumpIfFailed {
+                dest: JumpDest::Offset(reader.u16()?),
+            },
+            OP_JUMPIFWORD => Action::JumpIfWord {
+                data_offset: reader.u16()?,
+                cmp: reader.cmp()?,
+                value: reader.u256()?,
+                dest: JumpDest::Offset(reader.u16()?),
+            },
+            OP_ARITH => Action::Arith {
+                op: reader.arith_op()?,
+                dst: reader.u16()?,
+                lhs: reader.u16()?,
+                rhs: reader.u16()?,
+            },
+            OP_ARITHI => Action::ArithImm {
+                op: reader.arith_op()?,
+                dst: reader.u16()?,
+                lhs: reader.u16()?,
+                value: reader.u256()?,
+            },
+            OP_SNAPSHOT => Action::Snapshot {
+                token: reader.address()?,
+            },
+            OP_CHECKPROFIT => Action::CheckProfit {
+                token: reader.address()?,
+                min_profit: reader.u256()?,
+            },
+            OP_PAYCOINBASE => Action::PayCoinbase {
+                payment: reader.payment()?,
+            },
+            OP_MAXBLOCK => Action::MaxBlock {
+                number: reader.u256()?,
+            },
+            OP_DEADLINE => Action::Deadline {
+                timestamp: reader.u256()?,
+            },
+            OP_MAXBASEFEE => Action::MaxBaseFee {
+                fee: reader.u256()?,
+            },
+            OP_STOREREG => Action::StoreReg {
+                register: reader.u8()?,
+                data_offset: reader.u16()?,
+            },
+            OP_LOADREG => Action::LoadReg {
+                register: reader.u8()?,
+                data_offset: reader.u16()?,
+            },
+            OP_SWEEP => Action::Sweep {
+                token: reader.address()?,
+                recipient: reader.address()?,
+            },
+            OP_LOG => Action::Log {
+                tag: reader.b256()?,
+                data_offset: reader.u16()?,
+                size: reader.u16()?,
+            },
+            _ => return Err(DecodeError::UnknownOpcode { offset, opcode }),
+        };
+        actions.push(action);
+    }
+
+    Ok(actions)
+}
+
+/// Cursor over the action stream that reports truncation against the opcode
+/// currently being decoded.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    opcode: u8,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(DecodeError::Truncated {
+                offset: self.pos,
+                opcode: self.opcode,
+            })?;
+        let res = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(res)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn cmp(&mut self) -> Result<Cmp, DecodeError> {
+        let offset = self.pos;
+        Cmp::from_u8(self.take(1)?[0]).ok_or(DecodeError::InvalidOperand {
+            offset,
+            opcode: self.opcode,
+        })
+    }
+
+    fn arith_op(&mut self) -> Result<ArithOp, DecodeError> {
+        let offset = self.pos;
+        ArithOp::from_u8(self.take(1)?[0]).ok_or(DecodeError::InvalidOperand {
+            offset,
+            opcode: self.opcode,
+        })
+    }
+
+    fn payment(&mut self) -> Result<CoinbasePayment, DecodeError> {
+        let offset = self.pos;
+        let mode = self.take(1)?[0];
+        let operand = self.u256()?;
+        CoinbasePayment::from_parts(mode, operand).ok_or(DecodeError::InvalidOperand {
+            offset,
+            opcode: self.opcode,
+        })
+    }
+
+    fn address(&mut self) -> Result<Address, DecodeError> {
+        Ok(Address::from_slice(self.take(20)?))
+    }
+
+    fn u256(&mut self) -> Result<U256, DecodeError> {
+        Ok(U256::from_be_slice(self.take(32)?))
+    }
+
+    fn b256(&mut self) -> Result<B256, DecodeError> {
+        Ok(B256::from_slice(self.take(32)?))
+    }
+}
@@ -1,11 +1,22 @@
-use alloy_primitives::{Address, U256};
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::{Index, IndexMut, RangeBounds},
+};
 
+use alloy_primitives::{Address, B256, U256};
+
+use crate::decoder::{decode, DecodeError};
 use crate::opcodes::*;
+use crate::optimizer;
 
 /// Function selector for `executeActions()`.
 /// Derived from `keccak256("executeActions()")[..4]`.
 /// Verified by `test_execute_actions_selector` in `test.rs`.
-const EXECUTE_ACTIONS_SELECTOR: [u8; 4] = [0xc9, 0x4f, 0x55, 0x4d];
+pub(crate) const EXECUTE_ACTIONS_SELECTOR: [u8; 4] = [0xc9, 0x4f, 0x55, 0x4d];
+
+/// Number of 32-byte registers addressed by `STOREREG` and `LOADREG`.
+pub const REGISTER_COUNT: u8 = 16;
 
 // ---------------------------------------------------------------------------
 // Action enum — single source of truth for opcode encoding
@@ -13,58 +24,448 @@ const EXECUTE_ACTIONS_SELECTOR: [u8; 4] = [0xc9, 0x4f, 0x55, 0x4d];
 
 /// A single operation in the executor bytecode stream.
 ///
-/// Each variant maps 1:1 to an opcode constant in [`crate::opcodes`].
-enum Action {
+/// Each variant maps 1:1 to an opcode constant in [`crate::opcodes`]: the
+/// `*32` actions always encode to the 32-bit opcode variants (`CLEARDATA32`,
+/// ...), whatever their operands, and decode back to themselves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// `CLEARDATA`: replaces the data buffer with `size` zero bytes.
     ClearData {
         size: u16,
     },
+    /// [`ClearData`](Self::ClearData) with a `uint32` size, for buffers past
+    /// 64 KiB.
+    ClearData32 {
+        size: u32,
+    },
+    /// `SETDATA`: writes `data` into the buffer at `offset`. Its size is a
+    /// `uint16` too, so longer payloads need [`SetData32`](Self::SetData32).
     SetData {
         offset: u16,
         data: Vec<u8>,
     },
+    /// [`SetData`](Self::SetData) with a `uint32` offset.
+    SetData32 {
+        offset: u32,
+        data: Vec<u8>,
+    },
+    /// `SETADDR`: sets the target of subsequent calls.
     SetAddr {
         addr: Address,
     },
+    /// `SETVALUE`: sets the ETH value of the next call or create.
     SetValue {
         value: U256,
     },
+    /// `SETVALUEBAL`: sets the value of the next call or create to the
+    /// executor's ETH balance, as of execution time, minus `reserve`.
+    /// Reverts if the balance is below `reserve`.
+    SetValueFromBalance {
+        reserve: U256,
+    },
+    /// `SETVALUEDATA`: sets the value of the next call or create to the
+    /// 32-byte word at `data_offset`.
+    SetValueFromData {
+        data_offset: u16,
+    },
+    /// `SETGAS`: caps the gas forwarded to the next `CALL`, `DELEGATECALL` or
+    /// `STATICCALL`, which resets it. Zero forwards all remaining gas.
+    SetGas {
+        gas: u32,
+    },
+    /// `EXTCODECOPY`: copies `size` bytes of `source`'s code, starting at
+    /// `code_offset`, into the buffer at `data_offset`.
     ExtCodeCopy {
         source: Address,
         data_offset: u16,
         code_offset: u16,
         size: u16,
     },
+    /// [`ExtCodeCopy`](Self::ExtCodeCopy) with `uint32` offsets and size.
+    ExtCodeCopy32 {
+        source: Address,
+        data_offset: u32,
+        code_offset: u32,
+        size: u32,
+    },
+    /// `CALL`: calls the target with the buffer as calldata.
     Call,
     /// `created_address` is **optimizer-only metadata**: it tells the peephole
     /// optimizer which address the CREATE will produce so it can elide a
-    /// subsequent redundant `SetAddr`. It is *not* encoded into the bytecode.
+    /// subsequent redundant `SetAddr`. It is *not* encoded into the bytecode,
+    /// so actions produced by the [decoder](crate::decoder) carry `None`.
     Create {
-        created_address: Address,
+        created_address: Option<Address>,
     },
+    /// `CREATE2`: deploys the buffer as init code at the address derived
+    /// from the executor's address, `salt` and the init code hash.
+    ///
+    /// `created_address` is optimizer-only metadata, as for
+    /// [`Create`](Action::Create). [`FlowBuilder::create2`] computes it.
+    Create2 {
+        salt: B256,
+        created_address: Option<Address>,
+    },
+    /// `DELEGATECALL`: delegatecalls the target with the buffer as calldata.
     DelegateCall,
+    /// `STATICCALL`: calls the target read-only with the buffer as calldata.
+    /// Unlike `CALL`, it neither sends nor resets the value.
+    StaticCall,
+    /// `SETCALLBACK`: allows `callback_address` to re-enter a flash loan
+    /// callback once.
     SetCallback {
         callback_address: Address,
     },
+    /// `SETFAIL`: revert the whole flow if a later call or create fails.
     SetFail,
+    /// `CLEARFAIL`: ignore failures of later calls.
     ClearFail,
+    /// `COPYRETURN`: copies `size` bytes of the last call's return data,
+    /// starting at `ret_offset`, into the buffer at `data_offset`. Reverts if
+    /// the return data is shorter than `ret_offset + size` or the buffer
+    /// shorter than `data_offset + size`.
+    CopyReturn {
+        ret_offset: u16,
+        data_offset: u16,
+        size: u16,
+    },
+    /// `SELFBALANCE`: writes the executor's ETH balance as a 32-byte word
+    /// into the buffer at `data_offset`.
+    SelfBalance {
+        data_offset: u16,
+    },
+    /// `TOKENBALANCE`: writes `token.balanceOf(holder)` as a 32-byte word
+    /// into the buffer at `data_offset`. The query is a call, so a later
+    /// `COPYRETURN` reads its return data.
+    TokenBalance {
+        token: Address,
+        holder: Address,
+        data_offset: u16,
+    },
+    /// Jump destination. Encodes to nothing: jumps to it land on the action
+    /// that follows it.
+    Label {
+        name: String,
+    },
+    /// `JUMP`: continues execution at `dest`.
+    Jump {
+        dest: JumpDest,
+    },
+    /// `JUMPIFFAILED`: jumps to `dest` if the last `CALL`, `DELEGATECALL`,
+    /// `STATICCALL`, `CREATE` or `CREATE2` failed. Only a call made with the
+    /// fail flag cleared can fail without reverting the flow.
+    JumpIfFailed {
+        dest: JumpDest,
+    },
+    /// `JUMPIFWORD`: jumps to `dest` if the 32-byte word at `data_offset`
+    /// compares true against `value`, i.e. `word <cmp> value`.
+    JumpIfWord {
+        data_offset: u16,
+        cmp: Cmp,
+        value: U256,
+        dest: JumpDest,
+    },
+    /// `ARITH`: writes `word(lhs) <op> word(rhs)` to the word at `dst`, where
+    /// each offset addresses a 32-byte word of the buffer. Reverts on
+    /// overflow, underflow and division by zero.
+    Arith {
+        op: ArithOp,
+        dst: u16,
+        lhs: u16,
+        rhs: u16,
+    },
+    /// `ARITHI`: writes `word(lhs) <op> value` to the word at `dst`, with the
+    /// same checks as [`Arith`](Action::Arith).
+    ArithImm {
+        op: ArithOp,
+        dst: u16,
+        lhs: u16,
+        value: U256,
+    },
+    /// `SNAPSHOT`: records the executor's balance of `token`, or of ETH for
+    /// `Address::ZERO`, for a later [`CheckProfit`](Action::CheckProfit).
+    Snapshot {
+        token: Address,
+    },
+    /// `CHECKPROFIT`: reverts with `InsufficientProfit` unless the balance
+    /// of `token` grew by at least `min_profit` since its latest
+    /// [`Snapshot`](Action::Snapshot) in the same flow.
+    CheckProfit {
+        token: Address,
+        min_profit: U256,
+    },
+    /// `PAYCOINBASE`: sends ETH to `block.coinbase`, reverting the flow if
+    /// the transfer fails. Leaves the target, value and fail flag alone.
+    PayCoinbase {
+        payment: CoinbasePayment,
+    },
+    /// `MAXBLOCK`: reverts with `"BLOCK_EXPIRED"` once `block.number` is
+    /// past `number`.
+    MaxBlock {
+        number: U256,
+    },
+    /// `DEADLINE`: reverts with `"DEADLINE_EXPIRED"` once `block.timestamp`
+    /// is past `timestamp`.
+    Deadline {
+        timestamp: U256,
+    },
+    /// `MAXBASEFEE`: reverts with `"BASEFEE_TOO_HIGH"` while `block.basefee`
+    /// is above `fee`.
+    MaxBaseFee {
+        fee: U256,
+    },
+    /// `LOG`: emits `Checkpoint(tag, data)` with the `size` bytes of the
+    /// buffer at `data_offset`, for [`decode_checkpoints`].
+    ///
+    /// [`decode_checkpoints`]: crate::checkpoint::decode_checkpoints
+    Log {
+        tag: B256,
+        data_offset: u16,
+        size: u16,
+    },
+    /// `SWEEP`: sends the executor's whole balance of `token`, or of ETH for
+    /// `Address::ZERO`, to `recipient`, reverting the flow if the transfer
+    /// fails. Leaves the target, value and fail flag alone.
+    Sweep {
+        token: Address,
+        recipient: Address,
+    },
+    /// `STOREREG`: copies the 32-byte word at `data_offset` into `register`.
+    /// Registers belong to the flow and survive `CLEARDATA`.
+    StoreReg {
+        register: u8,
+        data_offset: u16,
+    },
+    /// `LOADREG`: writes `register` to the 32-byte word at `data_offset`.
+    /// A register never stored to holds zero.
+    LoadReg {
+        register: u8,
+        data_offset: u16,
+    },
+}
+
+/// Target of a jump action.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JumpDest {
+    /// An [`Action::Label`] of the same flow, resolved to a byte offset by
+    /// [`FlowBuilder::build`].
+    Label(String),
+    /// A byte offset into the action stream. Offsets at or past the end of
+    /// the stream end the flow. The [decoder](crate::decoder) produces these.
+    Offset(u16),
+}
+
+impl From<&str> for JumpDest {
+    fn from(label: &str) -> Self {
+        JumpDest::Label(label.to_owned())
+    }
+}
+
+impl From<u16> for JumpDest {
+    fn from(offset: u16) -> Self {
+        JumpDest::Offset(offset)
+    }
+}
+
+/// Comparison operator of `JUMPIFWORD`, encoded as its discriminant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Cmp {
+    Eq = 0,
+    Ne = 1,
+    Lt = 2,
+    Gt = 3,
+    Le = 4,
+    Ge = 5,
+}
+
+impl Cmp {
+    /// All operators, in encoding order.
+    pub const ALL: [Cmp; 6] = [Cmp::Eq, Cmp::Ne, Cmp::Lt, Cmp::Gt, Cmp::Le, Cmp::Ge];
+
+    /// Decodes an operand byte, or `None` if the executor would reject it.
+    pub fn from_u8(byte: u8) -> Option<Cmp> {
+        Self::ALL.get(byte as usize).copied()
+    }
+
+    /// Evaluates `word <self> value`.
+    pub fn eval(self, word: U256, value: U256) -> bool {
+        match self {
+            Cmp::Eq => word == value,
+            Cmp::Ne => word != value,
+            Cmp::Lt => word < value,
+            Cmp::Gt => word > value,
+            Cmp::Le => word <= value,
+            Cmp::Ge => word >= value,
+        }
+    }
+
+    /// Assembly mnemonic, e.g. `"LT"`.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Cmp::Eq => "EQ",
+            Cmp::Ne => "NE",
+            Cmp::Lt => "LT",
+            Cmp::Gt => "GT",
+            Cmp::Le => "LE",
+            Cmp::Ge => "GE",
+        }
+    }
+}
+
+/// Amount paid by `PAYCOINBASE`, encoded as a mode byte and a 32-byte
+/// operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CoinbasePayment {
+    /// A fixed amount of wei (mode 0).
+    Amount(U256),
+    /// A share of the executor's ETH balance at payment time, in basis
+    /// points (mode 1). The executor rejects more than 10,000.
+    BalanceBps(u16),
+}
+
+impl CoinbasePayment {
+    /// Largest [`BalanceBps`](Self::BalanceBps) share: the whole balance.
+    pub const MAX_BPS: u16 = 10_000;
+
+    /// Decodes a mode byte and operand, or `None` if the executor would
+    /// reject them.
+    pub fn from_parts(mode: u8, operand: U256) -> Option<CoinbasePayment> {
+        match mode {
+            0 => Some(CoinbasePayment::Amount(operand)),
+            1 => u16::try_from(operand)
+                .ok()
+                .filter(|bps| *bps <= Self::MAX_BPS)
+                .map(CoinbasePayment::BalanceBps),
+            _ => None,
+        }
+    }
+
+    /// The mode byte and operand this payment encodes to.
+    pub fn to_parts(self) -> (u8, U256) {
+        match self {
+            CoinbasePayment::Amount(amount) => (0, amount),
+            CoinbasePayment::BalanceBps(bps) => (1, U256::from(bps)),
+        }
+    }
+
+    /// Wei paid when the executor holds `balance`.
+    pub fn amount(self, balance: U256) -> U256 {
+        match self {
+            CoinbasePayment::Amount(amount) => amount,
+            CoinbasePayment::BalanceBps(bps) => balance * U256::from(bps) / U256::from(10_000),
+        }
+    }
+}
+
+/// Operator of `ARITH` and `ARITHI`, encoded as its discriminant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum ArithOp {
+    Add = 0,
+    Sub = 1,
+    Mul = 2,
+    Div = 3,
+}
+
+impl ArithOp {
+    /// All operators, in encoding order.
+    pub const ALL: [ArithOp; 4] = [ArithOp::Add, ArithOp::Sub, ArithOp::Mul, ArithOp::Div];
+
+    /// Decodes an operand byte, or `None` if the executor would reject it.
+    pub fn from_u8(byte: u8) -> Option<ArithOp> {
+        Self::ALL.get(byte as usize).copied()
+    }
+
+    /// Evaluates `lhs <self> rhs`, or `None` where the executor reverts.
+    pub fn eval(self, lhs: U256, rhs: U256) -> Option<U256> {
+        match self {
+            ArithOp::Add => lhs.checked_add(rhs),
+            ArithOp::Sub => lhs.checked_sub(rhs),
+            ArithOp::Mul => lhs.checked_mul(rhs),
+            ArithOp::Div => lhs.checked_div(rhs),
+        }
+    }
+
+    /// Assembly mnemonic, e.g. `"ADD"`.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            ArithOp::Add => "ADD",
+            ArithOp::Sub => "SUB",
+            ArithOp::Mul => "MUL",
+            ArithOp::Div => "DIV",
+        }
+    }
 }
 
 impl Action {
-    fn encode(&self) -> Vec<u8> {
+    /// Encodes this action into its opcode and operand bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is a jump to a [`JumpDest::Label`]: labels only have an
+    /// offset within a whole flow, see [`FlowBuilder::build`]. Also panics if
+    /// a `SETDATA` payload does not fit its size operand.
+    pub fn encode(&self) -> Vec<u8> {
+        self.encode_with(&mut |dest| match dest {
+            JumpDest::Offset(offset) => *offset,
+            JumpDest::Label(name) => panic!("jump to label `{name}` encoded outside a flow"),
+        })
+    }
+
+    /// A `CLEARDATA` of `size` bytes, as [`ClearData32`](Self::ClearData32)
+    /// only if `size` does not fit a `u16`.
+    pub(crate) fn clear_data(size: u32) -> Action {
+        match u16::try_from(size) {
+            Ok(size) => Action::ClearData { size },
+            Err(_) => Action::ClearData32 { size },
+        }
+    }
+
+    /// A `SETDATA` at `offset`, as [`SetData32`](Self::SetData32) only if
+    /// `offset` or the length of `data` does not fit a `u16`.
+    pub(crate) fn set_data(offset: u32, data: Vec<u8>) -> Action {
+        match u16::try_from(offset) {
+            Ok(offset) if data.len() <= u16::MAX as usize => Action::SetData { offset, data },
+            _ => Action::SetData32 { offset, data },
+        }
+    }
+
+    /// An `EXTCODECOPY`, as [`ExtCodeCopy32`](Self::ExtCodeCopy32) only if an
+    /// operand does not fit a `u16`.
+    pub(crate) fn extcodecopy(
+        source: Address,
+        data_offset: u32,
+        code_offset: u32,
+        size: u32,
+    ) -> Action {
+        match (
+            u16::try_from(data_offset),
+            u16::try_from(code_offset),
+            u16::try_from(size),
+        ) {
+            (Ok(data_offset), Ok(code_offset), Ok(size)) => Action::ExtCodeCopy {
+                source,
+                data_offset,
+                code_offset,
+                size,
+            },
+            _ => Action::ExtCodeCopy32 {
+                source,
+                data_offset,
+                code_offset,
+                size,
+            },
+        }
+    }
+
+    /// Encodes this action, resolving jump destinations with `resolve`.
+    pub(crate) fn encode_with(&self, resolve: &mut dyn FnMut(&JumpDest) -> u16) -> Vec<u8> {
         match self {
-            Action::ClearData { size } => {
-                let mut buf = vec![OP_CLEARDATA];
-                buf.extend(&size.to_be_bytes());
-                buf
-            }
-            Action::SetData { offset, data } => {
-                let data_size = data.len() as u16;
-                let mut buf = vec![OP_SETDATA];
-                buf.extend(&offset.to_be_bytes());
-                buf.extend(&data_size.to_be_bytes());
-                buf.extend(data);
-                buf
-            }
+            Action::ClearData { size } => encode_clear_data((*size).into(), false),
+            Action::ClearData32 { size } => encode_clear_data(*size, true),
+            Action::SetData { offset, data } => encode_set_data((*offset).into(), data, false),
+            Action::SetData32 { offset, data } => encode_set_data(*offset, data, true),
             Action::SetAddr { addr } => {
                 let mut buf = vec![OP_SETADDR];
                 buf.extend(addr.as_slice());
@@ -75,22 +476,46 @@ impl Action {
                 buf.extend(&value.to_be_bytes::<32>());
                 buf
             }
+            Action::SetValueFromBalance { reserve } => {
+                let mut buf = vec![OP_SETVALUEBAL];
+                buf.extend(&reserve.to_be_bytes::<32>());
+                buf
+            }
+            Action::SetValueFromData { data_offset } => {
+                let mut buf = vec![OP_SETVALUEDATA];
+                buf.extend(&data_offset.to_be_bytes());
+                buf
+            }
+            Action::SetGas { gas } => {
+                let mut buf = vec![OP_SETGAS];
+                buf.extend(&gas.to_be_bytes());
+                buf
+            }
             Action::ExtCodeCopy {
                 source,
                 data_offset,
                 code_offset,
                 size,
-            } => {
-                let mut buf = vec![OP_EXTCODECOPY];
-                buf.extend(source.as_slice());
-                buf.extend(&data_offset.to_be_bytes());
-                buf.extend(&code_offset.to_be_bytes());
-                buf.extend(&size.to_be_bytes());
-                buf
-            }
+            } => encode_extcodecopy(
+                source,
+                [(*data_offset).into(), (*code_offset).into(), (*size).into()],
+                false,
+            ),
+            Action::ExtCodeCopy32 {
+                source,
+                data_offset,
+                code_offset,
+                size,
+            } => encode_extcodecopy(source, [*data_offset, *code_offset, *size], true),
             Action::Call => vec![OP_CALL],
             Action::Create { .. } => vec![OP_CREATE],
+            Action::Create2 { salt, .. } => {
+                let mut buf = vec![OP_CREATE2];
+                buf.extend(salt.as_slice());
+                buf
+            }
             Action::DelegateCall => vec![OP_DELEGATECALL],
+            Action::StaticCall => vec![OP_STATICCALL],
             Action::SetCallback { callback_address } => {
                 let mut buf = vec![OP_SETCALLBACK];
                 buf.extend(callback_address.as_slice());
@@ -98,65 +523,725 @@ impl Action {
             }
             Action::SetFail => vec![OP_SETFAIL],
             Action::ClearFail => vec![OP_CLEARFAIL],
+            Action::CopyReturn {
+                ret_offset,
+                data_offset,
+                size,
+            } => {
+                let mut buf = vec![OP_COPYRETURN];
+                buf.extend(&ret_offset.to_be_bytes());
+                buf.extend(&data_offset.to_be_bytes());
+                buf.extend(&size.to_be_bytes());
+                buf
+            }
+            Action::SelfBalance { data_offset } => {
+                let mut buf = vec![OP_SELFBALANCE];
+                buf.extend(&data_offset.to_be_bytes());
+                buf
+            }
+            Action::TokenBalance {
+                token,
+                holder,
+                data_offset,
+            } => {
+                let mut buf = vec![OP_TOKENBALANCE];
+                buf.extend(token.as_slice());
+                buf.extend(holder.as_slice());
+                buf.extend(&data_offset.to_be_bytes());
+                buf
+            }
+            Action::Label { .. } => Vec::new(),
+            Action::Jump { dest } => {
+                let mut buf = vec![OP_JUMP];
+                buf.extend(&resolve(dest).to_be_bytes());
+                buf
+            }
+            Action::JumpIfFailed { dest } => {
+                let mut buf = vec![OP_JUMPIFFAILED];
+                buf.extend(&resolve(dest).to_be_bytes());
+                buf
+            }
+            Action::JumpIfWord {
+                data_offset,
+                cmp,
+                value,
+                dest,
+            } => {
+                let mut buf = vec![OP_JUMPIFWORD];
+                buf.extend(&data_offset.to_be_bytes());
+                buf.push(*cmp as u8);
+                buf.extend(&value.to_be_bytes::<32>());
+                buf.extend(&resolve(dest).to_be_bytes());
+                buf
+            }
+            Action::Arith { op, dst, lhs, rhs } => {
+                let mut buf = vec![OP_ARITH, *op as u8];
+                buf.extend(&dst.to_be_bytes());
+                buf.extend(&lhs.to_be_bytes());
+                buf.extend(&rhs.to_be_bytes());
+                buf
+            }
+            Action::ArithImm {
+                op,
+                dst,
+                lhs,
+                value,
+            } => {
+                let mut buf = vec![OP_ARITHI, *op as u8];
+                buf.extend(&dst.to_be_bytes());
+                buf.extend(&lhs.to_be_bytes());
+                buf.extend(&value.to_be_bytes::<32>());
+                buf
+            }
+            Action::PayCoinbase { payment } => {
+                let (mode, operand) = payment.to_parts();
+                let mut buf = vec![OP_PAYCOINBASE, mode];
+                buf.extend(&operand.to_be_bytes::<32>());
+                buf
+            }
+            Action::MaxBlock { number: bound }
+            | Action::Deadline { timestamp: bound }
+            | Action::MaxBaseFee { fee: bound } => {
+                let opcode = match self {
+                    Action::MaxBlock { .. } => OP_MAXBLOCK,
+                    Action::Deadline { .. } => OP_DEADLINE,
+                    _ => OP_MAXBASEFEE,
+                };
+                let mut buf = vec![opcode];
+                buf.extend(&bound.to_be_bytes::<32>());
+                buf
+            }
+            Action::Log {
+                tag,
+                data_offset,
+                size,
+            } => {
+                let mut buf = vec![OP_LOG];
+                buf.extend(tag.as_slice());
+                buf.extend(&data_offset.to_be_bytes());
+                buf.extend(&size.to_be_bytes());
+                buf
+            }
+            Action::Sweep { token, recipient } => {
+                let mut buf = vec![OP_SWEEP];
+                buf.extend(token.as_slice());
+                buf.extend(recipient.as_slice());
+                buf
+            }
+            Action::StoreReg {
+                register,
+                data_offset,
+            }
+            | Action::LoadReg {
+                register,
+                data_offset,
+            } => {
+                let opcode = match self {
+                    Action::StoreReg { .. } => OP_STOREREG,
+                    _ => OP_LOADREG,
+                };
+                let mut buf = vec![opcode, *register];
+                buf.extend(&data_offset.to_be_bytes());
+                buf
+            }
+            Action::Snapshot { token } => {
+                let mut buf = vec![OP_SNAPSHOT];
+                buf.extend(token.as_slice());
+                buf
+            }
+            Action::CheckProfit { token, min_profit } => {
+                let mut buf = vec![OP_CHECKPROFIT];
+                buf.extend(token.as_slice());
+                buf.extend(&min_profit.to_be_bytes::<32>());
+                buf
+            }
         }
     }
-}
 
-// ---------------------------------------------------------------------------
-// FlowBuilder
-// ---------------------------------------------------------------------------
+    /// The jump destination of a jump action.
+    pub fn jump_dest(&self) -> Option<&JumpDest> {
+        match self {
+            Action::Jump { dest }
+            | Action::JumpIfFailed { dest }
+            | Action::JumpIfWord { dest, .. } => Some(dest),
+            _ => None,
+        }
+    }
+}
 
-/// Builder for constructing executor bytecode action sequences.
-///
-/// Methods that add actions return `&mut Self` for chaining.
-/// Call [`optimize`](Self::optimize) before [`build`](Self::build) to remove
-/// redundant operations.
-#[derive(Default)]
-pub struct FlowBuilder {
-    actions: Vec<Action>,
+fn encode_clear_data(size: u32, wide: bool) -> Vec<u8> {
+    let opcode = if wide { OP_CLEARDATA32 } else { OP_CLEARDATA };
+    let mut buf = vec![opcode];
+    push_size(&mut buf, size, wide);
+    buf
 }
 
-impl FlowBuilder {
-    /// Creates an empty `FlowBuilder` with no actions.
-    pub fn empty() -> Self {
-        Self::default()
+fn encode_set_data(offset: u32, data: &[u8], wide: bool) -> Vec<u8> {
+    if wide {
+        assert!(
+            data.len() <= u32::MAX as usize,
+            "SETDATA32 payload exceeds u32::MAX"
+        );
+    } else {
+        assert!(
+            data.len() <= u16::MAX as usize,
+            "SETDATA payload exceeds u16::MAX, use SetData32"
+        );
     }
+    let data_size = data.len() as u32;
+    let opcode = if wide { OP_SETDATA32 } else { OP_SETDATA };
+    let mut buf = vec![opcode];
+    push_size(&mut buf, offset, wide);
+    push_size(&mut buf, data_size, wide);
+    buf.extend(data);
+    buf
+}
 
-    // -- Low-level opcode pushers ------------------------------------------
-
-    /// Adds a `CLEARDATA` operation to the action list.
-    pub fn set_cleardata_op(&mut self, size: u16) -> &mut Self {
-        self.actions.push(Action::ClearData { size });
-        self
+fn encode_extcodecopy(source: &Address, operands: [u32; 3], wide: bool) -> Vec<u8> {
+    let opcode = if wide {
+        OP_EXTCODECOPY32
+    } else {
+        OP_EXTCODECOPY
+    };
+    let mut buf = vec![opcode];
+    buf.extend(source.as_slice());
+    for operand in operands {
+        push_size(&mut buf, operand, wide);
     }
+    buf
+}
 
-    /// Adds a `SETDATA` operation to the action list.
-    pub fn set_data_op(&mut self, offset: u16, data: &[u8]) -> &mut Self {
-        self.actions.push(Action::SetData {
-            offset,
-            data: data.to_owned(),
-        });
-        self
+/// Appends a size or offset operand: a `uint32` for the 32-bit opcode
+/// variants, a `uint16` otherwise.
+fn push_size(buf: &mut Vec<u8>, operand: u32, wide: bool) {
+    if wide {
+        buf.extend(&operand.to_be_bytes());
+    } else {
+        buf.extend(&(operand as u16).to_be_bytes());
     }
+}
 
-    /// Adds a `SETADDR` operation to the action list.
-    pub fn set_addr_op(&mut self, addr: Address) -> &mut Self {
-        self.actions.push(Action::SetAddr { addr });
-        self
+/// Encodes `actions` into a raw action stream, resolving jumps to labels.
+///
+/// # Panics
+///
+/// Panics if a [`SetData`](Action::SetData) payload is longer than
+/// `u16::MAX` or a [`SetData32`](Action::SetData32) one longer than
+/// `u32::MAX`.
+pub(crate) fn encode_actions(actions: &[Action]) -> Result<Vec<u8>, FlowError> {
+    let labels = label_offsets(actions)?;
+    let mut unknown = None;
+    let mut resolve = |dest: &JumpDest| match dest {
+        JumpDest::Offset(offset) => *offset,
+        JumpDest::Label(name) => labels.get(name.as_str()).copied().unwrap_or_else(|| {
+            unknown.get_or_insert_with(|| name.clone());
+            0
+        }),
+    };
+    let bytecode = actions
+        .iter()
+        .flat_map(|a| a.encode_with(&mut resolve))
+        .collect();
+    match unknown {
+        Some(name) => Err(FlowError::UnknownLabel { name }),
+        None => Ok(bytecode),
     }
+}
 
-    /// Adds a `SETVALUE` operation to the action list.
-    pub fn set_value_op(&mut self, value: U256) -> &mut Self {
-        self.actions.push(Action::SetValue { value });
-        self
+/// Byte offset of every label in `actions`.
+///
+/// Fails on the first label that is defined twice or that lies past the
+/// reach of a `uint16` jump operand.
+pub(crate) fn label_offsets(actions: &[Action]) -> Result<HashMap<&str, u16>, FlowError> {
+    let mut labels = HashMap::new();
+    let mut pos = 0usize;
+    for action in actions {
+        if let Action::Label { name } = action {
+            let offset = u16::try_from(pos).map_err(|_| FlowError::LabelOutOfRange {
+                name: name.clone(),
+                offset: pos,
+            })?;
+            if labels.insert(name.as_str(), offset).is_some() {
+                return Err(FlowError::DuplicateLabel { name: name.clone() });
+            }
+        }
+        pos += action.encode_with(&mut |_| 0).len();
     }
+    Ok(labels)
+}
 
-    /// Adds an `EXTCODECOPY` operation to the action list.
-    pub fn set_extcodecopy_op(
-        &mut self,
-        source: Address,
-        data_offset: u16,
-        code_offset: u16,
+// ---------------------------------------------------------------------------
+// FlowError
+// ---------------------------------------------------------------------------
+
+/// Error returned by the fallible `try_*` builder methods.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlowError {
+    /// A payload is longer than a `uint32` size operand can express.
+    DataTooLong { len: usize },
+    /// A `SETDATA` would write past the end of the data buffer.
+    WriteOutOfBounds {
+        offset: usize,
+        len: usize,
+        buffer_size: usize,
+    },
+    /// An `EXTCODECOPY` would write past the end of the data buffer.
+    ExtCodeCopyOutOfBounds {
+        data_offset: usize,
+        size: usize,
+        buffer_size: usize,
+    },
+    /// A `COPYRETURN` would write past the end of the data buffer.
+    CopyReturnOutOfBounds {
+        data_offset: usize,
+        size: usize,
+        buffer_size: usize,
+    },
+    /// A 32-byte word written by `SELFBALANCE` or `TOKENBALANCE`, or read by
+    /// `SETVALUEDATA`, would overflow the data buffer.
+    WordOutOfBounds {
+        data_offset: usize,
+        buffer_size: usize,
+    },
+    /// An action writing to the data buffer runs before any `CLEARDATA` has
+    /// sized it.
+    DataBeforeClear,
+    /// A `CALL`, `DELEGATECALL` or `STATICCALL` runs before any target has
+    /// been set.
+    MissingTarget,
+    /// A jump targets a label that is not defined in the flow.
+    UnknownLabel { name: String },
+    /// A label is defined more than once.
+    DuplicateLabel { name: String },
+    /// A label sits at a byte offset a `uint16` jump operand cannot reach.
+    LabelOutOfRange { name: String, offset: usize },
+    /// A `CHECKPROFIT` runs before any `SNAPSHOT` of its token.
+    MissingSnapshot { token: Address },
+    /// A `PAYCOINBASE` share exceeds [`CoinbasePayment::MAX_BPS`].
+    InvalidShare { bps: u16 },
+    /// A `STOREREG` or `LOADREG` names a register past
+    /// [`REGISTER_COUNT`].
+    InvalidRegister { register: u8 },
+    /// The slice logged by `LOG` does not fit the data buffer.
+    LogOutOfBounds {
+        data_offset: usize,
+        size: usize,
+        buffer_size: usize,
+    },
+    /// A guard inserted at the start of the stream would move the action a
+    /// jump to raw byte offset `offset` lands on.
+    ShiftedJump { offset: u16 },
+}
+
+impl FlowError {
+    /// Checks that `data` fits the encoding and the buffer at `offset`.
+    ///
+    /// `buffer_size` is `None` when no `CLEARDATA` has run yet.
+    pub(crate) fn check_set_data(
+        offset: u32,
+        data: &[u8],
+        buffer_size: Option<usize>,
+    ) -> Result<(), FlowError> {
+        if data.len() > u32::MAX as usize {
+            return Err(FlowError::DataTooLong { len: data.len() });
+        }
+        let buffer_size = buffer_size.ok_or(FlowError::DataBeforeClear)?;
+        if offset as usize + data.len() > buffer_size {
+            return Err(FlowError::WriteOutOfBounds {
+                offset: offset as usize,
+                len: data.len(),
+                buffer_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that a copy of `size` bytes to `data_offset` fits the buffer.
+    pub(crate) fn check_extcodecopy(
+        data_offset: u32,
+        size: u32,
+        buffer_size: Option<usize>,
+    ) -> Result<(), FlowError> {
+        let buffer_size = buffer_size.ok_or(FlowError::DataBeforeClear)?;
+        if data_offset as usize + size as usize > buffer_size {
+            return Err(FlowError::ExtCodeCopyOutOfBounds {
+                data_offset: data_offset as usize,
+                size: size as usize,
+                buffer_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that a return data copy of `size` bytes to `data_offset` fits
+    /// the buffer.
+    pub(crate) fn check_copy_return(
+        data_offset: u16,
+        size: u16,
+        buffer_size: Option<usize>,
+    ) -> Result<(), FlowError> {
+        let buffer_size = buffer_size.ok_or(FlowError::DataBeforeClear)?;
+        if data_offset as usize + size as usize > buffer_size {
+            return Err(FlowError::CopyReturnOutOfBounds {
+                data_offset: data_offset as usize,
+                size: size as usize,
+                buffer_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that a 32-byte word written at `data_offset` fits the buffer.
+    pub(crate) fn check_word(
+        data_offset: u16,
+        buffer_size: Option<usize>,
+    ) -> Result<(), FlowError> {
+        let buffer_size = buffer_size.ok_or(FlowError::DataBeforeClear)?;
+        if data_offset as usize + 32 > buffer_size {
+            return Err(FlowError::WordOutOfBounds {
+                data_offset: data_offset as usize,
+                buffer_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that `register` exists and its word at `data_offset` fits the
+    /// buffer.
+    pub(crate) fn check_register(
+        register: u8,
+        data_offset: u16,
+        buffer_size: Option<usize>,
+    ) -> Result<(), FlowError> {
+        if register >= REGISTER_COUNT {
+            return Err(FlowError::InvalidRegister { register });
+        }
+        FlowError::check_word(data_offset, buffer_size)
+    }
+
+    /// Checks that a `LOG` of `size` bytes at `data_offset` reads inside the
+    /// buffer.
+    pub(crate) fn check_log(
+        data_offset: u16,
+        size: u16,
+        buffer_size: Option<usize>,
+    ) -> Result<(), FlowError> {
+        let buffer_size = buffer_size.ok_or(FlowError::DataBeforeClear)?;
+        if data_offset as usize + size as usize > buffer_size {
+            return Err(FlowError::LogOutOfBounds {
+                data_offset: data_offset as usize,
+                size: size as usize,
+                buffer_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that the executor accepts a `PAYCOINBASE` share.
+    pub(crate) fn check_payment(payment: CoinbasePayment) -> Result<(), FlowError> {
+        match payment {
+            CoinbasePayment::BalanceBps(bps) if bps > CoinbasePayment::MAX_BPS => {
+                Err(FlowError::InvalidShare { bps })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that a payload fits a `uint32` size operand.
+    fn check_len(data: &[u8]) -> Result<u32, FlowError> {
+        u32::try_from(data.len()).map_err(|_| FlowError::DataTooLong { len: data.len() })
+    }
+}
+
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlowError::DataTooLong { len } => {
+                write!(f, "data length {len} exceeds u32::MAX")
+            }
+            FlowError::WriteOutOfBounds {
+                offset,
+                len,
+                buffer_size,
+            } => write!(
+                f,
+                "SETDATA of {len} bytes at offset {offset} overflows {buffer_size}-byte buffer"
+            ),
+            FlowError::ExtCodeCopyOutOfBounds {
+                data_offset,
+                size,
+                buffer_size,
+            } => write!(
+                f,
+                "EXTCODECOPY of {size} bytes at offset {data_offset} overflows {buffer_size}-byte buffer"
+            ),
+            FlowError::CopyReturnOutOfBounds {
+                data_offset,
+                size,
+                buffer_size,
+            } => write!(
+                f,
+                "COPYRETURN of {size} bytes at offset {data_offset} overflows {buffer_size}-byte buffer"
+            ),
+            FlowError::WordOutOfBounds {
+                data_offset,
+                buffer_size,
+            } => write!(
+                f,
+                "32-byte word at offset {data_offset} overflows {buffer_size}-byte buffer"
+            ),
+            FlowError::DataBeforeClear => write!(f, "data buffer written before any CLEARDATA"),
+            FlowError::MissingTarget => write!(f, "call issued before any target was set"),
+            FlowError::UnknownLabel { name } => write!(f, "jump to undefined label `{name}`"),
+            FlowError::DuplicateLabel { name } => write!(f, "label `{name}` defined twice"),
+            FlowError::LabelOutOfRange { name, offset } => {
+                write!(f, "label `{name}` at offset {offset} exceeds u16::MAX")
+            }
+            FlowError::MissingSnapshot { token } => {
+                write!(f, "profit check of {token} before any snapshot of it")
+            }
+            FlowError::InvalidShare { bps } => {
+                write!(f, "coinbase share of {bps} bps exceeds 10000")
+            }
+            FlowError::InvalidRegister { register } => {
+                write!(f, "register {register} exceeds the {REGISTER_COUNT} registers")
+            }
+            FlowError::LogOutOfBounds {
+                data_offset,
+                size,
+                buffer_size,
+            } => write!(
+                f,
+                "LOG of {size} bytes at offset {data_offset} exceeds buffer of {buffer_size} bytes"
+            ),
+            FlowError::ShiftedJump { offset } => {
+                write!(f, "guard would shift the jump to byte offset {offset}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FlowError {}
+
+// ---------------------------------------------------------------------------
+// FlowBuilder
+// ---------------------------------------------------------------------------
+
+/// Builder for constructing executor bytecode action sequences.
+///
+/// Methods that add actions return `&mut Self` for chaining.
+/// Call [`optimize`](Self::optimize) before [`build`](Self::build) to remove
+/// redundant operations.
+#[derive(Debug, Clone, Default)]
+pub struct FlowBuilder {
+    actions: Vec<Action>,
+    executor: Option<Address>,
+}
+
+impl FlowBuilder {
+    /// Creates an empty `FlowBuilder` with no actions.
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty `FlowBuilder` for a flow run by `executor`, so
+    /// [`create2`](Self::create2) can predict deployment addresses.
+    pub fn for_executor(executor: Address) -> Self {
+        Self {
+            executor: Some(executor),
+            ..Self::default()
+        }
+    }
+
+    /// The address running the flow, if known: the executor, or the proxy
+    /// in front of it.
+    pub fn executor(&self) -> Option<Address> {
+        self.executor
+    }
+
+    /// Sets the address running the flow; see [`for_executor`](Self::for_executor).
+    pub fn set_executor(&mut self, executor: Address) -> &mut Self {
+        self.executor = Some(executor);
+        self
+    }
+
+    // -- Inspection and editing --------------------------------------------
+
+    /// Returns the actions in order.
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+
+    /// Returns an iterator over the actions.
+    pub fn iter(&self) -> std::slice::Iter<'_, Action> {
+        self.actions.iter()
+    }
+
+    /// Returns the number of actions.
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// Returns `true` if there are no actions.
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Returns the action at `index`, or `None` if out of bounds.
+    pub fn get(&self, index: usize) -> Option<&Action> {
+        self.actions.get(index)
+    }
+
+    /// Returns a mutable reference to the action at `index`, or `None` if out
+    /// of bounds.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Action> {
+        self.actions.get_mut(index)
+    }
+
+    /// Appends an action.
+    pub fn push(&mut self, action: Action) -> &mut Self {
+        self.actions.push(action);
+        self
+    }
+
+    /// Inserts an action at `index`, shifting later actions back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, action: Action) -> &mut Self {
+        self.actions.insert(index, action);
+        self
+    }
+
+    /// Removes and returns the action at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> Action {
+        self.actions.remove(index)
+    }
+
+    /// Replaces the action at `index`, returning the old one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn replace(&mut self, index: usize, action: Action) -> Action {
+        std::mem::replace(&mut self.actions[index], action)
+    }
+
+    /// Replaces the actions in `range` with `replace_with`, returning the
+    /// removed actions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds.
+    pub fn splice<R, I>(&mut self, range: R, replace_with: I) -> Vec<Action>
+    where
+        R: RangeBounds<usize>,
+        I: IntoIterator<Item = Action>,
+    {
+        self.actions.splice(range, replace_with).collect()
+    }
+
+    /// Consumes the builder and returns its actions.
+    pub fn into_actions(self) -> Vec<Action> {
+        self.actions
+    }
+
+    // -- Low-level opcode pushers ------------------------------------------
+
+    /// Adds a `CLEARDATA` operation to the action list.
+    pub fn set_cleardata_op(&mut self, size: u16) -> &mut Self {
+        self.actions.push(Action::ClearData { size });
+        self
+    }
+
+    /// Adds a `CLEARDATA` operation with a 32-bit size to the action list.
+    pub fn set_cleardata32_op(&mut self, size: u32) -> &mut Self {
+        self.actions.push(Action::ClearData32 { size });
+        self
+    }
+
+    /// Adds a `SETDATA` operation to the action list, as `SETDATA32` if
+    /// `data` is longer than a `uint16` size can express.
+    pub fn set_data_op(&mut self, offset: u16, data: &[u8]) -> &mut Self {
+        self.actions
+            .push(Action::set_data(offset.into(), data.to_owned()));
+        self
+    }
+
+    /// Adds a `SETDATA` operation with a 32-bit offset to the action list.
+    pub fn set_data32_op(&mut self, offset: u32, data: &[u8]) -> &mut Self {
+        self.actions.push(Action::SetData32 {
+            offset,
+            data: data.to_owned(),
+        });
+        self
+    }
+
+    /// Adds a `SETADDR` operation to the action list.
+    pub fn set_addr_op(&mut self, addr: Address) -> &mut Self {
+        self.actions.push(Action::SetAddr { addr });
+        self
+    }
+
+    /// Adds a `SETVALUE` operation to the action list.
+    pub fn set_value_op(&mut self, value: U256) -> &mut Self {
+        self.actions.push(Action::SetValue { value });
+        self
+    }
+
+    /// Sets the value of the next call or create to the executor's whole ETH
+    /// balance when the flow gets there, e.g. to wrap ETH it just received
+    /// without guessing the amount.
+    pub fn set_value_from_balance(&mut self) -> &mut Self {
+        self.set_value_from_balance_keeping(U256::ZERO)
+    }
+
+    /// Like [`set_value_from_balance`](Self::set_value_from_balance), but
+    /// leaves `reserve` wei behind, e.g. for a later `PAYCOINBASE`. The flow
+    /// reverts if the balance is below `reserve`.
+    pub fn set_value_from_balance_keeping(&mut self, reserve: U256) -> &mut Self {
+        self.actions.push(Action::SetValueFromBalance { reserve });
+        self
+    }
+
+    /// Sets the value of the next call or create to the 32-byte word at
+    /// `data_offset`, e.g. an amount copied from a call's return data. The
+    /// value survives `CLEARDATA`, so the word can be read before the buffer
+    /// is resized for the call.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the word does not fit the buffer sized by the last
+    /// `CLEARDATA`; see [`try_set_value_from_data`](Self::try_set_value_from_data).
+    pub fn set_value_from_data(&mut self, data_offset: u16) -> &mut Self {
+        self.try_set_value_from_data(data_offset)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`set_value_from_data`](Self::set_value_from_data).
+    pub fn try_set_value_from_data(&mut self, data_offset: u16) -> Result<&mut Self, FlowError> {
+        FlowError::check_word(data_offset, self.buffer_size())?;
+        self.actions.push(Action::SetValueFromData { data_offset });
+        Ok(self)
+    }
+
+    /// Adds a `SETGAS` operation to the action list.
+    pub fn set_gas_op(&mut self, gas: u32) -> &mut Self {
+        self.actions.push(Action::SetGas { gas });
+        self
+    }
+
+    /// Adds an `EXTCODECOPY` operation to the action list.
+    pub fn set_extcodecopy_op(
+        &mut self,
+        source: Address,
+        data_offset: u16,
+        code_offset: u16,
         size: u16,
     ) -> &mut Self {
         self.actions.push(Action::ExtCodeCopy {
@@ -168,6 +1253,82 @@ impl FlowBuilder {
         self
     }
 
+    /// Adds an `EXTCODECOPY` operation with 32-bit offsets and size to the
+    /// action list.
+    pub fn set_extcodecopy32_op(
+        &mut self,
+        source: Address,
+        data_offset: u32,
+        code_offset: u32,
+        size: u32,
+    ) -> &mut Self {
+        self.actions.push(Action::ExtCodeCopy32 {
+            source,
+            data_offset,
+            code_offset,
+            size,
+        });
+        self
+    }
+
+    /// Like [`set_data_op`](Self::set_data_op), but fails instead of pushing a
+    /// write that overflows the buffer sized by the last `CLEARDATA`.
+    pub fn try_set_data_op(&mut self, offset: u16, data: &[u8]) -> Result<&mut Self, FlowError> {
+        FlowError::check_set_data(offset.into(), data, self.buffer_size())?;
+        Ok(self.set_data_op(offset, data))
+    }
+
+    /// Fallible version of [`set_data32_op`](Self::set_data32_op), like
+    /// [`try_set_data_op`](Self::try_set_data_op).
+    pub fn try_set_data32_op(&mut self, offset: u32, data: &[u8]) -> Result<&mut Self, FlowError> {
+        FlowError::check_set_data(offset, data, self.buffer_size())?;
+        Ok(self.set_data32_op(offset, data))
+    }
+
+    /// Like [`set_extcodecopy_op`](Self::set_extcodecopy_op), but fails
+    /// instead of pushing a copy that overflows the buffer sized by the last
+    /// `CLEARDATA`.
+    pub fn try_set_extcodecopy_op(
+        &mut self,
+        source: Address,
+        data_offset: u16,
+        code_offset: u16,
+        size: u16,
+    ) -> Result<&mut Self, FlowError> {
+        FlowError::check_extcodecopy(data_offset.into(), size.into(), self.buffer_size())?;
+        Ok(self.set_extcodecopy_op(source, data_offset, code_offset, size))
+    }
+
+    /// Fallible version of [`set_extcodecopy32_op`](Self::set_extcodecopy32_op),
+    /// like [`try_set_extcodecopy_op`](Self::try_set_extcodecopy_op).
+    pub fn try_set_extcodecopy32_op(
+        &mut self,
+        source: Address,
+        data_offset: u32,
+        code_offset: u32,
+        size: u32,
+    ) -> Result<&mut Self, FlowError> {
+        FlowError::check_extcodecopy(data_offset, size, self.buffer_size())?;
+        Ok(self.set_extcodecopy32_op(source, data_offset, code_offset, size))
+    }
+
+    /// Sizes the buffer for a high-level helper, with the 32-bit `CLEARDATA`
+    /// only when `size` needs it.
+    fn clear_data(&mut self, size: u32) -> &mut Self {
+        self.actions.push(Action::clear_data(size));
+        self
+    }
+
+    /// Size of the data buffer as set by the most recent `CLEARDATA`, or
+    /// `None` if there is none yet.
+    fn buffer_size(&self) -> Option<usize> {
+        self.actions.iter().rev().find_map(|a| match a {
+            Action::ClearData { size } => Some(*size as usize),
+            Action::ClearData32 { size } => Some(*size as usize),
+            _ => None,
+        })
+    }
+
     /// Adds a `CALL` operation to the action list.
     pub fn call_op(&mut self) -> &mut Self {
         self.actions.push(Action::Call);
@@ -179,7 +1340,19 @@ impl FlowBuilder {
     /// `created_address` is the expected address of the deployed contract,
     /// used by the peephole optimizer to eliminate redundant `SETADDR` ops.
     pub fn create_op(&mut self, created_address: Address) -> &mut Self {
-        self.actions.push(Action::Create { created_address });
+        self.actions.push(Action::Create {
+            created_address: Some(created_address),
+        });
+        self
+    }
+
+    /// Adds a `CREATE2` operation to the action list, without an expected
+    /// address for the optimizer; see [`create2`](Self::create2).
+    pub fn create2_op(&mut self, salt: B256) -> &mut Self {
+        self.actions.push(Action::Create2 {
+            salt,
+            created_address: None,
+        });
         self
     }
 
@@ -189,35 +1362,197 @@ impl FlowBuilder {
         self
     }
 
+    /// Adds a `STATICCALL` operation to the action list.
+    pub fn staticcall_op(&mut self) -> &mut Self {
+        self.actions.push(Action::StaticCall);
+        self
+    }
+
     // -- High-level helpers ------------------------------------------------
 
     /// Prepares a `CALL`: sets target, value, data buffer, then executes.
+    /// Calldata past 64 KiB is written with `CLEARDATA32` and `SETDATA32`,
+    /// as by every helper that fills the buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is longer than `u32::MAX`; see [`try_call`](Self::try_call).
     pub fn call(&mut self, target: Address, data: &[u8], value: U256) -> &mut Self {
-        assert!(
-            data.len() <= u16::MAX as usize,
-            "data length exceeds u16::MAX"
-        );
-        self.set_addr_op(target)
+        self.try_call(target, data, value)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`call`](Self::call).
+    pub fn try_call(
+        &mut self,
+        target: Address,
+        data: &[u8],
+        value: U256,
+    ) -> Result<&mut Self, FlowError> {
+        let size = FlowError::check_len(data)?;
+        Ok(self
+            .set_addr_op(target)
+            .set_value_op(value)
+            .clear_data(size)
+            .set_data_op(0, data)
+            .call_op())
+    }
+
+    /// Like [`call`](Self::call), but forwards at most `gas` to the target,
+    /// so a misbehaving target cannot burn the rest of the flow's gas. With
+    /// the fail flag cleared, running out of gas is an ordinary failure the
+    /// flow can recover from.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is longer than `u32::MAX`; see
+    /// [`try_call_with_gas`](Self::try_call_with_gas).
+    pub fn call_with_gas(
+        &mut self,
+        target: Address,
+        data: &[u8],
+        value: U256,
+        gas: u32,
+    ) -> &mut Self {
+        self.try_call_with_gas(target, data, value, gas)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`call_with_gas`](Self::call_with_gas).
+    pub fn try_call_with_gas(
+        &mut self,
+        target: Address,
+        data: &[u8],
+        value: U256,
+        gas: u32,
+    ) -> Result<&mut Self, FlowError> {
+        let size = FlowError::check_len(data)?;
+        Ok(self
+            .set_addr_op(target)
             .set_value_op(value)
-            .set_cleardata_op(data.len() as u16)
+            .set_gas_op(gas)
+            .clear_data(size)
             .set_data_op(0, data)
-            .call_op()
+            .call_op())
     }
 
     /// Prepares a `DELEGATECALL`: sets target, data buffer, then executes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is longer than `u32::MAX`; see
+    /// [`try_delegatecall`](Self::try_delegatecall).
     pub fn delegatecall(&mut self, target: Address, data: &[u8]) -> &mut Self {
-        self.set_addr_op(target)
-            .set_cleardata_op(data.len() as u16)
+        self.try_delegatecall(target, data)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`delegatecall`](Self::delegatecall).
+    pub fn try_delegatecall(
+        &mut self,
+        target: Address,
+        data: &[u8],
+    ) -> Result<&mut Self, FlowError> {
+        let size = FlowError::check_len(data)?;
+        Ok(self
+            .set_addr_op(target)
+            .clear_data(size)
             .set_data_op(0, data)
-            .delegatecall_op()
+            .delegatecall_op())
+    }
+
+    /// Prepares a `STATICCALL`: sets target, data buffer, then executes.
+    ///
+    /// Use it for read-only probes such as pool reserves or oracle prices:
+    /// the target cannot change state, and its output can be read with
+    /// [`copy_return`](Self::copy_return) or tested with
+    /// [`jump_if_word`](Self::jump_if_word).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is longer than `u32::MAX`; see
+    /// [`try_staticcall`](Self::try_staticcall).
+    pub fn staticcall(&mut self, target: Address, data: &[u8]) -> &mut Self {
+        self.try_staticcall(target, data)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`staticcall`](Self::staticcall).
+    pub fn try_staticcall(&mut self, target: Address, data: &[u8]) -> Result<&mut Self, FlowError> {
+        let size = FlowError::check_len(data)?;
+        Ok(self
+            .set_addr_op(target)
+            .clear_data(size)
+            .set_data_op(0, data)
+            .staticcall_op())
     }
 
     /// Prepares a `CREATE`: sets value, data buffer, then deploys.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is longer than `u32::MAX`; see
+    /// [`try_create`](Self::try_create).
     pub fn create(&mut self, created_address: Address, data: &[u8], value: U256) -> &mut Self {
-        self.set_value_op(value)
-            .set_cleardata_op(data.len() as u16)
+        self.try_create(created_address, data, value)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`create`](Self::create).
+    pub fn try_create(
+        &mut self,
+        created_address: Address,
+        data: &[u8],
+        value: U256,
+    ) -> Result<&mut Self, FlowError> {
+        let size = FlowError::check_len(data)?;
+        Ok(self
+            .set_value_op(value)
+            .clear_data(size)
             .set_data_op(0, data)
-            .create_op(created_address)
+            .create_op(created_address))
+    }
+
+    /// Prepares a `CREATE2`: sets value, data buffer, then deploys `initcode`
+    /// with `salt`.
+    ///
+    /// When the [executor](Self::executor) is known, the deployment address
+    /// is computed from it, `salt` and `keccak256(initcode)` and recorded
+    /// for the optimizer; see [`create2_address`](Self::create2_address).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `initcode` is longer than `u32::MAX`; see
+    /// [`try_create2`](Self::try_create2).
+    pub fn create2(&mut self, salt: B256, initcode: &[u8], value: U256) -> &mut Self {
+        self.try_create2(salt, initcode, value)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`create2`](Self::create2).
+    pub fn try_create2(
+        &mut self,
+        salt: B256,
+        initcode: &[u8],
+        value: U256,
+    ) -> Result<&mut Self, FlowError> {
+        let size = FlowError::check_len(initcode)?;
+        let created_address = self.create2_address(salt, initcode);
+        self.set_value_op(value)
+            .clear_data(size)
+            .set_data_op(0, initcode);
+        self.actions.push(Action::Create2 {
+            salt,
+            created_address,
+        });
+        Ok(self)
+    }
+
+    /// Address a `CREATE2` of `initcode` with `salt` deploys to when run by
+    /// the [executor](Self::executor), or `None` if it is not known.
+    pub fn create2_address(&self, salt: B256, initcode: &[u8]) -> Option<Address> {
+        self.executor
+            .map(|executor| executor.create2_from_code(salt, initcode))
     }
 
     /// Sets the callback address for flash loan handlers.
@@ -238,100 +1573,624 @@ impl FlowBuilder {
         self
     }
 
-    // -- Optimizer ---------------------------------------------------------
+    /// Copies `size` bytes of the last call's return data, starting at
+    /// `ret_offset`, into the data buffer at `data_offset`.
+    ///
+    /// Use it between the `SETDATA` and the `CALL` of the next call to patch
+    /// an on-chain result, e.g. a swap's output amount, into its calldata.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the copy does not fit the buffer sized by the last
+    /// `CLEARDATA`; see [`try_copy_return`](Self::try_copy_return).
+    pub fn copy_return(&mut self, ret_offset: u16, data_offset: u16, size: u16) -> &mut Self {
+        self.try_copy_return(ret_offset, data_offset, size)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
 
-    /// Runs the peephole optimizer to remove redundant operations.
-    pub fn optimize(&mut self) -> &mut Self {
-        self.peephole_opt();
+    /// Fallible version of [`copy_return`](Self::copy_return).
+    pub fn try_copy_return(
+        &mut self,
+        ret_offset: u16,
+        data_offset: u16,
+        size: u16,
+    ) -> Result<&mut Self, FlowError> {
+        FlowError::check_copy_return(data_offset, size, self.buffer_size())?;
+        self.actions.push(Action::CopyReturn {
+            ret_offset,
+            data_offset,
+            size,
+        });
+        Ok(self)
+    }
+
+    /// Writes the executor's ETH balance, as of execution time, into the
+    /// data buffer at `data_offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the word does not fit the buffer sized by the last
+    /// `CLEARDATA`; see [`try_write_balance`](Self::try_write_balance).
+    pub fn write_balance(&mut self, data_offset: u16) -> &mut Self {
+        self.try_write_balance(data_offset)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`write_balance`](Self::write_balance).
+    pub fn try_write_balance(&mut self, data_offset: u16) -> Result<&mut Self, FlowError> {
+        FlowError::check_word(data_offset, self.buffer_size())?;
+        self.actions.push(Action::SelfBalance { data_offset });
+        Ok(self)
+    }
+
+    /// Writes `token.balanceOf(holder)`, as of execution time, into the data
+    /// buffer at `data_offset`, e.g. to fill in the amount of a
+    /// `transfer(to, amount)` with the full balance.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the word does not fit the buffer sized by the last
+    /// `CLEARDATA`; see [`try_write_token_balance`](Self::try_write_token_balance).
+    pub fn write_token_balance(
+        &mut self,
+        token: Address,
+        holder: Address,
+        data_offset: u16,
+    ) -> &mut Self {
+        self.try_write_token_balance(token, holder, data_offset)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`write_token_balance`](Self::write_token_balance).
+    pub fn try_write_token_balance(
+        &mut self,
+        token: Address,
+        holder: Address,
+        data_offset: u16,
+    ) -> Result<&mut Self, FlowError> {
+        FlowError::check_word(data_offset, self.buffer_size())?;
+        self.actions.push(Action::TokenBalance {
+            token,
+            holder,
+            data_offset,
+        });
+        Ok(self)
+    }
+
+    /// Writes `word(lhs) <op> word(rhs)` to the word at `dst`, computed at
+    /// execution time. All three are buffer offsets of 32-byte words and may
+    /// overlap, e.g. `dst == lhs` to update a word in place.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a word does not fit the buffer sized by the last
+    /// `CLEARDATA`; see [`try_arith`](Self::try_arith).
+    pub fn arith(&mut self, op: ArithOp, dst: u16, lhs: u16, rhs: u16) -> &mut Self {
+        self.try_arith(op, dst, lhs, rhs)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`arith`](Self::arith).
+    pub fn try_arith(
+        &mut self,
+        op: ArithOp,
+        dst: u16,
+        lhs: u16,
+        rhs: u16,
+    ) -> Result<&mut Self, FlowError> {
+        for offset in [lhs, rhs, dst] {
+            FlowError::check_word(offset, self.buffer_size())?;
+        }
+        self.actions.push(Action::Arith { op, dst, lhs, rhs });
+        Ok(self)
+    }
+
+    /// Writes `word(lhs) <op> value` to the word at `dst`, computed at
+    /// execution time, e.g. `borrowed + premium` from a callback's amount.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a word does not fit the buffer sized by the last
+    /// `CLEARDATA`; see [`try_arith_imm`](Self::try_arith_imm).
+    pub fn arith_imm(&mut self, op: ArithOp, dst: u16, lhs: u16, value: U256) -> &mut Self {
+        self.try_arith_imm(op, dst, lhs, value)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`arith_imm`](Self::arith_imm).
+    pub fn try_arith_imm(
+        &mut self,
+        op: ArithOp,
+        dst: u16,
+        lhs: u16,
+        value: U256,
+    ) -> Result<&mut Self, FlowError> {
+        for offset in [lhs, dst] {
+            FlowError::check_word(offset, self.buffer_size())?;
+        }
+        self.actions.push(Action::ArithImm {
+            op,
+            dst,
+            lhs,
+            value,
+        });
+        Ok(self)
+    }
+
+    /// Scales the word at `data_offset` in place by `numerator /
+    /// denominator`, rounding down, e.g. `95 / 100` for 95% of a quoted
+    /// output. The multiplication reverts if it overflows.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the word does not fit the buffer sized by the last
+    /// `CLEARDATA`; see [`try_arith_imm`](Self::try_arith_imm).
+    pub fn scale_word(
+        &mut self,
+        data_offset: u16,
+        numerator: U256,
+        denominator: U256,
+    ) -> &mut Self {
+        self.arith_imm(ArithOp::Mul, data_offset, data_offset, numerator)
+            .arith_imm(ArithOp::Div, data_offset, data_offset, denominator)
+    }
+
+    /// Records the executor's balance of `token`, or of ETH for
+    /// `Address::ZERO`, as the baseline of a later
+    /// [`require_profit`](Self::require_profit). Snapshotting a token again
+    /// replaces its baseline.
+    ///
+    /// The snapshot lives in the current flow only, so take it in the same
+    /// flow that checks it, e.g. around a flash loan call rather than inside
+    /// its callback.
+    pub fn begin_profit_check(&mut self, token: Address) -> &mut Self {
+        self.actions.push(Action::Snapshot { token });
         self
     }
 
-    /// Single-pass peephole optimizer.  Marks redundant actions, then removes
-    /// them via `retain` in O(n).
-    fn peephole_opt(&mut self) {
-        let mut last_value = U256::ZERO;
-        let mut last_target = Address::ZERO;
-        let mut last_data: Vec<u8> = Vec::new();
-        let mut last_fail = false;
-
-        let mut keep = vec![true; self.actions.len()];
-
-        for (idx, action) in self.actions.iter().enumerate() {
-            let redundant = match action {
-                Action::SetFail => {
-                    if last_fail {
-                        true
-                    } else {
-                        last_fail = true;
-                        false
-                    }
-                }
-                Action::ClearFail => {
-                    if last_fail {
-                        last_fail = false;
-                        false
-                    } else {
-                        true
-                    }
-                }
-                Action::Call => {
-                    last_value = U256::ZERO;
-                    false
-                }
-                Action::Create { created_address } => {
-                    last_target = *created_address;
-                    last_value = U256::ZERO;
-                    false
-                }
-                Action::SetAddr { addr } => {
-                    let res = last_target == *addr;
-                    last_target = *addr;
-                    res
-                }
-                Action::SetValue { value } => {
-                    let res = last_value == *value;
-                    last_value = *value;
-                    res
-                }
-                Action::ClearData { size } => {
-                    let res = last_data.len() == *size as usize;
-                    last_data = vec![0; *size as usize];
-                    res
-                }
-                Action::SetData { offset, data } => {
-                    let offset_uz = *offset as usize;
-                    let mut new_data = last_data.clone();
-                    new_data.splice(offset_uz..offset_uz + data.len(), data.to_owned());
-                    let res = last_data == new_data;
-                    last_data = new_data;
-                    res
-                }
-                _ => false,
-            };
-            if redundant {
-                keep[idx] = false;
-            }
+    /// Reverts the flow with `InsufficientProfit` unless the executor's
+    /// balance of `token` grew by at least `min_profit` since
+    /// [`begin_profit_check`](Self::begin_profit_check), typically as the
+    /// last action of a flow. A `min_profit` of zero only rejects losses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the flow has no snapshot of `token`; see
+    /// [`try_require_profit`](Self::try_require_profit).
+    pub fn require_profit(&mut self, token: Address, min_profit: U256) -> &mut Self {
+        self.try_require_profit(token, min_profit)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`require_profit`](Self::require_profit).
+    pub fn try_require_profit(
+        &mut self,
+        token: Address,
+        min_profit: U256,
+    ) -> Result<&mut Self, FlowError> {
+        if !self
+            .actions
+            .iter()
+            .any(|a| matches!(a, Action::Snapshot { token: t } if *t == token))
+        {
+            return Err(FlowError::MissingSnapshot { token });
+        }
+        self.actions.push(Action::CheckProfit { token, min_profit });
+        Ok(self)
+    }
+
+    /// Tips the block builder: sends `payment` to `block.coinbase`, whoever
+    /// builds the block, reverting the flow if the transfer fails. Pair a
+    /// [`CoinbasePayment::BalanceBps`] share with a preceding profit check to
+    /// pay out part of what the flow made.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a share exceeds [`CoinbasePayment::MAX_BPS`]; see
+    /// [`try_pay_coinbase`](Self::try_pay_coinbase).
+    pub fn pay_coinbase(&mut self, payment: CoinbasePayment) -> &mut Self {
+        self.try_pay_coinbase(payment)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`pay_coinbase`](Self::pay_coinbase).
+    pub fn try_pay_coinbase(&mut self, payment: CoinbasePayment) -> Result<&mut Self, FlowError> {
+        FlowError::check_payment(payment)?;
+        self.actions.push(Action::PayCoinbase { payment });
+        Ok(self)
+    }
+
+    /// Emits a `Checkpoint` event tagged `tag` with the `size` bytes of the
+    /// buffer at `data_offset`, e.g. an amount written by
+    /// [`write_token_balance`](Self::write_token_balance) or
+    /// [`copy_return`](Self::copy_return). Read the events back from the
+    /// receipt with [`decode_checkpoints`](crate::checkpoint::decode_checkpoints).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the slice does not fit the buffer sized by the last
+    /// `CLEARDATA`; see [`try_log`](Self::try_log).
+    pub fn log(&mut self, tag: B256, data_offset: u16, size: u16) -> &mut Self {
+        self.try_log(tag, data_offset, size)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`log`](Self::log).
+    pub fn try_log(
+        &mut self,
+        tag: B256,
+        data_offset: u16,
+        size: u16,
+    ) -> Result<&mut Self, FlowError> {
+        FlowError::check_log(data_offset, size, self.buffer_size())?;
+        self.actions.push(Action::Log {
+            tag,
+            data_offset,
+            size,
+        });
+        Ok(self)
+    }
+
+    /// Sends the executor's whole balance of each of `tokens`, then of ETH,
+    /// to `recipient`, e.g. the owner at the end of a flow. Balances are read
+    /// when the flow runs, so nothing is left behind; a zero balance is
+    /// skipped.
+    pub fn sweep(&mut self, tokens: &[Address], recipient: Address) -> &mut Self {
+        for token in tokens.iter().filter(|token| !token.is_zero()) {
+            self.sweep_op(*token, recipient);
+        }
+        self.sweep_op(Address::ZERO, recipient)
+    }
+
+    /// Adds a single `SWEEP` of `token`, or of ETH for `Address::ZERO`.
+    pub fn sweep_op(&mut self, token: Address, recipient: Address) -> &mut Self {
+        self.actions.push(Action::Sweep { token, recipient });
+        self
+    }
+
+    /// Copies the 32-byte word at `data_offset` into `register`, where it
+    /// survives the `CLEARDATA` of later calls, e.g. to keep an amount
+    /// returned by one call for a call further down the flow.
+    ///
+    /// Registers live in the current flow only: a flash loan callback runs
+    /// its flow with all sixteen registers at zero, and what it stores there
+    /// is gone once it returns, so store and load in the same flow.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the register does not exist or the word does not fit the
+    /// buffer sized by the last `CLEARDATA`; see
+    /// [`try_store_register`](Self::try_store_register).
+    pub fn store_register(&mut self, register: u8, data_offset: u16) -> &mut Self {
+        self.try_store_register(register, data_offset)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`store_register`](Self::store_register).
+    pub fn try_store_register(
+        &mut self,
+        register: u8,
+        data_offset: u16,
+    ) -> Result<&mut Self, FlowError> {
+        FlowError::check_register(register, data_offset, self.buffer_size())?;
+        self.actions.push(Action::StoreReg {
+            register,
+            data_offset,
+        });
+        Ok(self)
+    }
+
+    /// Writes `register` to the 32-byte word at `data_offset`. Like
+    /// [`copy_return`](Self::copy_return), use it between the `SETDATA` and
+    /// the `CALL` of a call to patch the value into its calldata.
+    ///
+    /// A register not yet stored to in the current flow holds zero; each
+    /// flash loan callback flow starts with fresh registers, as with
+    /// [`store_register`](Self::store_register).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the register does not exist or the word does not fit the
+    /// buffer sized by the last `CLEARDATA`; see
+    /// [`try_load_register`](Self::try_load_register).
+    pub fn load_register(&mut self, register: u8, data_offset: u16) -> &mut Self {
+        self.try_load_register(register, data_offset)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`load_register`](Self::load_register).
+    pub fn try_load_register(
+        &mut self,
+        register: u8,
+        data_offset: u16,
+    ) -> Result<&mut Self, FlowError> {
+        FlowError::check_register(register, data_offset, self.buffer_size())?;
+        self.actions.push(Action::LoadReg {
+            register,
+            data_offset,
+        });
+        Ok(self)
+    }
+
+    /// Keeps the return data word at `ret_offset` of the last call in
+    /// `register`, e.g. the output amount of a swap. Reuses the data buffer,
+    /// which the next high-level call resets anyway.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the register does not exist.
+    pub fn store_return_word(&mut self, register: u8, ret_offset: u16) -> &mut Self {
+        self.set_cleardata_op(32)
+            .copy_return(ret_offset, 0, 32)
+            .store_register(register, 0)
+    }
+
+    /// Guards the flow against landing late: it reverts once `block.number`
+    /// is past `max`, e.g. `require_block(n + 2)` for a flow built against
+    /// block `n`. Unlike the other helpers this inserts its action at the
+    /// start of the stream, so a stale flow reverts before doing anything.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the flow already jumps to a raw [`JumpDest::Offset`], which
+    /// the inserted action would shift; see
+    /// [`try_require_block`](Self::try_require_block). Jumps to labels are
+    /// resolved by [`build`](Self::build) and stay correct.
+    pub fn require_block(&mut self, max: u64) -> &mut Self {
+        self.try_require_block(max)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`require_block`](Self::require_block).
+    pub fn try_require_block(&mut self, max: u64) -> Result<&mut Self, FlowError> {
+        self.insert_guard(Action::MaxBlock {
+            number: U256::from(max),
+        })
+    }
+
+    /// Like [`require_block`](Self::require_block), but reverts once
+    /// `block.timestamp` is past `timestamp`.
+    pub fn require_deadline(&mut self, timestamp: u64) -> &mut Self {
+        self.try_require_deadline(timestamp)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`require_deadline`](Self::require_deadline).
+    pub fn try_require_deadline(&mut self, timestamp: u64) -> Result<&mut Self, FlowError> {
+        self.insert_guard(Action::Deadline {
+            timestamp: U256::from(timestamp),
+        })
+    }
+
+    /// Like [`require_block`](Self::require_block), but reverts while
+    /// `block.basefee` is above `fee` wei.
+    pub fn require_max_basefee(&mut self, fee: U256) -> &mut Self {
+        self.try_require_max_basefee(fee)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`require_max_basefee`](Self::require_max_basefee).
+    pub fn try_require_max_basefee(&mut self, fee: U256) -> Result<&mut Self, FlowError> {
+        self.insert_guard(Action::MaxBaseFee { fee })
+    }
+
+    fn insert_guard(&mut self, guard: Action) -> Result<&mut Self, FlowError> {
+        let shifted = self.actions.iter().find_map(|a| match a.jump_dest() {
+            Some(JumpDest::Offset(offset)) => Some(*offset),
+            _ => None,
+        });
+        if let Some(offset) = shifted {
+            return Err(FlowError::ShiftedJump { offset });
         }
+        self.actions.insert(0, guard);
+        Ok(self)
+    }
+
+    /// Defines label `name` at the current position, as a target for
+    /// [`jump`](Self::jump) and the conditional jumps.
+    ///
+    /// Labels may be jumped to before they are defined; they are resolved to
+    /// byte offsets by [`build`](Self::build).
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        self.actions.push(Action::Label {
+            name: name.to_owned(),
+        });
+        self
+    }
+
+    /// Continues execution at `label`.
+    pub fn jump(&mut self, label: &str) -> &mut Self {
+        self.actions.push(Action::Jump { dest: label.into() });
+        self
+    }
 
+    /// Continues execution at `label` if the last `CALL`, `DELEGATECALL`,
+    /// `STATICCALL`, `CREATE` or `CREATE2` failed, e.g. to fall back to
+    /// another route.
+    /// The call must run with the fail flag cleared (see
+    /// [`clear_fail`](Self::clear_fail)), otherwise its failure reverts the
+    /// flow before the jump is reached.
+    pub fn jump_if_failed(&mut self, label: &str) -> &mut Self {
+        self.actions
+            .push(Action::JumpIfFailed { dest: label.into() });
+        self
+    }
+
+    /// Continues execution at `label` if the 32-byte word at `data_offset`
+    /// of the data buffer satisfies `word <cmp> value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the word does not fit the buffer sized by the last
+    /// `CLEARDATA`; see [`try_jump_if_word`](Self::try_jump_if_word).
+    pub fn jump_if_word(
+        &mut self,
+        data_offset: u16,
+        cmp: Cmp,
+        value: U256,
+        label: &str,
+    ) -> &mut Self {
+        self.try_jump_if_word(data_offset, cmp, value, label)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`jump_if_word`](Self::jump_if_word).
+    pub fn try_jump_if_word(
+        &mut self,
+        data_offset: u16,
+        cmp: Cmp,
+        value: U256,
+        label: &str,
+    ) -> Result<&mut Self, FlowError> {
+        FlowError::check_word(data_offset, self.buffer_size())?;
+        self.actions.push(Action::JumpIfWord {
+            data_offset,
+            cmp,
+            value,
+            dest: label.into(),
+        });
+        Ok(self)
+    }
+
+    // -- Optimizer ---------------------------------------------------------
+
+    /// Runs the peephole optimizer to remove redundant operations.
+    ///
+    /// In debug builds the result is checked against the original flow with
+    /// [`check_equivalence`](crate::optimizer::check_equivalence).
+    pub fn optimize(&mut self) -> &mut Self {
+        #[cfg(debug_assertions)]
+        let original = self.actions.clone();
+
+        let keep = optimizer::peephole(&self.actions);
         let mut i = 0;
         self.actions.retain(|_| {
             let k = keep[i];
             i += 1;
             k
         });
+
+        #[cfg(debug_assertions)]
+        if let Err(e) = optimizer::check_equivalence(&original, &self.actions) {
+            panic!("optimizer changed the flow's behaviour: {e}");
+        }
+        self
     }
 
     // -- Encoding ----------------------------------------------------------
 
+    /// Decodes executor bytecode back into a `FlowBuilder`.
+    ///
+    /// Accepts both [`build`](Self::build) and [`build_raw`](Self::build_raw)
+    /// output; see [`crate::decoder::decode`].
+    pub fn from_bytecode(bytecode: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Self {
+            actions: decode(bytecode)?,
+            executor: None,
+        })
+    }
+
     /// Encodes the action list into raw bytecode (no function selector).
+    ///
+    /// # Panics
+    ///
+    /// Panics if a `SETDATA` payload does not fit its size operand or a jump
+    /// label is undefined, duplicated or out of range; see
+    /// [`try_build_raw`](Self::try_build_raw).
     pub fn build_raw(&self) -> Vec<u8> {
-        self.actions.iter().flat_map(|a| a.encode()).collect()
+        encode_actions(&self.actions).unwrap_or_else(|e| panic!("{e}"))
     }
 
     /// Encodes the action list into calldata for `executeActions()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a `SETDATA` payload does not fit its size operand or a jump
+    /// label is undefined, duplicated or out of range; see
+    /// [`try_build`](Self::try_build).
     pub fn build(&self) -> Vec<u8> {
         let mut res = EXECUTE_ACTIONS_SELECTOR.to_vec();
         res.extend(self.build_raw());
         res
     }
+
+    /// Like [`build_raw`](Self::build_raw), but first runs
+    /// [`validate`](Self::validate) and returns the first problem found, so
+    /// the result can never write outside the executor's data buffer.
+    pub fn try_build_raw(&self) -> Result<Vec<u8>, FlowError> {
+        if let Err(errors) = self.validate() {
+            return Err(errors[0].error.clone());
+        }
+        Ok(self.build_raw())
+    }
+
+    /// Fallible version of [`build`](Self::build); see
+    /// [`try_build_raw`](Self::try_build_raw).
+    pub fn try_build(&self) -> Result<Vec<u8>, FlowError> {
+        let mut res = EXECUTE_ACTIONS_SELECTOR.to_vec();
+        res.extend(self.try_build_raw()?);
+        Ok(res)
+    }
+}
+
+impl From<Vec<Action>> for FlowBuilder {
+    fn from(actions: Vec<Action>) -> Self {
+        Self {
+            actions,
+            executor: None,
+        }
+    }
+}
+
+impl From<FlowBuilder> for Vec<Action> {
+    fn from(fb: FlowBuilder) -> Self {
+        fb.actions
+    }
+}
+
+impl FromIterator<Action> for FlowBuilder {
+    fn from_iter<T: IntoIterator<Item = Action>>(iter: T) -> Self {
+        Self {
+            actions: iter.into_iter().collect(),
+            executor: None,
+        }
+    }
+}
+
+impl Extend<Action> for FlowBuilder {
+    fn extend<T: IntoIterator<Item = Action>>(&mut self, iter: T) {
+        self.actions.extend(iter);
+    }
+}
+
+impl IntoIterator for FlowBuilder {
+    type Item = Action;
+    type IntoIter = std::vec::IntoIter<Action>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.actions.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a FlowBuilder {
+    type Item = &'a Action;
+    type IntoIter = std::slice::Iter<'a, Action>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.actions.iter()
+    }
+}
+
+impl Index<usize> for FlowBuilder {
+    type Output = Action;
+
+    fn index(&self, index: usize) -> &Action {
+        &self.actions[index]
+    }
+}
+
+impl IndexMut<usize> for FlowBuilder {
+    fn index_mut(&mut self, index: usize) -> &mut Action {
+        &mut self.actions[index]
+    }
 }
@@ -0,0 +1,1115 @@
+//! Pure-Rust reference interpreter of the executor contract.
+//!
+//! [`Interpreter`] runs executor calldata exactly like
+//! `executor._executeActions`, without an EVM: it keeps the same `txData`
+//! buffer, registers, target, value, gas cap and fail flag, resets the value
+//! after `CALL` and `CREATE` and the gas cap after every call, and honours
+//! the callback address for flash-loan re-entry. Every interaction with the
+//! outside world is answered by a [`Responder`], so strategies and nested
+//! callback flows can be unit-tested deterministically. Gas is not metered:
+//! a responder sees each request's gas cap and decides whether it suffices.
+
+use std::fmt;
+
+use alloy_primitives::{keccak256, Address, B256, U256};
+
+use crate::flow_builder::{ArithOp, Cmp, CoinbasePayment, REGISTER_COUNT};
+use crate::opcodes::*;
+use crate::revert::{decode_revert, RevertReason};
+
+/// An external interaction requested by the executor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request<'a> {
+    Call {
+        target: Address,
+        value: U256,
+        /// Gas cap set by `SETGAS`; `None` forwards all remaining gas.
+        gas: Option<u32>,
+        calldata: &'a [u8],
+    },
+    DelegateCall {
+        target: Address,
+        gas: Option<u32>,
+        calldata: &'a [u8],
+    },
+    /// A `CREATE` or `CREATE2`.
+    Create {
+        /// Address the contract will be deployed at.
+        address: Address,
+        value: U256,
+        initcode: &'a [u8],
+    },
+    /// A read-only call: a `STATICCALL`, or the `balanceOf` query of
+    /// `TOKENBALANCE`, `SNAPSHOT` or `CHECKPROFIT`, which is not recorded as
+    /// an [`Effect`].
+    StaticCall {
+        target: Address,
+        gas: Option<u32>,
+        calldata: &'a [u8],
+    },
+}
+
+/// A call back into the executor made by the callee while handling a
+/// [`Request`], e.g. a lender invoking `onMorphoFlashLoan`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reentry {
+    /// `msg.sender` of the call back into the executor.
+    pub sender: Address,
+    pub calldata: Vec<u8>,
+}
+
+/// How a mocked callee handles a [`Request`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub success: bool,
+    pub return_data: Vec<u8>,
+    /// Calls made back into the executor before returning. If any of them
+    /// reverts, the request fails with its revert data.
+    pub reentries: Vec<Reentry>,
+}
+
+impl Response {
+    /// A successful response with no return data.
+    pub fn success() -> Self {
+        Self {
+            success: true,
+            return_data: Vec::new(),
+            reentries: Vec::new(),
+        }
+    }
+
+    /// A failed (reverted) response.
+    pub fn failure() -> Self {
+        Self {
+            success: false,
+            ..Self::success()
+        }
+    }
+
+    /// Sets the return data.
+    pub fn with_return_data(mut self, return_data: impl Into<Vec<u8>>) -> Self {
+        self.return_data = return_data.into();
+        self
+    }
+
+    /// Adds a call back into the executor from `sender`.
+    pub fn reenter(mut self, sender: Address, calldata: impl Into<Vec<u8>>) -> Self {
+        self.reentries.push(Reentry {
+            sender,
+            calldata: calldata.into(),
+        });
+        self
+    }
+}
+
+/// Mock of everything outside the executor.
+///
+/// Implemented for any `FnMut(&Request) -> Response` closure.
+pub trait Responder {
+    /// Handles a `CALL`, `DELEGATECALL`, `CREATE` or static call.
+    fn respond(&mut self, request: &Request<'_>) -> Response;
+
+    /// Returns the ETH balance of `address`, used by `SELFBALANCE` and the
+    /// ETH profit checks.
+    fn balance(&mut self, _address: Address) -> U256 {
+        U256::ZERO
+    }
+
+    /// Returns the code of `address`, used by `EXTCODECOPY`.
+    fn code(&mut self, _address: Address) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+impl<F> Responder for F
+where
+    F: FnMut(&Request<'_>) -> Response,
+{
+    fn respond(&mut self, request: &Request<'_>) -> Response {
+        self(request)
+    }
+}
+
+/// An observable effect of running a flow, in execution order.
+///
+/// `depth` is 0 for the top-level flow and increases by one for each nested
+/// callback flow. Effects of calls that ended up reverting are discarded,
+/// as they would be on-chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Effect {
+    Call {
+        depth: usize,
+        target: Address,
+        value: U256,
+        /// Gas cap set by `SETGAS`; `None` forwards all remaining gas.
+        gas: Option<u32>,
+        calldata: Vec<u8>,
+        success: bool,
+    },
+    DelegateCall {
+        depth: usize,
+        target: Address,
+        gas: Option<u32>,
+        calldata: Vec<u8>,
+        success: bool,
+    },
+    StaticCall {
+        depth: usize,
+        target: Address,
+        gas: Option<u32>,
+        calldata: Vec<u8>,
+        success: bool,
+    },
+    /// A `CREATE` or `CREATE2`.
+    Create {
+        depth: usize,
+        /// `None` if the deployment failed.
+        address: Option<Address>,
+        value: U256,
+        initcode: Vec<u8>,
+    },
+    /// `SETCALLBACK` registered `address` as the allowed callback sender.
+    SetCallback { depth: usize, address: Address },
+    /// `sender` entered a nested flow through a flash-loan callback.
+    Callback { depth: usize, sender: Address },
+    /// `LOG` emitted a `Checkpoint` event.
+    Log {
+        depth: usize,
+        tag: B256,
+        data: Vec<u8>,
+    },
+}
+
+/// Why the executor reverted.
+///
+/// Offsets are relative to the start of the action stream being executed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Revert {
+    /// A `CALL`, `DELEGATECALL`, `STATICCALL`, `CREATE` or `CREATE2` failed
+    /// with `SETFAIL` on, or a `PAYCOINBASE` transfer failed
+    /// (`ActionFailed`). `reason` is the call's or init code's revert data,
+    /// see [`decode_revert`].
+    ActionFailed { offset: usize, reason: Vec<u8> },
+    /// The executor tried to change state while re-entered from inside a
+    /// `STATICCALL`, e.g. a callback clearing its callback slot. The EVM
+    /// halts the executor's frame.
+    WriteProtection,
+    /// A callback arrived from an address other than the registered one.
+    InvalidCallbackSender { sender: Address },
+    /// A `COPYRETURN` read past the end of the return data
+    /// (`"RETURNDATA_OOB"`).
+    ReturnDataOutOfBounds { offset: usize },
+    /// The `balanceOf` query of a `TOKENBALANCE` failed or returned less
+    /// than a word.
+    BalanceQueryFailed { offset: usize },
+    /// A `JUMPIFWORD` with an unknown comparison operand (`"BAD_CMP"`).
+    InvalidComparison { offset: usize },
+    /// An `ARITH` or `ARITHI` with an unknown operator (`"BAD_ARITH"`).
+    InvalidArithOp { offset: usize },
+    /// A `PAYCOINBASE` with an unknown mode or a share above 10,000 bps
+    /// (`"BAD_PAYMENT"`).
+    InvalidPayment { offset: usize },
+    /// A `MAXBLOCK` ran past its block (`"BLOCK_EXPIRED"`).
+    BlockExpired { offset: usize },
+    /// A `DEADLINE` ran past its timestamp (`"DEADLINE_EXPIRED"`).
+    DeadlineExpired { offset: usize },
+    /// A `MAXBASEFEE` ran above its base fee (`"BASEFEE_TOO_HIGH"`).
+    BaseFeeTooHigh { offset: usize },
+    /// A `SETVALUEBAL` found the ETH balance below its reserve
+    /// (`"BALANCE_BELOW_RESERVE"`).
+    BalanceBelowReserve { offset: usize },
+    /// An action read or wrote past the end of `txData` (`"TXDATA_OOB"`).
+    TxDataOutOfBounds { offset: usize },
+    /// A Solidity panic: `0x11` for an arithmetic overflow or underflow,
+    /// `0x12` for a division by zero, `0x21` for an unknown opcode, `0x32`
+    /// for an out-of-bounds read of the action stream or register index.
+    Panic { offset: usize, code: u8 },
+    /// A flow ran more than the interpreter's step limit, e.g. a jump loop
+    /// that never exits. On chain it would run out of gas.
+    StepLimitExceeded { offset: usize },
+    /// A `CHECKPROFIT` of a token the flow never snapshotted
+    /// (`"NO_SNAPSHOT"`).
+    MissingSnapshot { offset: usize },
+    /// A `CHECKPROFIT` found the balance of `token` (`Address::ZERO` for
+    /// ETH) grew by less than `min_profit` (`InsufficientProfit`).
+    InsufficientProfit {
+        offset: usize,
+        token: Address,
+        balance_before: U256,
+        balance_after: U256,
+        min_profit: U256,
+    },
+}
+
+impl fmt::Display for Revert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Revert::ActionFailed { offset, reason } => {
+                write!(
+                    f,
+                    "ActionFailed at offset {offset}: {}",
+                    decode_revert(reason)
+                )
+            }
+            Revert::WriteProtection => write!(f, "state change inside a static call"),
+            Revert::InvalidCallbackSender { sender } => {
+                write!(f, "Invalid callback sender {sender}")
+            }
+            Revert::ReturnDataOutOfBounds { offset } => {
+                write!(f, "RETURNDATA_OOB at offset {offset}")
+            }
+            Revert::BalanceQueryFailed { offset } => {
+                write!(f, "balanceOf query failed at offset {offset}")
+            }
+            Revert::InvalidComparison { offset } => write!(f, "BAD_CMP at offset {offset}"),
+            Revert::InvalidArithOp { offset } => write!(f, "BAD_ARITH at offset {offset}"),
+            Revert::InvalidPayment { offset } => write!(f, "BAD_PAYMENT at offset {offset}"),
+            Revert::BlockExpired { offset } => write!(f, "BLOCK_EXPIRED at offset {offset}"),
+            Revert::DeadlineExpired { offset } => {
+                write!(f, "DEADLINE_EXPIRED at offset {offset}")
+            }
+            Revert::BaseFeeTooHigh { offset } => {
+                write!(f, "BASEFEE_TOO_HIGH at offset {offset}")
+            }
+            Revert::BalanceBelowReserve { offset } => {
+                write!(f, "BALANCE_BELOW_RESERVE at offset {offset}")
+            }
+            Revert::TxDataOutOfBounds { offset } => {
+                write!(f, "TXDATA_OOB at offset {offset}")
+            }
+            Revert::Panic { offset, code } => {
+                write!(f, "panic 0x{code:02x} at offset {offset}")
+            }
+            Revert::StepLimitExceeded { offset } => {
+                write!(f, "step limit exceeded at offset {offset}")
+            }
+            Revert::MissingSnapshot { offset } => write!(f, "NO_SNAPSHOT at offset {offset}"),
+            Revert::InsufficientProfit {
+                offset,
+                token,
+                balance_before,
+                balance_after,
+                min_profit,
+            } => write!(
+                f,
+                "InsufficientProfit at offset {offset}: balance of {token} went from \
+                 {balance_before} to {balance_after}, required a profit of {min_profit}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Revert {}
+
+impl Revert {
+    /// The revert data the executor produces, decoded. Halts (e.g. running
+    /// out of gas) and reverts that bubble up an empty call failure have
+    /// none.
+    pub fn reason(&self) -> RevertReason {
+        let error = |message: &str| RevertReason::Error(message.to_owned());
+        match self {
+            Revert::ActionFailed { offset, reason } => RevertReason::ActionFailed {
+                offset: *offset,
+                reason: Box::new(decode_revert(reason)),
+            },
+            Revert::InvalidCallbackSender { .. } => error("Invalid callback sender"),
+            Revert::ReturnDataOutOfBounds { .. } => error("RETURNDATA_OOB"),
+            Revert::InvalidComparison { .. } => error("BAD_CMP"),
+            Revert::InvalidArithOp { .. } => error("BAD_ARITH"),
+            Revert::InvalidPayment { .. } => error("BAD_PAYMENT"),
+            Revert::BlockExpired { .. } => error("BLOCK_EXPIRED"),
+            Revert::DeadlineExpired { .. } => error("DEADLINE_EXPIRED"),
+            Revert::BaseFeeTooHigh { .. } => error("BASEFEE_TOO_HIGH"),
+            Revert::BalanceBelowReserve { .. } => error("BALANCE_BELOW_RESERVE"),
+            Revert::TxDataOutOfBounds { .. } => error("TXDATA_OOB"),
+            Revert::MissingSnapshot { .. } => error("NO_SNAPSHOT"),
+            Revert::Panic { code, .. } => RevertReason::Panic(U256::from(*code)),
+            Revert::InsufficientProfit {
+                token,
+                balance_before,
+                balance_after,
+                min_profit,
+                ..
+            } => RevertReason::InsufficientProfit {
+                token: *token,
+                balance_before: *balance_before,
+                balance_after: *balance_after,
+                min_profit: *min_profit,
+            },
+            Revert::WriteProtection
+            | Revert::BalanceQueryFailed { .. }
+            | Revert::StepLimitExceeded { .. } => RevertReason::Empty,
+        }
+    }
+}
+
+/// Per-invocation state of `_executeActions`.
+struct Frame {
+    target: Address,
+    value: U256,
+    /// Gas cap of the next call, 0 for none.
+    gas: u32,
+    fail: bool,
+    /// Outcome of the last `CALL`, `DELEGATECALL` or `CREATE`.
+    success: bool,
+    tx_data: Vec<u8>,
+    /// Return data of the last `CALL`, `DELEGATECALL` or `CREATE`.
+    return_data: Vec<u8>,
+    /// Balances recorded by `SNAPSHOT`, oldest first.
+    snapshots: Vec<(Address, U256)>,
+    registers: [U256; REGISTER_COUNT as usize],
+}
+
+/// Default number of actions a single flow may run; see
+/// [`Interpreter::with_step_limit`].
+pub const DEFAULT_STEP_LIMIT: usize = 100_000;
+
+/// Reference interpreter for executor calldata.
+pub struct Interpreter<R> {
+    executor: Address,
+    coinbase: Address,
+    block_number: U256,
+    timestamp: U256,
+    basefee: U256,
+    nonce: u64,
+    callback: Address,
+    responder: R,
+    effects: Vec<Effect>,
+    step_limit: usize,
+    /// Set while re-entries of a `STATICCALL` run.
+    read_only: bool,
+}
+
+impl<R: Responder> Interpreter<R> {
+    /// Creates an interpreter for an executor deployed at `executor`, with
+    /// account nonce 1, no callback registered and a zero `block.coinbase`,
+    /// `block.number`, `block.timestamp` and `block.basefee`.
+    pub fn new(executor: Address, responder: R) -> Self {
+        Self {
+            executor,
+            coinbase: Address::ZERO,
+            block_number: U256::ZERO,
+            timestamp: U256::ZERO,
+            basefee: U256::ZERO,
+            nonce: 1,
+            callback: Address::ZERO,
+            responder,
+            effects: Vec::new(),
+            step_limit: DEFAULT_STEP_LIMIT,
+            read_only: false,
+        }
+    }
+
+    /// Sets the executor's account nonce, which determines `CREATE` addresses.
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    /// Sets `block.coinbase`, the recipient of `PAYCOINBASE`.
+    pub fn with_coinbase(mut self, coinbase: Address) -> Self {
+        self.coinbase = coinbase;
+        self
+    }
+
+    /// Sets `block.number`, checked by `MAXBLOCK`.
+    pub fn with_block_number(mut self, number: u64) -> Self {
+        self.block_number = U256::from(number);
+        self
+    }
+
+    /// Sets `block.timestamp`, checked by `DEADLINE`.
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = U256::from(timestamp);
+        self
+    }
+
+    /// Sets `block.basefee`, checked by `MAXBASEFEE`.
+    pub fn with_basefee(mut self, basefee: U256) -> Self {
+        self.basefee = basefee;
+        self
+    }
+
+    /// Sets how many actions a single flow (top-level or callback) may run
+    /// before reverting with [`Revert::StepLimitExceeded`], standing in for
+    /// the gas limit that stops runaway jump loops on chain.
+    pub fn with_step_limit(mut self, step_limit: usize) -> Self {
+        self.step_limit = step_limit;
+        self
+    }
+
+    /// Returns the currently registered callback address.
+    pub fn callback(&self) -> Address {
+        self.callback
+    }
+
+    /// Returns the responder, e.g. to inspect a mock's recorded state.
+    pub fn responder(&mut self) -> &mut R {
+        &mut self.responder
+    }
+
+    /// Runs a transaction to the executor with the given calldata, usually
+    /// the output of [`FlowBuilder::build`](crate::FlowBuilder::build).
+    ///
+    /// On revert, the callback slot and nonce are rolled back and the
+    /// reason is returned.
+    pub fn execute(&mut self, calldata: &[u8]) -> Result<Vec<Effect>, Revert> {
+        let (callback, nonce) = (self.callback, self.nonce);
+        self.effects.clear();
+        match self.enter(Address::ZERO, calldata, 0) {
+            Ok(()) => Ok(std::mem::take(&mut self.effects)),
+            Err(revert) => {
+                self.effects.clear();
+                self.callback = callback;
+                self.nonce = nonce;
+                Err(revert)
+            }
+        }
+    }
+
+    /// Dispatches a call into the executor on its function selector.
+    fn enter(&mut self, sender: Address, calldata: &[u8], depth: usize) -> Result<(), Revert> {
+        let Some(selector) = calldata.get(..4) else {
+            return Ok(()); // receive()/fallback()
+        };
+        if selector == selector_of("executeActions()") {
+            self.run(&calldata[4..], depth)
+        } else if selector == selector_of("onMorphoFlashLoan(uint256,bytes)") {
+            self.on_callback(sender, calldata, 100, depth)
+        } else if selector == selector_of("executeOperation(address,uint256,uint256,address,bytes)")
+        {
+            self.on_callback(sender, calldata, 196, depth)
+        } else {
+            Ok(()) // fallback()
+        }
+    }
+
+    /// Mirrors `_onCallback`: checks the sender, clears the callback slot and
+    /// runs the actions starting at `offset`.
+    fn on_callback(
+        &mut self,
+        sender: Address,
+        calldata: &[u8],
+        offset: usize,
+        depth: usize,
+    ) -> Result<(), Revert> {
+        if sender != self.callback {
+            return Err(Revert::InvalidCallbackSender { sender });
+        }
+        if self.read_only {
+            return Err(Revert::WriteProtection);
+        }
+        self.callback = Address::ZERO;
+        self.effects.push(Effect::Callback { depth, sender });
+        self.run(calldata.get(offset..).unwrap_or_default(), depth)
+    }
+
+    /// Mirrors `_executeActions` over a raw action stream.
+    fn run(&mut self, data: &[u8], depth: usize) -> Result<(), Revert> {
+        let mut frame = Frame {
+            target: Address::ZERO,
+            value: U256::ZERO,
+            gas: 0,
+            fail: false,
+            success: true,
+            tx_data: Vec::new(),
+            return_data: Vec::new(),
+            snapshots: Vec::new(),
+            registers: [U256::ZERO; REGISTER_COUNT as usize],
+        };
+        let mut offset = 0;
+        let mut steps = 0;
+
+        while offset < data.len() {
+            let at = offset;
+            steps += 1;
+            if steps > self.step_limit {
+                return Err(Revert::StepLimitExceeded { offset: at });
+            }
+            let op = data[offset];
+            offset += 1;
+            let panic = |code| Revert::Panic { offset: at, code };
+
+            match op {
+                OP_EOF => break,
+                OP_CLEARDATA | OP_CLEARDATA32 => {
+                    let wide = op == OP_CLEARDATA32;
+                    let size = read_size(data, &mut offset, wide).ok_or(panic(0x32))?;
+                    frame.tx_data = vec![0; size];
+                }
+                OP_SETDATA | OP_SETDATA32 => {
+                    let wide = op == OP_SETDATA32;
+                    let data_offset = read_size(data, &mut offset, wide).ok_or(panic(0x32))?;
+                    let data_size = read_size(data, &mut offset, wide).ok_or(panic(0x32))?;
+                    check_range(&frame.tx_data, data_offset, data_size, at)?;
+                    let dst = &mut frame.tx_data[data_offset..data_offset + data_size];
+                    for i in 0..data_size / 32 {
+                        let word = read_padded(data, &mut offset, 32);
+                        dst[i * 32..(i + 1) * 32].copy_from_slice(&word);
+                    }
+                    // The tail is copied with checked indexing of the stream.
+                    for byte in &mut dst[(data_size / 32) * 32..] {
+                        *byte = *data.get(offset).ok_or(panic(0x32))?;
+                        offset += 1;
+                    }
+                }
+                OP_SETADDR => frame.target = read_address(data, &mut offset),
+                OP_SETVALUE => {
+                    frame.value = U256::from_be_slice(&read_padded(data, &mut offset, 32))
+                }
+                OP_SETVALUEBAL => {
+                    let reserve = U256::from_be_slice(&read_padded(data, &mut offset, 32));
+                    frame.value = self
+                        .responder
+                        .balance(self.executor)
+                        .checked_sub(reserve)
+                        .ok_or(Revert::BalanceBelowReserve { offset: at })?;
+                }
+                OP_SETVALUEDATA => {
+                    let data_offset = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    frame.value = read_word(&frame.tx_data, data_offset, at)?;
+                }
+                OP_SETGAS => frame.gas = read_u32(data, &mut offset).ok_or(panic(0x32))?,
+                OP_EXTCODECOPY | OP_EXTCODECOPY32 => {
+                    let wide = op == OP_EXTCODECOPY32;
+                    let source = read_address(data, &mut offset);
+                    let data_offset = read_size(data, &mut offset, wide).ok_or(panic(0x32))?;
+                    let code_offset = read_size(data, &mut offset, wide).ok_or(panic(0x32))?;
+                    let size = read_size(data, &mut offset, wide).ok_or(panic(0x32))?;
+                    check_range(&frame.tx_data, data_offset, size, at)?;
+                    let code = self.responder.code(source);
+                    for (i, byte) in frame.tx_data[data_offset..data_offset + size]
+                        .iter_mut()
+                        .enumerate()
+                    {
+                        *byte = code.get(code_offset + i).copied().unwrap_or(0);
+                    }
+                }
+                OP_CALL => {
+                    let value = std::mem::take(&mut frame.value);
+                    let gas = take_gas(&mut frame.gas);
+                    if self.read_only && !value.is_zero() {
+                        return Err(Revert::WriteProtection);
+                    }
+                    let idx = self.effects.len();
+                    self.effects.push(Effect::Call {
+                        depth,
+                        target: frame.target,
+                        value,
+                        gas,
+                        calldata: frame.tx_data.clone(),
+                        success: false,
+                    });
+                    let request = Request::Call {
+                        target: frame.target,
+                        value,
+                        gas,
+                        calldata: &frame.tx_data,
+                    };
+                    let ok;
+                    (ok, frame.return_data) = self.dispatch(&request, depth);
+                    if let Effect::Call { success, .. } = &mut self.effects[idx] {
+                        *success = ok;
+                    }
+                    frame.success = ok;
+                    if frame.fail && !ok {
+                        return Err(Revert::ActionFailed {
+                            offset: at,
+                            reason: frame.return_data.clone(),
+                        });
+                    }
+                }
+                OP_CREATE | OP_CREATE2 => {
+                    let address = if op == OP_CREATE2 {
+                        let salt = B256::from_slice(&read_padded(data, &mut offset, 32));
+                        self.executor.create2_from_code(salt, &frame.tx_data)
+                    } else {
+                        create_address(self.executor, self.nonce)
+                    };
+                    if self.read_only {
+                        return Err(Revert::WriteProtection);
+                    }
+                    let value = std::mem::take(&mut frame.value);
+                    // CREATE2 bumps the nonce too
+                    self.nonce += 1;
+                    let idx = self.effects.len();
+                    self.effects.push(Effect::Create {
+                        depth,
+                        address: None,
+                        value,
+                        initcode: frame.tx_data.clone(),
+                    });
+                    let request = Request::Create {
+                        address,
+                        value,
+                        initcode: &frame.tx_data,
+                    };
+                    let ok;
+                    (ok, frame.return_data) = self.dispatch(&request, depth);
+                    // A successful create leaves no return data
+                    if ok {
+                        frame.return_data.clear();
+                    }
+                    frame.target = if ok { address } else { Address::ZERO };
+                    frame.success = ok;
+                    if let Effect::Create { address: a, .. } = &mut self.effects[idx] {
+                        *a = ok.then_some(address);
+                    }
+                    if frame.fail && !ok {
+                        return Err(Revert::ActionFailed {
+                            offset: at,
+                            reason: frame.return_data.clone(),
+                        });
+                    }
+                }
+                OP_DELEGATECALL => {
+                    let gas = take_gas(&mut frame.gas);
+                    let idx = self.effects.len();
+                    self.effects.push(Effect::DelegateCall {
+                        depth,
+                        target: frame.target,
+                        gas,
+                        calldata: frame.tx_data.clone(),
+                        success: false,
+                    });
+                    let request = Request::DelegateCall {
+                        target: frame.target,
+                        gas,
+                        calldata: &frame.tx_data,
+                    };
+                    let ok;
+                    (ok, frame.return_data) = self.dispatch(&request, depth);
+                    if let Effect::DelegateCall { success, .. } = &mut self.effects[idx] {
+                        *success = ok;
+                    }
+                    frame.success = ok;
+                    if frame.fail && !ok {
+                        return Err(Revert::ActionFailed {
+                            offset: at,
+                            reason: frame.return_data.clone(),
+                        });
+                    }
+                }
+                OP_SETCALLBACK => {
+                    if self.read_only {
+                        return Err(Revert::WriteProtection);
+                    }
+                    self.callback = read_address(data, &mut offset);
+                    self.effects.push(Effect::SetCallback {
+                        depth,
+                        address: self.callback,
+                    });
+                }
+                OP_STATICCALL => {
+                    let gas = take_gas(&mut frame.gas);
+                    let idx = self.effects.len();
+                    self.effects.push(Effect::StaticCall {
+                        depth,
+                        target: frame.target,
+                        gas,
+                        calldata: frame.tx_data.clone(),
+                        success: false,
+                    });
+                    let request = Request::StaticCall {
+                        target: frame.target,
+                        gas,
+                        calldata: &frame.tx_data,
+                    };
+                    let ok;
+                    (ok, frame.return_data) = self.dispatch(&request, depth);
+                    if let Effect::StaticCall { success, .. } = &mut self.effects[idx] {
+                        *success = ok;
+                    }
+                    frame.success = ok;
+                    if frame.fail && !ok {
+                        return Err(Revert::ActionFailed {
+                            offset: at,
+                            reason: frame.return_data.clone(),
+                        });
+                    }
+                }
+                OP_SETFAIL => frame.fail = true,
+                OP_CLEARFAIL => frame.fail = false,
+                OP_COPYRETURN => {
+                    let ret_offset = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    let data_offset = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    let size = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    let bytes = frame
+                        .return_data
+                        .get(ret_offset..ret_offset + size)
+                        .ok_or(Revert::ReturnDataOutOfBounds { offset: at })?;
+                    write_tx_data(&mut frame.tx_data, data_offset, bytes, at)?;
+                }
+                OP_SELFBALANCE => {
+                    let data_offset = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    let balance = self.responder.balance(self.executor);
+                    write_tx_data(
+                        &mut frame.tx_data,
+                        data_offset,
+                        &balance.to_be_bytes::<32>(),
+                        at,
+                    )?;
+                }
+                OP_TOKENBALANCE => {
+                    let token = read_address(data, &mut offset);
+                    let holder = read_address(data, &mut offset);
+                    let data_offset = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    // Checked before the balanceOf call, like the executor
+                    check_range(&frame.tx_data, data_offset, 32, at)?;
+                    let balance = self.token_balance(token, holder, &mut frame, depth, at)?;
+                    write_tx_data(
+                        &mut frame.tx_data,
+                        data_offset,
+                        &balance.to_be_bytes::<32>(),
+                        at,
+                    )?;
+                }
+                OP_JUMP => offset = read_u16(data, &mut offset).ok_or(panic(0x32))?,
+                OP_JUMPIFFAILED => {
+                    let dest = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    if !frame.success {
+                        offset = dest;
+                    }
+                }
+                OP_JUMPIFWORD => {
+                    let data_offset = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    let cmp = *data.get(offset).ok_or(panic(0x32))?;
+                    offset += 1;
+                    let value = U256::from_be_slice(&read_padded(data, &mut offset, 32));
+                    let dest = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    let word = read_word(&frame.tx_data, data_offset, at)?;
+                    let cmp = Cmp::from_u8(cmp).ok_or(Revert::InvalidComparison { offset: at })?;
+                    if cmp.eval(word, value) {
+                        offset = dest;
+                    }
+                }
+                OP_ARITH | OP_ARITHI => {
+                    let arith_op = *data.get(offset).ok_or(panic(0x32))?;
+                    offset += 1;
+                    let dst = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    let lhs = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    let rhs = if op == OP_ARITH {
+                        let rhs = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                        read_word(&frame.tx_data, rhs, at)?
+                    } else {
+                        U256::from_be_slice(&read_padded(data, &mut offset, 32))
+                    };
+                    let lhs = read_word(&frame.tx_data, lhs, at)?;
+                    let arith_op =
+                        ArithOp::from_u8(arith_op).ok_or(Revert::InvalidArithOp { offset: at })?;
+                    let result = arith_op.eval(lhs, rhs).ok_or(match arith_op {
+                        ArithOp::Div => panic(0x12),
+                        _ => panic(0x11),
+                    })?;
+                    write_tx_data(&mut frame.tx_data, dst, &result.to_be_bytes::<32>(), at)?;
+                }
+                OP_PAYCOINBASE => {
+                    let mode = *data.get(offset).ok_or(panic(0x32))?;
+                    offset += 1;
+                    let operand = U256::from_be_slice(&read_padded(data, &mut offset, 32));
+                    let payment = CoinbasePayment::from_parts(mode, operand)
+                        .ok_or(Revert::InvalidPayment { offset: at })?;
+                    let value = payment.amount(self.responder.balance(self.executor));
+                    if self.read_only && !value.is_zero() {
+                        return Err(Revert::WriteProtection);
+                    }
+                    if !self.transfer(self.coinbase, value, Vec::new(), &mut frame, depth) {
+                        return Err(Revert::ActionFailed {
+                            offset: at,
+                            reason: frame.return_data.clone(),
+                        });
+                    }
+                }
+                OP_STOREREG | OP_LOADREG => {
+                    let register = *data.get(offset).ok_or(panic(0x32))? as usize;
+                    offset += 1;
+                    let data_offset = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    // The word is checked before the register index
+                    check_range(&frame.tx_data, data_offset, 32, at)?;
+                    let slot = frame.registers.get_mut(register).ok_or(panic(0x32))?;
+                    if op == OP_STOREREG {
+                        *slot = read_word(&frame.tx_data, data_offset, at)?;
+                    } else {
+                        let word = slot.to_be_bytes::<32>();
+                        write_tx_data(&mut frame.tx_data, data_offset, &word, at)?;
+                    }
+                }
+                OP_SWEEP => {
+                    let token = read_address(data, &mut offset);
+                    let recipient = read_address(data, &mut offset);
+                    let amount = self.self_balance(token, &mut frame, depth, at)?;
+                    if !amount.is_zero() {
+                        if self.read_only {
+                            return Err(Revert::WriteProtection);
+                        }
+                        let sent = if token.is_zero() {
+                            self.transfer(recipient, amount, Vec::new(), &mut frame, depth)
+                        } else {
+                            let mut calldata = selector_of("transfer(address,uint256)").to_vec();
+                            calldata.extend_from_slice(recipient.into_word().as_slice());
+                            calldata.extend_from_slice(&amount.to_be_bytes::<32>());
+                            // Tokens that return nothing, like USDT, count as a
+                            // success, as with `SafeERC20`
+                            self.transfer(token, U256::ZERO, calldata, &mut frame, depth)
+                                && (frame.return_data.is_empty()
+                                    || frame.return_data.get(..32).map(U256::from_be_slice)
+                                        == Some(U256::from(1)))
+                        };
+                        if !sent {
+                            return Err(Revert::ActionFailed {
+                                offset: at,
+                                reason: frame.return_data.clone(),
+                            });
+                        }
+                    }
+                }
+                OP_MAXBLOCK | OP_DEADLINE | OP_MAXBASEFEE => {
+                    let bound = U256::from_be_slice(&read_padded(data, &mut offset, 32));
+                    let (value, revert) = match op {
+                        OP_MAXBLOCK => (self.block_number, Revert::BlockExpired { offset: at }),
+                        OP_DEADLINE => (self.timestamp, Revert::DeadlineExpired { offset: at }),
+                        _ => (self.basefee, Revert::BaseFeeTooHigh { offset: at }),
+                    };
+                    if value > bound {
+                        return Err(revert);
+                    }
+                }
+                OP_LOG => {
+                    let tag = B256::from_slice(&read_padded(data, &mut offset, 32));
+                    let data_offset = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    let size = read_u16(data, &mut offset).ok_or(panic(0x32))?;
+                    check_range(&frame.tx_data, data_offset, size, at)?;
+                    if self.read_only {
+                        return Err(Revert::WriteProtection);
+                    }
+                    let data = frame.tx_data[data_offset..data_offset + size].to_vec();
+                    self.effects.push(Effect::Log { depth, tag, data });
+                }
+                OP_SNAPSHOT => {
+                    let token = read_address(data, &mut offset);
+                    let balance = self.self_balance(token, &mut frame, depth, at)?;
+                    frame.snapshots.push((token, balance));
+                }
+                OP_CHECKPROFIT => {
+                    let token = read_address(data, &mut offset);
+                    let min_profit = U256::from_be_slice(&read_padded(data, &mut offset, 32));
+                    let balance_before = frame
+                        .snapshots
+                        .iter()
+                        .rev()
+                        .find(|(t, _)| *t == token)
+                        .map(|(_, balance)| *balance)
+                        .ok_or(Revert::MissingSnapshot { offset: at })?;
+                    let balance_after = self.self_balance(token, &mut frame, depth, at)?;
+                    if balance_after
+                        .checked_sub(balance_before)
+                        .is_none_or(|profit| profit < min_profit)
+                    {
+                        return Err(Revert::InsufficientProfit {
+                            offset: at,
+                            token,
+                            balance_before,
+                            balance_after,
+                            min_profit,
+                        });
+                    }
+                }
+                _ => return Err(panic(0x21)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Mirrors `IERC20Balance(token).balanceOf(holder)`, which replaces the
+    /// frame's return data.
+    fn token_balance(
+        &mut self,
+        token: Address,
+        holder: Address,
+        frame: &mut Frame,
+        depth: usize,
+        at: usize,
+    ) -> Result<U256, Revert> {
+        let mut calldata = selector_of("balanceOf(address)").to_vec();
+        calldata.extend_from_slice(holder.into_word().as_slice());
+        let request = Request::StaticCall {
+            target: token,
+            gas: None,
+            calldata: &calldata,
+        };
+        let ok;
+        (ok, frame.return_data) = self.dispatch(&request, depth);
+        frame
+            .return_data
+            .get(..32)
+            .filter(|_| ok)
+            .map(U256::from_be_slice)
+            .ok_or(Revert::BalanceQueryFailed { offset: at })
+    }
+
+    /// Mirrors `_selfBalance`: the executor's ETH balance for
+    /// `Address::ZERO`, its token balance otherwise.
+    fn self_balance(
+        &mut self,
+        token: Address,
+        frame: &mut Frame,
+        depth: usize,
+        at: usize,
+    ) -> Result<U256, Revert> {
+        if token.is_zero() {
+            Ok(self.responder.balance(self.executor))
+        } else {
+            self.token_balance(token, self.executor, frame, depth, at)
+        }
+    }
+
+    /// Makes a call the executor issues on its own, as `PAYCOINBASE` and
+    /// `SWEEP` do, without the frame's target, value, gas cap or fail flag.
+    /// Sets the return data and returns whether the call succeeded.
+    fn transfer(
+        &mut self,
+        target: Address,
+        value: U256,
+        calldata: Vec<u8>,
+        frame: &mut Frame,
+        depth: usize,
+    ) -> bool {
+        let idx = self.effects.len();
+        self.effects.push(Effect::Call {
+            depth,
+            target,
+            value,
+            gas: None,
+            calldata: calldata.clone(),
+            success: false,
+        });
+        let request = Request::Call {
+            target,
+            value,
+            gas: None,
+            calldata: &calldata,
+        };
+        let ok;
+        (ok, frame.return_data) = self.dispatch(&request, depth);
+        if let Effect::Call { success, .. } = &mut self.effects[idx] {
+            *success = ok;
+        }
+        ok
+    }
+
+    /// Asks the responder to handle `request` and runs any re-entries it
+    /// makes. Returns whether the request succeeded and its return data; if
+    /// a re-entry reverts, everything it did is rolled back and the request
+    /// fails with the re-entry's revert data, as a callee bubbling it up
+    /// would. Re-entries of a static call run read-only.
+    fn dispatch(&mut self, request: &Request<'_>, depth: usize) -> (bool, Vec<u8>) {
+        let response = self.responder.respond(request);
+        if !response.success {
+            return (false, response.return_data);
+        }
+        let (effects, callback, nonce) = (self.effects.len(), self.callback, self.nonce);
+        let read_only = self.read_only;
+        self.read_only |= matches!(request, Request::StaticCall { .. });
+        for reentry in &response.reentries {
+            if let Err(revert) = self.enter(reentry.sender, &reentry.calldata, depth + 1) {
+                self.effects.truncate(effects);
+                self.callback = callback;
+                self.nonce = nonce;
+                self.read_only = read_only;
+                return (false, revert.reason().encode());
+            }
+        }
+        self.read_only = read_only;
+        (true, response.return_data)
+    }
+}
+
+/// Address of a contract deployed by `deployer` with `CREATE` at `nonce`:
+/// `keccak256(rlp([deployer, nonce]))[12..]`.
+pub fn create_address(deployer: Address, nonce: u64) -> Address {
+    let nonce_bytes = nonce.to_be_bytes();
+    let nonce_bytes = &nonce_bytes[nonce.leading_zeros() as usize / 8..];
+    let mut rlp = vec![0, 0x94];
+    rlp.extend_from_slice(deployer.as_slice());
+    match nonce_bytes {
+        [] => rlp.push(0x80),
+        [b] if *b < 0x80 => rlp.push(*b),
+        _ => {
+            rlp.push(0x80 + nonce_bytes.len() as u8);
+            rlp.extend_from_slice(nonce_bytes);
+        }
+    }
+    rlp[0] = 0xc0 + (rlp.len() - 1) as u8;
+    Address::from_slice(&keccak256(&rlp)[12..])
+}
+
+pub(crate) fn selector_of(signature: &str) -> [u8; 4] {
+    keccak256(signature)[..4].try_into().unwrap()
+}
+
+/// Reads a `uint16` with the checked indexing of `_parseUint16`.
+fn read_u16(data: &[u8], offset: &mut usize) -> Option<usize> {
+    let hi = *data.get(*offset)?;
+    let lo = *data.get(*offset + 1)?;
+    *offset += 2;
+    Some(u16::from_be_bytes([hi, lo]) as usize)
+}
+
+/// Reads a `uint32` with the checked indexing of `_parseUint32`.
+fn read_u32(data: &[u8], offset: &mut usize) -> Option<u32> {
+    let bytes = data.get(*offset..*offset + 4)?;
+    *offset += 4;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Reads a size or offset like `_parseSize`: a `uint32` for the 32-bit
+/// opcode variants, a `uint16` otherwise.
+fn read_size(data: &[u8], offset: &mut usize, wide: bool) -> Option<usize> {
+    if wide {
+        read_u32(data, offset).map(|size| size as usize)
+    } else {
+        read_u16(data, offset)
+    }
+}
+
+/// Takes the gas cap of the next call, resetting it like the executor does.
+fn take_gas(gas: &mut u32) -> Option<u32> {
+    Some(std::mem::take(gas)).filter(|gas| *gas != 0)
+}
+
+/// Reads `len` bytes with an `mload`, which yields zeros past the end of the
+/// action stream.
+fn read_padded(data: &[u8], offset: &mut usize, len: usize) -> Vec<u8> {
+    let bytes = (*offset..*offset + len)
+        .map(|i| data.get(i).copied().unwrap_or(0))
+        .collect();
+    *offset += len;
+    bytes
+}
+
+/// Checks that `size` bytes at `offset` lie within `txData`, like
+/// `_checkRange`.
+fn check_range(tx_data: &[u8], offset: usize, size: usize, at: usize) -> Result<(), Revert> {
+    if offset + size > tx_data.len() {
+        return Err(Revert::TxDataOutOfBounds { offset: at });
+    }
+    Ok(())
+}
+
+/// Reads a buffer word, reverting if it runs past the end of `txData`.
+fn read_word(tx_data: &[u8], offset: usize, at: usize) -> Result<U256, Revert> {
+    check_range(tx_data, offset, 32, at)?;
+    Ok(U256::from_be_slice(&tx_data[offset..offset + 32]))
+}
+
+fn read_address(data: &[u8], offset: &mut usize) -> Address {
+    Address::from_slice(&read_padded(data, offset, 20))
+}
+
+/// Writes `bytes` at `offset`, reverting if they run past the end of
+/// `txData`.
+fn write_tx_data(tx_data: &mut [u8], offset: usize, bytes: &[u8], at: usize) -> Result<(), Revert> {
+    check_range(tx_data, offset, bytes.len(), at)?;
+    tx_data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    Ok(())
+}
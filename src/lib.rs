@@ -21,11 +21,22 @@ pub const EXECUTOR_RUNTIME: &[u8] = &[];
 pub const DELEGATE_PROXY_RUNTIME: &[u8] = &[];
 
 
+pub mod asm;
+pub mod checkpoint;
+pub mod decoder;
 pub mod flow_builder;
+pub mod interpreter;
 pub mod opcodes;
+pub mod optimizer;
+pub mod revert;
+#[cfg(feature = "revm")]
+pub mod simulator;
+pub mod validator;
 
 // Re-export Flowbuilder
-pub use flow_builder::FlowBuilder;
+pub use flow_builder::{
+    Action, ArithOp, Cmp, CoinbasePayment, FlowBuilder, FlowError, JumpDest, REGISTER_COUNT,
+};
 
 #[cfg(test)]
 mod test;
@@ -1,7 +1,7 @@
-/// Operation opcodes for the executor bytecode format.
-///
-/// Each constant represents a single-byte opcode consumed by the
-/// on-chain `executor` contract's `_executeActions` interpreter loop.
+//! Operation opcodes for the executor bytecode format.
+//!
+//! Each constant represents a single-byte opcode consumed by the
+//! on-chain `executor` contract's `_executeActions` interpreter loop.
 
 pub const OP_EOF: u8 = 0x00;
 pub const OP_CLEARDATA: u8 = 0x01;
@@ -15,3 +15,29 @@ pub const OP_DELEGATECALL: u8 = 0x08;
 pub const OP_SETCALLBACK: u8 = 0x09;
 pub const OP_SETFAIL: u8 = 0x0a;
 pub const OP_CLEARFAIL: u8 = 0x0b;
+pub const OP_COPYRETURN: u8 = 0x0c;
+pub const OP_SELFBALANCE: u8 = 0x0d;
+pub const OP_TOKENBALANCE: u8 = 0x0e;
+pub const OP_JUMP: u8 = 0x0f;
+pub const OP_JUMPIFFAILED: u8 = 0x10;
+pub const OP_JUMPIFWORD: u8 = 0x11;
+pub const OP_STATICCALL: u8 = 0x12;
+pub const OP_CREATE2: u8 = 0x13;
+pub const OP_ARITH: u8 = 0x14;
+pub const OP_ARITHI: u8 = 0x15;
+pub const OP_SNAPSHOT: u8 = 0x16;
+pub const OP_CHECKPROFIT: u8 = 0x17;
+pub const OP_SETGAS: u8 = 0x18;
+pub const OP_PAYCOINBASE: u8 = 0x19;
+pub const OP_MAXBLOCK: u8 = 0x1a;
+pub const OP_DEADLINE: u8 = 0x1b;
+pub const OP_MAXBASEFEE: u8 = 0x1c;
+pub const OP_LOG: u8 = 0x1d;
+pub const OP_SWEEP: u8 = 0x1e;
+pub const OP_STOREREG: u8 = 0x1f;
+pub const OP_LOADREG: u8 = 0x20;
+pub const OP_SETVALUEBAL: u8 = 0x21;
+pub const OP_SETVALUEDATA: u8 = 0x22;
+pub const OP_CLEARDATA32: u8 = 0x23;
+pub const OP_SETDATA32: u8 = 0x24;
+pub const OP_EXTCODECOPY32: u8 = 0x25;
@@ -0,0 +1,828 @@
+//! Peephole optimizer and equivalence checker.
+//!
+//! Both run flows through the same abstract model of the executor's state:
+//! target, value, gas cap, fail flag, callback slot, registers and the
+//! `txData` buffer. The
+//! optimizer only removes actions that leave that state unchanged (or whose
+//! effect is never observed), and [`check_equivalence`] confirms it by
+//! comparing the observable effects of the two flows.
+//!
+//! The model is straight-line: neither pass looks past the first label or
+//! jump, and the optimizer leaves everything from there on untouched.
+
+use std::{fmt, rc::Rc};
+
+use alloy_primitives::{Address, B256, U256};
+
+use crate::flow_builder::{Action, ArithOp, CoinbasePayment, JumpDest, REGISTER_COUNT};
+
+// ---------------------------------------------------------------------------
+// Abstract executor state
+// ---------------------------------------------------------------------------
+
+/// Call target as seen by the executor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    Addr(Address),
+    /// Address returned by the `CREATE` or `CREATE2` that produced the effect
+    /// at this index, when the builder did not record it.
+    Created(usize),
+}
+
+/// A single byte of the data buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Byte {
+    Lit(u8),
+    /// Byte `offset` of `source`'s code, read after `epoch` external effects.
+    Code {
+        source: Address,
+        offset: usize,
+        epoch: usize,
+    },
+    /// Byte `offset` of the return data of the `call`-th call, counting
+    /// balance queries as calls.
+    Return {
+        offset: usize,
+        call: usize,
+    },
+    /// Byte `index` of a balance word, queried after `epoch` external
+    /// effects.
+    Balance {
+        of: Balance,
+        index: usize,
+        epoch: usize,
+    },
+    /// Byte `index` of the result word of an arithmetic opcode.
+    Arith {
+        expr: Rc<Arith>,
+        index: usize,
+    },
+}
+
+/// Operands of an `ARITH` or `ARITHI`, as the words they read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Arith {
+    op: ArithOp,
+    lhs: Vec<Byte>,
+    rhs: Vec<Byte>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Balance {
+    Ether,
+    Token { token: Address, holder: Address },
+}
+
+/// ETH value of the next call or create.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Lit(U256),
+    /// The ETH balance minus `reserve`, read after `epoch` external effects.
+    Balance {
+        reserve: U256,
+        epoch: usize,
+    },
+    /// A buffer word read by `SETVALUEDATA`.
+    Word(Vec<Byte>),
+    /// The word at `data_offset` of a buffer the model cannot read it from.
+    Unknown {
+        data_offset: u16,
+        data: Buffer,
+    },
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::Lit(U256::ZERO)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Buffer {
+    Known(Vec<Byte>),
+    /// An out-of-bounds write left the buffer in a state the model does not
+    /// track. `ops` are the writes applied on top of `base` since then, each
+    /// with the epoch it ran in.
+    Tainted {
+        base: Vec<Byte>,
+        ops: Vec<(usize, Action)>,
+    },
+}
+
+/// Contents of the executor's `callbackAddress` storage slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Callback {
+    /// Whatever was stored before the flow started.
+    Initial,
+    Set(Address),
+    /// Possibly consumed or overwritten by the external effect at this index.
+    AfterEffect(usize),
+}
+
+/// An interaction of the flow with the outside world.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Effect {
+    Call {
+        target: Target,
+        value: Value,
+        gas: u32,
+        data: Buffer,
+        fail: bool,
+        callback: Callback,
+    },
+    DelegateCall {
+        target: Target,
+        gas: u32,
+        data: Buffer,
+        fail: bool,
+        callback: Callback,
+    },
+    StaticCall {
+        target: Target,
+        gas: u32,
+        data: Buffer,
+        fail: bool,
+    },
+    Create {
+        value: Value,
+        data: Buffer,
+        callback: Callback,
+    },
+    Create2 {
+        salt: B256,
+        value: Value,
+        data: Buffer,
+        callback: Callback,
+    },
+    /// `PAYCOINBASE`.
+    PayCoinbase {
+        payment: CoinbasePayment,
+        callback: Callback,
+    },
+    /// `SWEEP`.
+    Sweep {
+        token: Address,
+        recipient: Address,
+        callback: Callback,
+    },
+    /// `SNAPSHOT` of a balance, taken between the surrounding effects.
+    Snapshot { token: Address },
+    /// `CHECKPROFIT`, which may revert the flow.
+    CheckProfit { token: Address, min_profit: U256 },
+    /// `MAXBLOCK`, `DEADLINE`, `MAXBASEFEE` or a `SETVALUEBAL` with a
+    /// reserve, which may revert the flow, or a register access out of
+    /// range, which always does.
+    Guard(Action),
+    /// `STOREREG` of a word the model does not know, with the buffer it was
+    /// read from.
+    StoreUnknown {
+        register: u8,
+        data_offset: u16,
+        data: Buffer,
+    },
+    /// `LOG` of a slice of `data`.
+    Log {
+        tag: B256,
+        data_offset: u16,
+        size: u16,
+        data: Buffer,
+    },
+    /// The flow reaches its first label or jump with this state; `rest` is
+    /// everything from there on.
+    Branch {
+        target: Target,
+        value: Value,
+        gas: u32,
+        data: Buffer,
+        fail: bool,
+        callback: Callback,
+        calls: usize,
+        registers: Vec<Option<Vec<Byte>>>,
+        rest: Vec<Action>,
+    },
+    /// The callback slot persists in storage once the flow ends.
+    End { callback: Callback },
+}
+
+struct State {
+    target: Target,
+    value: Value,
+    /// Gas cap of the next call, 0 for none.
+    gas: u32,
+    buffer: Buffer,
+    fail: bool,
+    callback: Callback,
+    effects: usize,
+    /// Calls made so far, including balance queries; identifies the return
+    /// data `COPYRETURN` reads.
+    calls: usize,
+    /// Register words, `None` after a [`StoreUnknown`](Effect::StoreUnknown).
+    registers: Vec<Option<Vec<Byte>>>,
+}
+
+impl State {
+    fn new() -> Self {
+        Self {
+            target: Target::Addr(Address::ZERO),
+            value: Value::default(),
+            gas: 0,
+            buffer: Buffer::Known(Vec::new()),
+            fail: false,
+            callback: Callback::Initial,
+            effects: 0,
+            calls: 0,
+            registers: vec![Some(vec![Byte::Lit(0); 32]); REGISTER_COUNT as usize],
+        }
+    }
+
+    /// Applies `action`, returning the external effect it produces, if any.
+    fn apply(&mut self, action: &Action) -> Option<Effect> {
+        match action {
+            Action::ClearData { size } => {
+                self.buffer = Buffer::Known(vec![Byte::Lit(0); *size as usize]);
+            }
+            Action::ClearData32 { size } => {
+                self.buffer = Buffer::Known(vec![Byte::Lit(0); *size as usize]);
+            }
+            Action::SetData { offset, data } => {
+                let bytes = data.iter().map(|b| Byte::Lit(*b)).collect();
+                self.write(*offset as usize, bytes, action);
+            }
+            Action::SetData32 { offset, data } => {
+                let bytes = data.iter().map(|b| Byte::Lit(*b)).collect();
+                self.write(*offset as usize, bytes, action);
+            }
+            Action::ExtCodeCopy {
+                source,
+                data_offset,
+                code_offset,
+                size,
+            } => {
+                let bytes = self.code_bytes(*source, *code_offset as usize, *size as usize);
+                self.write(*data_offset as usize, bytes, action);
+            }
+            Action::ExtCodeCopy32 {
+                source,
+                data_offset,
+                code_offset,
+                size,
+            } => {
+                let bytes = self.code_bytes(*source, *code_offset as usize, *size as usize);
+                self.write(*data_offset as usize, bytes, action);
+            }
+            Action::CopyReturn {
+                ret_offset,
+                data_offset,
+                size,
+            } => {
+                let bytes = (0..*size as usize)
+                    .map(|i| Byte::Return {
+                        offset: *ret_offset as usize + i,
+                        call: self.calls,
+                    })
+                    .collect();
+                self.write(*data_offset as usize, bytes, action);
+            }
+            Action::SelfBalance { data_offset } => {
+                self.write_balance(Balance::Ether, *data_offset, action);
+            }
+            Action::TokenBalance {
+                token,
+                holder,
+                data_offset,
+            } => {
+                self.calls += 1;
+                let of = Balance::Token {
+                    token: *token,
+                    holder: *holder,
+                };
+                self.write_balance(of, *data_offset, action);
+            }
+            Action::Arith { op, dst, lhs, rhs } => {
+                let rhs = self.read_word(*rhs);
+                self.write_arith(*op, *dst, *lhs, rhs, action);
+            }
+            Action::ArithImm {
+                op,
+                dst,
+                lhs,
+                value,
+            } => {
+                let rhs = value.to_be_bytes::<32>().map(Byte::Lit).to_vec();
+                self.write_arith(*op, *dst, *lhs, Some(rhs), action);
+            }
+            Action::SetAddr { addr } => self.target = Target::Addr(*addr),
+            Action::SetValue { value } => self.value = Value::Lit(*value),
+            Action::SetValueFromBalance { reserve } => {
+                self.value = Value::Balance {
+                    reserve: *reserve,
+                    epoch: self.effects,
+                };
+                // Reverts if the balance is below the reserve
+                if !reserve.is_zero() {
+                    return Some(Effect::Guard(action.clone()));
+                }
+            }
+            Action::SetValueFromData { data_offset } => {
+                self.value = self.word_value(*data_offset);
+            }
+            Action::SetGas { gas } => self.gas = *gas,
+            Action::Call => {
+                let effect = Effect::Call {
+                    target: self.target.clone(),
+                    value: std::mem::take(&mut self.value),
+                    gas: std::mem::take(&mut self.gas),
+                    data: self.buffer.clone(),
+                    fail: self.fail,
+                    callback: self.callback.clone(),
+                };
+                return Some(self.external(effect));
+            }
+            Action::Create { created_address } => {
+                let effect = Effect::Create {
+                    value: std::mem::take(&mut self.value),
+                    data: self.buffer.clone(),
+                    callback: self.callback.clone(),
+                };
+                self.target = match created_address {
+                    Some(addr) => Target::Addr(*addr),
+                    None => Target::Created(self.effects),
+                };
+                return Some(self.external(effect));
+            }
+            Action::Create2 {
+                salt,
+                created_address,
+            } => {
+                let effect = Effect::Create2 {
+                    salt: *salt,
+                    value: std::mem::take(&mut self.value),
+                    data: self.buffer.clone(),
+                    callback: self.callback.clone(),
+                };
+                self.target = match created_address {
+                    Some(addr) => Target::Addr(*addr),
+                    None => Target::Created(self.effects),
+                };
+                return Some(self.external(effect));
+            }
+            Action::DelegateCall => {
+                let effect = Effect::DelegateCall {
+                    target: self.target.clone(),
+                    gas: std::mem::take(&mut self.gas),
+                    data: self.buffer.clone(),
+                    fail: self.fail,
+                    callback: self.callback.clone(),
+                };
+                return Some(self.external(effect));
+            }
+            Action::StaticCall => {
+                // Cannot change state, so neither the callback slot nor the
+                // epoch moves; only the return data does.
+                self.calls += 1;
+                return Some(Effect::StaticCall {
+                    target: self.target.clone(),
+                    gas: std::mem::take(&mut self.gas),
+                    data: self.buffer.clone(),
+                    fail: self.fail,
+                });
+            }
+            Action::PayCoinbase { payment } => {
+                let effect = Effect::PayCoinbase {
+                    payment: *payment,
+                    callback: self.callback.clone(),
+                };
+                return Some(self.external(effect));
+            }
+            Action::Sweep { token, recipient } => {
+                // The balance query of a token is a call of its own
+                self.calls += usize::from(!token.is_zero());
+                let effect = Effect::Sweep {
+                    token: *token,
+                    recipient: *recipient,
+                    callback: self.callback.clone(),
+                };
+                return Some(self.external(effect));
+            }
+            Action::Snapshot { token } => {
+                // A token balance is queried with a call, an ETH balance is not
+                self.calls += usize::from(!token.is_zero());
+                return Some(Effect::Snapshot { token: *token });
+            }
+            Action::CheckProfit { token, min_profit } => {
+                self.calls += usize::from(!token.is_zero());
+                return Some(Effect::CheckProfit {
+                    token: *token,
+                    min_profit: *min_profit,
+                });
+            }
+            Action::MaxBlock { .. } | Action::Deadline { .. } | Action::MaxBaseFee { .. } => {
+                return Some(Effect::Guard(action.clone()));
+            }
+            Action::StoreReg {
+                register,
+                data_offset,
+            } => {
+                let word = self.read_word(*data_offset);
+                let Some(slot) = self.registers.get_mut(*register as usize) else {
+                    return Some(Effect::Guard(action.clone()));
+                };
+                *slot = word;
+                if slot.is_none() {
+                    return Some(Effect::StoreUnknown {
+                        register: *register,
+                        data_offset: *data_offset,
+                        data: self.buffer.clone(),
+                    });
+                }
+            }
+            Action::LoadReg {
+                register,
+                data_offset,
+            } => match self.registers.get(*register as usize) {
+                Some(Some(word)) => self.write(*data_offset as usize, word.clone(), action),
+                Some(None) => self.taint(action),
+                None => return Some(Effect::Guard(action.clone())),
+            },
+            Action::Log {
+                tag,
+                data_offset,
+                size,
+            } => {
+                return Some(Effect::Log {
+                    tag: *tag,
+                    data_offset: *data_offset,
+                    size: *size,
+                    data: self.buffer.clone(),
+                });
+            }
+            Action::SetCallback { callback_address } => {
+                self.callback = Callback::Set(*callback_address)
+            }
+            Action::SetFail => self.fail = true,
+            Action::ClearFail => self.fail = false,
+            // Control flow is never applied: both passes stop before it.
+            Action::Label { .. }
+            | Action::Jump { .. }
+            | Action::JumpIfFailed { .. }
+            | Action::JumpIfWord { .. } => {}
+        }
+        None
+    }
+
+    /// Records an external effect, after which the callback slot is unknown.
+    fn external(&mut self, effect: Effect) -> Effect {
+        self.callback = Callback::AfterEffect(self.effects);
+        self.effects += 1;
+        self.calls += 1;
+        effect
+    }
+
+    fn write_balance(&mut self, of: Balance, data_offset: u16, action: &Action) {
+        let bytes = (0..32)
+            .map(|index| Byte::Balance {
+                of: of.clone(),
+                index,
+                epoch: self.effects,
+            })
+            .collect();
+        self.write(data_offset as usize, bytes, action);
+    }
+
+    /// The word at `offset`, or `None` if the model does not know it.
+    fn read_word(&self, offset: u16) -> Option<Vec<Byte>> {
+        match &self.buffer {
+            Buffer::Known(buf) => buf
+                .get(offset as usize..offset as usize + 32)
+                .map(<[Byte]>::to_vec),
+            Buffer::Tainted { .. } => None,
+        }
+    }
+
+    /// The value `SETVALUEDATA` reads from the word at `data_offset`.
+    fn word_value(&self, data_offset: u16) -> Value {
+        let Some(word) = self.read_word(data_offset) else {
+            return Value::Unknown {
+                data_offset,
+                data: self.buffer.clone(),
+            };
+        };
+        let lits: Option<Vec<u8>> = word
+            .iter()
+            .map(|b| match b {
+                Byte::Lit(b) => Some(*b),
+                _ => None,
+            })
+            .collect();
+        match lits {
+            Some(lits) => Value::Lit(U256::from_be_slice(&lits)),
+            None => Value::Word(word),
+        }
+    }
+
+    fn write_arith(
+        &mut self,
+        op: ArithOp,
+        dst: u16,
+        lhs: u16,
+        rhs: Option<Vec<Byte>>,
+        action: &Action,
+    ) {
+        let (Some(lhs), Some(rhs)) = (self.read_word(lhs), rhs) else {
+            // An operand read outside the buffer depends on untracked memory
+            self.taint(action);
+            return;
+        };
+        let expr = Rc::new(Arith { op, lhs, rhs });
+        let bytes = (0..32)
+            .map(|index| Byte::Arith {
+                expr: expr.clone(),
+                index,
+            })
+            .collect();
+        self.write(dst as usize, bytes, action);
+    }
+
+    /// The bytes an `EXTCODECOPY` of `source`'s code reads.
+    fn code_bytes(&self, source: Address, code_offset: usize, size: usize) -> Vec<Byte> {
+        (0..size)
+            .map(|i| Byte::Code {
+                source,
+                offset: code_offset + i,
+                epoch: self.effects,
+            })
+            .collect()
+    }
+
+    fn write(&mut self, offset: usize, bytes: Vec<Byte>, action: &Action) {
+        match &mut self.buffer {
+            Buffer::Known(buf) if offset + bytes.len() <= buf.len() => {
+                buf.splice(offset..offset + bytes.len(), bytes);
+            }
+            _ => self.taint(action),
+        }
+    }
+
+    /// Records `action` as a write the model does not track.
+    fn taint(&mut self, action: &Action) {
+        match &mut self.buffer {
+            Buffer::Known(buf) => {
+                self.buffer = Buffer::Tainted {
+                    base: std::mem::take(buf),
+                    ops: vec![(self.effects, action.clone())],
+                };
+            }
+            Buffer::Tainted { ops, .. } => ops.push((self.effects, action.clone())),
+        }
+    }
+
+    /// Whether applying `action` would leave the state unchanged.
+    fn is_noop(&self, action: &Action) -> bool {
+        match action {
+            Action::SetFail => self.fail,
+            Action::ClearFail => !self.fail,
+            Action::SetAddr { addr } => self.target == Target::Addr(*addr),
+            Action::SetValue { value } => self.value == Value::Lit(*value),
+            Action::SetValueFromData { data_offset } => self.value == self.word_value(*data_offset),
+            Action::SetGas { gas } => self.gas == *gas,
+            Action::SetCallback { callback_address } => {
+                self.callback == Callback::Set(*callback_address)
+            }
+            Action::ClearData { size } => self.is_cleared(*size as usize),
+            Action::ClearData32 { size } => self.is_cleared(*size as usize),
+            Action::SetData { offset, data } => self.holds(*offset as usize, data),
+            Action::SetData32 { offset, data } => self.holds(*offset as usize, data),
+            _ => false,
+        }
+    }
+
+    /// Whether the buffer is `size` zero bytes.
+    fn is_cleared(&self, size: usize) -> bool {
+        match &self.buffer {
+            Buffer::Known(buf) => buf.len() == size && buf.iter().all(|b| *b == Byte::Lit(0)),
+            Buffer::Tainted { .. } => false,
+        }
+    }
+
+    /// Whether the buffer already holds `data` at `offset`.
+    fn holds(&self, offset: usize, data: &[u8]) -> bool {
+        match &self.buffer {
+            Buffer::Known(buf) => buf
+                .get(offset..offset + data.len())
+                .is_some_and(|cur| cur.iter().zip(data).all(|(c, d)| *c == Byte::Lit(*d))),
+            Buffer::Tainted { .. } => false,
+        }
+    }
+
+    /// Whether a same-size `CLEARDATA` can be dropped because every non-zero
+    /// byte it would clear is overwritten by `rest` before the buffer is next
+    /// read.
+    fn clear_is_dead(&self, size: usize, rest: &[Action]) -> bool {
+        let Buffer::Known(buf) = &self.buffer else {
+            return false;
+        };
+        if buf.len() != size {
+            return false;
+        }
+        let mut pending: Vec<bool> = buf.iter().map(|b| *b != Byte::Lit(0)).collect();
+        for action in rest {
+            let (offset, len) = match action {
+                Action::SetData { offset, data } => (*offset as usize, data.len()),
+                Action::SetData32 { offset, data } => (*offset as usize, data.len()),
+                Action::ExtCodeCopy {
+                    data_offset, size, ..
+                }
+                | Action::CopyReturn {
+                    data_offset, size, ..
+                } => (*data_offset as usize, *size as usize),
+                Action::ExtCodeCopy32 {
+                    data_offset, size, ..
+                } => (*data_offset as usize, *size as usize),
+                Action::SelfBalance { data_offset } | Action::TokenBalance { data_offset, .. } => {
+                    (*data_offset as usize, 32)
+                }
+                Action::Arith { dst, lhs, rhs, .. } => {
+                    if reads_pending(&pending, *lhs) || reads_pending(&pending, *rhs) {
+                        return false;
+                    }
+                    (*dst as usize, 32)
+                }
+                Action::ArithImm { dst, lhs, .. } => {
+                    if reads_pending(&pending, *lhs) {
+                        return false;
+                    }
+                    (*dst as usize, 32)
+                }
+                Action::StoreReg { data_offset, .. } | Action::SetValueFromData { data_offset } => {
+                    if reads_pending(&pending, *data_offset) {
+                        return false;
+                    }
+                    continue;
+                }
+                Action::LoadReg { data_offset, .. } => (*data_offset as usize, 32),
+                Action::ClearData { .. } | Action::ClearData32 { .. } => return true,
+                Action::Call
+                | Action::DelegateCall
+                | Action::StaticCall
+                | Action::Create { .. }
+                | Action::Create2 { .. }
+                | Action::Log { .. } => return pending.iter().all(|p| !p),
+                a if is_control_flow(a) => return pending.iter().all(|p| !p),
+                _ => continue,
+            };
+            match pending.get_mut(offset..offset + len) {
+                Some(range) => range.fill(false),
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Optimizer
+// ---------------------------------------------------------------------------
+
+/// Single-pass peephole optimizer. Returns, for each action, whether it must
+/// be kept.
+///
+/// The model state follows the *optimized* flow, so every removal is judged
+/// against what the executor will actually see.
+///
+/// Nothing is removed from the first label or jump on. A flow with a jump to
+/// a raw byte offset is kept whole, since any removal would move its target.
+pub(crate) fn peephole(actions: &[Action]) -> Vec<bool> {
+    let mut state = State::new();
+    let mut keep = vec![true; actions.len()];
+    if actions
+        .iter()
+        .any(|a| matches!(a.jump_dest(), Some(JumpDest::Offset(_))))
+    {
+        return keep;
+    }
+
+    for (idx, action) in actions.iter().enumerate() {
+        if is_control_flow(action) {
+            break;
+        }
+        let redundant = state.is_noop(action)
+            || matches!(action, Action::ClearData { size }
+                if state.clear_is_dead(*size as usize, &actions[idx + 1..]))
+            || matches!(action, Action::ClearData32 { size }
+                if state.clear_is_dead(*size as usize, &actions[idx + 1..]))
+            || matches!(action, Action::SetGas { .. } if gas_is_dead(&actions[idx + 1..]));
+        if redundant {
+            keep[idx] = false;
+        } else {
+            state.apply(action);
+        }
+    }
+    keep
+}
+
+// ---------------------------------------------------------------------------
+// Equivalence checker
+// ---------------------------------------------------------------------------
+
+/// Error returned by [`check_equivalence`] when two flows differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EquivalenceError {
+    /// Index of the first observable effect that differs.
+    pub index: usize,
+    /// The effect produced by the original flow.
+    pub expected: String,
+    /// The effect produced by the other flow.
+    pub found: String,
+}
+
+impl fmt::Display for EquivalenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "effect {} differs: expected {}, found {}",
+            self.index, self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for EquivalenceError {}
+
+/// Checks that two action lists have identical observable effects: the same
+/// calls, delegatecalls, static calls, creates (including salts), coinbase
+/// payments and sweeps, with the same target, value, gas cap, calldata, fail
+/// flag and callback address, the same profit snapshots, profit checks, block
+/// guards and logged buffers between them, the same registers at the first
+/// label or jump, and the same callback left behind.
+pub fn check_equivalence(
+    original: &[Action],
+    optimized: &[Action],
+) -> Result<(), EquivalenceError> {
+    let expected = effects(original);
+    let found = effects(optimized);
+    let describe = |e: Option<&Effect>| e.map_or("nothing".to_string(), |e| format!("{e:?}"));
+
+    for index in 0..expected.len().max(found.len()) {
+        if expected.get(index) != found.get(index) {
+            return Err(EquivalenceError {
+                index,
+                expected: describe(expected.get(index)),
+                found: describe(found.get(index)),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn effects(actions: &[Action]) -> Vec<Effect> {
+    let mut state = State::new();
+    let mut effects = Vec::new();
+    for (idx, action) in actions.iter().enumerate() {
+        if is_control_flow(action) {
+            effects.push(Effect::Branch {
+                target: state.target,
+                value: state.value,
+                gas: state.gas,
+                data: state.buffer,
+                fail: state.fail,
+                callback: state.callback,
+                calls: state.calls,
+                registers: state.registers,
+                rest: actions[idx..].to_vec(),
+            });
+            return effects;
+        }
+        effects.extend(state.apply(action));
+    }
+    effects.push(Effect::End {
+        callback: state.callback,
+    });
+    effects
+}
+
+/// Whether the word at `offset` may still read a byte the `CLEARDATA` under
+/// test would zero.
+fn reads_pending(pending: &[bool], offset: u16) -> bool {
+    let offset = offset as usize;
+    pending
+        .get(offset..offset + 32)
+        .is_none_or(|word| word.iter().any(|p| *p))
+}
+
+/// Whether a `SETGAS` is overridden by another one, or the flow ends, before
+/// any call uses it.
+fn gas_is_dead(rest: &[Action]) -> bool {
+    for action in rest {
+        match action {
+            Action::SetGas { .. } => return true,
+            Action::Call | Action::DelegateCall | Action::StaticCall => return false,
+            a if is_control_flow(a) => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Whether `action` is a label or jump, past which the model cannot follow.
+fn is_control_flow(action: &Action) -> bool {
+    matches!(action, Action::Label { .. }) || action.jump_dest().is_some()
+}
@@ -0,0 +1,224 @@
+//! Decoding of the executor's revert data.
+//!
+//! With the fail flag set, a failed `CALL`, `DELEGATECALL`, `STATICCALL`,
+//! `CREATE` or `CREATE2`, like a failed `PAYCOINBASE` transfer, reverts the
+//! flow with `ActionFailed(uint256 offset, bytes reason)`: the byte offset of
+//! the action in its action stream and the revert data of the call or of the
+//! init code. [`decode_revert`] unpacks that error, the profit guard's
+//! `InsufficientProfit`, and the standard `Error(string)` and
+//! `Panic(uint256)` payloads, recursing into `reason` so that a failure deep
+//! inside nested flash-loan callbacks reads as a chain of failed actions.
+//! [`FlowBuilder::action_index`] maps an offset back to the action that
+//! failed.
+
+use std::fmt;
+
+use alloy_primitives::{hex, Address, U256};
+
+use crate::flow_builder::FlowBuilder;
+use crate::interpreter::selector_of;
+
+/// A decoded revert payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevertReason {
+    /// No revert data, e.g. a bare `revert()` or an out-of-gas halt.
+    Empty,
+    /// `Error(string)`, raised by `require` and `revert("...")`.
+    Error(String),
+    /// `Panic(uint256)`, e.g. `0x11` for an arithmetic overflow.
+    Panic(U256),
+    /// `ActionFailed`: the action at byte `offset` failed with `reason`.
+    ActionFailed {
+        offset: usize,
+        reason: Box<RevertReason>,
+    },
+    /// `InsufficientProfit` raised by `CHECKPROFIT`.
+    InsufficientProfit {
+        token: Address,
+        balance_before: U256,
+        balance_after: U256,
+        min_profit: U256,
+    },
+    /// Any other payload, e.g. a custom error of the called contract.
+    Unknown(Vec<u8>),
+}
+
+impl RevertReason {
+    /// The innermost reason, past every [`ActionFailed`](Self::ActionFailed)
+    /// layer.
+    pub fn root_cause(&self) -> &RevertReason {
+        match self {
+            RevertReason::ActionFailed { reason, .. } => reason.root_cause(),
+            reason => reason,
+        }
+    }
+
+    /// Offsets of the failed actions, outermost flow first.
+    pub fn action_offsets(&self) -> Vec<usize> {
+        let mut offsets = Vec::new();
+        let mut reason = self;
+        while let RevertReason::ActionFailed {
+            offset,
+            reason: inner,
+        } = reason
+        {
+            offsets.push(*offset);
+            reason = inner;
+        }
+        offsets
+    }
+
+    /// ABI-encodes the reason as revert data; the inverse of
+    /// [`decode_revert`].
+    pub fn encode(&self) -> Vec<u8> {
+        let word = |value: U256| value.to_be_bytes::<32>();
+        match self {
+            RevertReason::Empty => Vec::new(),
+            RevertReason::Error(message) => {
+                let mut data = selector_of("Error(string)").to_vec();
+                data.extend(word(U256::from(32)));
+                push_bytes(&mut data, message.as_bytes());
+                data
+            }
+            RevertReason::Panic(code) => {
+                let mut data = selector_of("Panic(uint256)").to_vec();
+                data.extend(word(*code));
+                data
+            }
+            RevertReason::ActionFailed { offset, reason } => {
+                let mut data = selector_of("ActionFailed(uint256,bytes)").to_vec();
+                data.extend(word(U256::from(*offset)));
+                data.extend(word(U256::from(64)));
+                push_bytes(&mut data, &reason.encode());
+                data
+            }
+            RevertReason::InsufficientProfit {
+                token,
+                balance_before,
+                balance_after,
+                min_profit,
+            } => {
+                let mut data =
+                    selector_of("InsufficientProfit(address,uint256,uint256,uint256)").to_vec();
+                data.extend(token.into_word());
+                data.extend(word(*balance_before));
+                data.extend(word(*balance_after));
+                data.extend(word(*min_profit));
+                data
+            }
+            RevertReason::Unknown(data) => data.clone(),
+        }
+    }
+}
+
+impl fmt::Display for RevertReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevertReason::Empty => write!(f, "reverted without data"),
+            RevertReason::Error(message) => write!(f, "Error({message:?})"),
+            RevertReason::Panic(code) => write!(f, "Panic(0x{code:02x})"),
+            RevertReason::ActionFailed { offset, reason } => {
+                write!(f, "action at offset {offset} failed: {reason}")
+            }
+            RevertReason::InsufficientProfit {
+                token,
+                balance_before,
+                balance_after,
+                min_profit,
+            } => write!(
+                f,
+                "InsufficientProfit: balance of {token} went from {balance_before} to \
+                 {balance_after}, required a profit of {min_profit}"
+            ),
+            RevertReason::Unknown(data) => {
+                write!(f, "unknown revert data 0x{}", hex::encode(data))
+            }
+        }
+    }
+}
+
+/// Decodes revert data returned by the executor, or by any contract.
+///
+/// Payloads that are not one of the known errors, or are malformed, decode
+/// to [`RevertReason::Unknown`].
+pub fn decode_revert(data: &[u8]) -> RevertReason {
+    if data.is_empty() {
+        return RevertReason::Empty;
+    }
+    try_decode(data).unwrap_or_else(|| RevertReason::Unknown(data.to_vec()))
+}
+
+/// Appends the tail of an ABI-encoded `bytes`: its length, then the bytes
+/// zero-padded to a whole word.
+fn push_bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+    data.extend(U256::from(bytes.len()).to_be_bytes::<32>());
+    data.extend(bytes);
+    data.resize(
+        data.len() + bytes.len().next_multiple_of(32) - bytes.len(),
+        0,
+    );
+}
+
+fn try_decode(data: &[u8]) -> Option<RevertReason> {
+    let (selector, args) = data.split_at_checked(4)?;
+    let word = |i: usize| args.get(i * 32..(i + 1) * 32).map(U256::from_be_slice);
+    if selector == selector_of("Error(string)") {
+        let message = dynamic_bytes(args, word(0)?)?;
+        Some(RevertReason::Error(
+            String::from_utf8(message.to_vec()).ok()?,
+        ))
+    } else if selector == selector_of("Panic(uint256)") {
+        Some(RevertReason::Panic(word(0)?))
+    } else if selector == selector_of("ActionFailed(uint256,bytes)") {
+        Some(RevertReason::ActionFailed {
+            offset: word(0)?.try_into().ok()?,
+            reason: Box::new(decode_revert(dynamic_bytes(args, word(1)?)?)),
+        })
+    } else if selector == selector_of("InsufficientProfit(address,uint256,uint256,uint256)") {
+        let token = args.get(..32)?;
+        if token[..12].iter().any(|b| *b != 0) {
+            return None;
+        }
+        Some(RevertReason::InsufficientProfit {
+            token: Address::from_slice(&token[12..]),
+            balance_before: word(1)?,
+            balance_after: word(2)?,
+            min_profit: word(3)?,
+        })
+    } else {
+        None
+    }
+}
+
+/// Reads an ABI-encoded `bytes` or `string` whose head word is `offset`,
+/// from the arguments of an error or the data of an event.
+pub(crate) fn dynamic_bytes(args: &[u8], offset: U256) -> Option<&[u8]> {
+    let offset = usize::try_from(offset).ok()?;
+    let len = args.get(offset..offset.checked_add(32)?)?;
+    let len = usize::try_from(U256::from_be_slice(len)).ok()?;
+    let start = offset + 32;
+    args.get(start..start.checked_add(len)?)
+}
+
+impl FlowBuilder {
+    /// Index of the action starting at byte `offset` of the built action
+    /// stream, e.g. from [`RevertReason::ActionFailed`]. Labels take no
+    /// bytes and are skipped.
+    ///
+    /// Offsets of a nested callback flow are relative to that flow, so look
+    /// them up in the builder of the inner flow.
+    pub fn action_index(&self, offset: usize) -> Option<usize> {
+        let mut pos = 0;
+        for (index, action) in self.iter().enumerate() {
+            let len = action.encode_with(&mut |_| 0).len();
+            if pos == offset && len > 0 {
+                return Some(index);
+            }
+            pos += len;
+            if pos > offset {
+                return None;
+            }
+        }
+        None
+    }
+}
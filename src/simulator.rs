@@ -0,0 +1,303 @@
+//! In-process EVM execution of flows, backed by revm.
+//!
+//! [`Simulator`] keeps an in-memory state with the executor deployed and runs
+//! [`FlowBuilder`]s against it as transactions from the owner, without any
+//! node or RPC. By default the state starts empty; [`Simulator::with_db`]
+//! layers it on top of any revm [`DatabaseRef`], e.g. a forked or
+//! bot-maintained state.
+
+use std::{collections::BTreeMap, fmt};
+
+use alloy_primitives::{Address, Bytes, Log, TxKind, U256};
+use revm::{
+    context::{
+        result::{ExecutionResult, Output, ResultAndState},
+        BlockEnv, Context, TxEnv,
+    },
+    database::{CacheDB, EmptyDB},
+    database_interface::{DatabaseCommit, DatabaseRef},
+    state::{AccountInfo, Bytecode},
+    ExecuteEvm, MainBuilder, MainContext,
+};
+
+use crate::{interpreter::create_address, FlowBuilder, DELEGATE_PROXY_INIT, EXECUTOR_RUNTIME};
+
+/// Gas limit of every simulated transaction.
+pub const GAS_LIMIT: u64 = 30_000_000;
+
+/// Outcome of a simulated transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Simulation {
+    pub success: bool,
+    pub gas_used: u64,
+    pub logs: Vec<Log>,
+    /// Return data on success, revert data on revert, empty on halt.
+    pub output: Bytes,
+    /// Accounts changed by the transaction.
+    pub state_diff: BTreeMap<Address, AccountDiff>,
+}
+
+/// Changes to a single account, as `(before, after)` pairs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountDiff {
+    pub balance: (U256, U256),
+    pub nonce: (u64, u64),
+    /// Changed storage slots only.
+    pub storage: BTreeMap<U256, (U256, U256)>,
+    /// The new code, if the account's code changed.
+    pub code: Option<Bytes>,
+}
+
+/// Error returned when a transaction is invalid or the database fails, as
+/// opposed to a transaction that reverts (see [`Simulation::success`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationError(pub String);
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "simulation failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+/// In-memory EVM with the executor deployed.
+pub struct Simulator<DB = EmptyDB> {
+    db: CacheDB<DB>,
+    block: BlockEnv,
+    owner: Address,
+    executor: Address,
+}
+
+impl Simulator {
+    /// Creates an empty state with the executor owned by `owner`.
+    pub fn new(owner: Address) -> Self {
+        Self::with_db(owner, EmptyDB::default())
+    }
+}
+
+impl<DB> Simulator<DB>
+where
+    DB: DatabaseRef,
+    DB::Error: fmt::Display,
+{
+    /// Creates a state layered on top of `db`, with the executor owned by
+    /// `owner`.
+    ///
+    /// The executor is placed where `owner` would deploy it with its first
+    /// transaction, `EXECUTOR_RUNTIME` is installed directly and the owner
+    /// slot is written, so no deployment transaction is needed. The owner's
+    /// nonce is advanced past that deployment, so its next `CREATE` does not
+    /// land on the executor.
+    pub fn with_db(owner: Address, db: DB) -> Self {
+        let mut sim = Self {
+            db: CacheDB::new(db),
+            block: BlockEnv::default(),
+            owner,
+            executor: create_address(owner, 0),
+        };
+        sim.set_code(sim.executor, EXECUTOR_RUNTIME);
+        sim.set_storage(
+            sim.executor,
+            U256::ZERO,
+            U256::from_be_bytes(owner.into_word().0),
+        );
+        let mut info = sim.account(owner);
+        info.nonce = info.nonce.max(1);
+        sim.db.insert_account_info(owner, info);
+        sim
+    }
+
+    /// The account every transaction is sent from.
+    pub fn owner(&self) -> Address {
+        self.owner
+    }
+
+    /// The executor transactions are sent to.
+    pub fn executor(&self) -> Address {
+        self.executor
+    }
+
+    /// Sends transactions to `executor` instead, e.g. a proxy returned by
+    /// [`deploy_proxy`](Self::deploy_proxy).
+    pub fn set_executor(&mut self, executor: Address) {
+        self.executor = executor;
+    }
+
+    /// Block environment used for every transaction.
+    pub fn block_mut(&mut self) -> &mut BlockEnv {
+        &mut self.block
+    }
+
+    /// Direct access to the in-memory state.
+    pub fn db_mut(&mut self) -> &mut CacheDB<DB> {
+        &mut self.db
+    }
+
+    /// Overrides the balance of `address`.
+    pub fn set_balance(&mut self, address: Address, balance: U256) {
+        let mut info = self.account(address);
+        info.balance = balance;
+        self.db.insert_account_info(address, info);
+    }
+
+    /// Installs runtime `code` at `address`.
+    pub fn set_code(&mut self, address: Address, code: &[u8]) {
+        let code = Bytecode::new_raw(Bytes::copy_from_slice(code));
+        let mut info = self.account(address);
+        info.nonce = info.nonce.max(1);
+        info.code_hash = code.hash_slow();
+        info.code = Some(code);
+        self.db.insert_account_info(address, info);
+    }
+
+    /// Overrides a storage slot of `address`.
+    pub fn set_storage(&mut self, address: Address, slot: U256, value: U256) {
+        self.db
+            .insert_account_storage(address, slot, value)
+            .unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    fn account(&self, address: Address) -> AccountInfo {
+        self.db
+            .basic_ref(address)
+            .unwrap_or_else(|e| panic!("{e}"))
+            .unwrap_or_default()
+    }
+
+    /// Deploys a delegate proxy in front of the executor and returns its
+    /// address.
+    ///
+    /// The proxy's target is an immutable, so unlike the executor it is
+    /// deployed from `DELEGATE_PROXY_INIT` rather than installed from its
+    /// runtime code.
+    pub fn deploy_proxy(&mut self) -> Result<Address, SimulationError> {
+        // abi.encode(address _target, bytes constructorData = "")
+        let mut initcode = DELEGATE_PROXY_INIT.to_vec();
+        initcode.extend_from_slice(self.executor.into_word().as_slice());
+        initcode.extend_from_slice(&U256::from(0x40).to_be_bytes::<32>());
+        initcode.extend_from_slice(&[0; 32]);
+
+        let nonce = self.account(self.owner).nonce;
+        let sim = self.transact(TxKind::Create, initcode.into(), U256::ZERO, true)?;
+        if !sim.success {
+            return Err(SimulationError(format!(
+                "proxy deployment reverted: {}",
+                sim.output
+            )));
+        }
+        Ok(create_address(self.owner, nonce))
+    }
+
+    /// Runs `flow` against the executor and keeps the resulting state.
+    pub fn execute(
+        &mut self,
+        flow: &FlowBuilder,
+        value: U256,
+    ) -> Result<Simulation, SimulationError> {
+        self.transact(
+            TxKind::Call(self.executor),
+            flow.build().into(),
+            value,
+            true,
+        )
+    }
+
+    /// Runs `flow` against the executor and discards the resulting state.
+    pub fn simulate(
+        &mut self,
+        flow: &FlowBuilder,
+        value: U256,
+    ) -> Result<Simulation, SimulationError> {
+        self.transact(
+            TxKind::Call(self.executor),
+            flow.build().into(),
+            value,
+            false,
+        )
+    }
+
+    /// Sends an arbitrary transaction from the owner.
+    pub fn transact(
+        &mut self,
+        kind: TxKind,
+        data: Bytes,
+        value: U256,
+        commit: bool,
+    ) -> Result<Simulation, SimulationError> {
+        let tx = TxEnv {
+            caller: self.owner,
+            kind,
+            data,
+            value,
+            nonce: self.account(self.owner).nonce,
+            gas_limit: GAS_LIMIT,
+            ..Default::default()
+        };
+        let mut evm = Context::mainnet()
+            .with_db(&mut self.db)
+            .with_block(self.block.clone())
+            .build_mainnet();
+        let ResultAndState { result, state } = evm
+            .transact(tx)
+            .map_err(|e| SimulationError(e.to_string()))?;
+        drop(evm);
+
+        let mut state_diff = BTreeMap::new();
+        for (address, account) in &state {
+            if !account.is_touched() {
+                continue;
+            }
+            let before = self.account(*address);
+            let diff = AccountDiff {
+                balance: (before.balance, account.info.balance),
+                nonce: (before.nonce, account.info.nonce),
+                storage: account
+                    .changed_storage_slots()
+                    .map(|(slot, value)| (*slot, (value.original_value, value.present_value)))
+                    .collect(),
+                code: (before.code_hash != account.info.code_hash).then(|| {
+                    account
+                        .info
+                        .code
+                        .as_ref()
+                        .map(|code| code.original_bytes())
+                        .unwrap_or_default()
+                }),
+            };
+            let unchanged = diff.balance.0 == diff.balance.1
+                && diff.nonce.0 == diff.nonce.1
+                && diff.storage.is_empty()
+                && diff.code.is_none();
+            if !unchanged {
+                state_diff.insert(*address, diff);
+            }
+        }
+        if commit {
+            self.db.commit(state);
+        }
+
+        let (success, gas_used, logs, output) = match result {
+            ExecutionResult::Success {
+                gas_used,
+                logs,
+                output,
+                ..
+            } => {
+                let output = match output {
+                    Output::Call(bytes) | Output::Create(bytes, _) => bytes,
+                };
+                (true, gas_used, logs, output)
+            }
+            ExecutionResult::Revert { gas_used, output } => (false, gas_used, Vec::new(), output),
+            ExecutionResult::Halt { gas_used, .. } => (false, gas_used, Vec::new(), Bytes::new()),
+        };
+        Ok(Simulation {
+            success,
+            gas_used,
+            logs,
+            output,
+            state_diff,
+        })
+    }
+}
@@ -1,12 +1,28 @@
-use crate::{FlowBuilder, DELEGATE_PROXY_INIT, EXECUTOR_INIT};
+use crate::{
+    asm,
+    checkpoint::{decode_checkpoints, Checkpoint},
+    decoder::{decode, decode_raw, DecodeError},
+    flow_builder::{ArithOp, Cmp, JumpDest},
+    interpreter::{create_address, Effect, Interpreter, Request, Responder, Response, Revert},
+    opcodes::{
+        OP_ARITHI, OP_CALL, OP_CLEARDATA, OP_CLEARDATA32, OP_CLEARFAIL, OP_EXTCODECOPY32, OP_JUMP,
+        OP_JUMPIFWORD, OP_PAYCOINBASE, OP_SETADDR, OP_SETDATA, OP_SETDATA32, OP_SETFAIL,
+        OP_SETVALUE,
+    },
+    optimizer::check_equivalence,
+    revert::{decode_revert, RevertReason},
+    validator::ValidationError,
+    Action, CoinbasePayment, FlowBuilder, FlowError, DELEGATE_PROXY_INIT, EXECUTOR_INIT,
+    REGISTER_COUNT,
+};
 use alloy::{
     hex,
     network::{Ethereum, TransactionBuilder},
-    primitives::{address, bytes, uint, Address, U256},
+    primitives::{address, b256, bytes, uint, Address, B256, U256},
     providers::{ext::AnvilApi, Provider, ProviderBuilder},
     rpc::types::TransactionRequest,
     sol,
-    sol_types::{SolCall, SolConstructor},
+    sol_types::{SolCall, SolConstructor, SolError, SolValue},
 };
 
 // Constants
@@ -17,10 +33,28 @@ const WALLET: Address = Address::repeat_byte(0x41);
 const BOB: Address = Address::repeat_byte(0x42);
 const WETH9: Address = address!("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2");
 const MORPHO: Address = address!("BBBBBbbBBb9cC5e90e3b3Af64bdAF62C37EEFFCb");
+const FIFTY_ETH: U256 = uint!(50000000000000000000_U256); // 50e18
+
+// Mock contracts compiled by build.rs from contracts/mocks.sol
+const MOCK_WETH_INIT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/mock_weth.bin"));
+const MOCK_MORPHO_INIT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/mock_morpho.bin"));
+const MOCK_AAVE_POOL_INIT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/mock_aave_pool.bin"));
 
 // Test helpers
 async fn setup_provider() -> impl Provider + AnvilApi<Ethereum> + Clone {
     let provider = get_provider();
+    fund_accounts(&provider).await;
+    provider
+}
+
+/// Plain local chain without a fork, for tests that only use the mocks.
+async fn setup_local_provider() -> impl Provider + AnvilApi<Ethereum> + Clone {
+    let provider = ProviderBuilder::new().connect_anvil();
+    fund_accounts(&provider).await;
+    provider
+}
+
+async fn fund_accounts(provider: &(impl Provider + AnvilApi<Ethereum>)) {
     provider
         .anvil_set_balance(WALLET, BUDGET + U256::from(10u64.pow(18)))
         .await
@@ -29,7 +63,100 @@ async fn setup_provider() -> impl Provider + AnvilApi<Ethereum> + Clone {
         .anvil_set_balance(BOB, BUDGET + U256::from(10u64.pow(18)))
         .await
         .unwrap();
-    provider
+}
+
+/// Sends `tx` from WALLET, mines it and returns the receipt status.
+async fn send_tx(provider: &(impl Provider + AnvilApi<Ethereum>), tx: TransactionRequest) -> bool {
+    let tx_hash = provider
+        .eth_send_unsigned_transaction(tx.with_from(WALLET))
+        .await
+        .unwrap();
+    provider.evm_mine(None).await.unwrap();
+    let receipt = provider
+        .get_transaction_receipt(tx_hash)
+        .await
+        .unwrap()
+        .unwrap();
+    receipt.status()
+}
+
+async fn deploy_contract(
+    provider: &(impl Provider + AnvilApi<Ethereum>),
+    initcode: &[u8],
+) -> Address {
+    let tx_hash = provider
+        .eth_send_unsigned_transaction(
+            TransactionRequest::default()
+                .with_from(WALLET)
+                .with_deploy_code(initcode.to_vec()),
+        )
+        .await
+        .unwrap();
+    provider.evm_mine(None).await.unwrap();
+    let receipt = provider
+        .get_transaction_receipt(tx_hash)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(receipt.status());
+    receipt.contract_address.unwrap()
+}
+
+struct Mocks {
+    weth: Address,
+    morpho: Address,
+    aave_pool: Address,
+}
+
+/// Deploys the mock WETH and lenders, each lender holding 50 WETH.
+async fn deploy_mocks(provider: &(impl Provider + AnvilApi<Ethereum>)) -> Mocks {
+    let mocks = Mocks {
+        weth: deploy_contract(provider, MOCK_WETH_INIT).await,
+        morpho: deploy_contract(provider, MOCK_MORPHO_INIT).await,
+        aave_pool: deploy_contract(provider, MOCK_AAVE_POOL_INIT).await,
+    };
+    let deposit = TransactionRequest::default()
+        .with_to(mocks.weth)
+        .with_value(FIFTY_ETH * U256::from(2));
+    assert!(send_tx(provider, deposit).await);
+    for lender in [mocks.morpho, mocks.aave_pool] {
+        let transfer = TransactionRequest::default()
+            .with_to(mocks.weth)
+            .with_input(
+                IERC20::transferCall {
+                    to: lender,
+                    value: FIFTY_ETH,
+                }
+                .abi_encode(),
+            );
+        assert!(send_tx(provider, transfer).await);
+    }
+    mocks
+}
+
+/// Runs `fb` on the executor and returns the receipt status.
+async fn execute_flow(
+    provider: &(impl Provider + AnvilApi<Ethereum>),
+    executor: Address,
+    fb: &FlowBuilder,
+    value: U256,
+) -> bool {
+    let tx = TransactionRequest::default()
+        .with_to(executor)
+        .with_value(value)
+        .with_input(fb.build());
+    send_tx(provider, tx).await
+}
+
+/// `flashLoan` calldata borrowing `assets` of `token` and running `inner` in
+/// the callback.
+fn morpho_flashloan(token: Address, assets: U256, inner: &FlowBuilder) -> Vec<u8> {
+    IMorpho::flashLoanCall {
+        token,
+        assets,
+        data: inner.build_raw().into(),
+    }
+    .abi_encode()
 }
 
 async fn deploy_executor(provider: &(impl Provider + AnvilApi<Ethereum>)) -> Address {
@@ -89,87 +216,2374 @@ sol! {
     }
 }
 
-sol! {
-    interface IMorpho {
-        function flashLoan(address token, uint256 assets, bytes calldata data) external;
+sol! {
+    interface IMorpho {
+        function flashLoan(address token, uint256 assets, bytes calldata data) external;
+    }
+}
+
+#[test]
+fn test_execute_actions_selector() {
+    // Verify the hardcoded selector in FlowBuilder matches the keccak256 of the signature
+    use alloy::primitives::keccak256;
+    let hash = keccak256("executeActions()");
+    let expected_selector = &hash[..4];
+    let built = FlowBuilder::empty().build();
+    assert_eq!(
+        &built[..4],
+        expected_selector,
+        "selector drift: update EXECUTE_ACTIONS_SELECTOR"
+    );
+}
+
+#[test]
+fn test_flow_builder_max_data_length() {
+    // Verify that data of exactly u16::MAX bytes is accepted (off-by-one regression guard)
+    let data = vec![0xABu8; u16::MAX as usize];
+    let addr = Address::repeat_byte(0x01);
+    // Should not panic
+    let _calldata = FlowBuilder::empty().call(addr, &data, U256::ZERO).build();
+}
+
+#[test]
+fn test_flow_builder_try_variants() {
+    let addr = Address::repeat_byte(0x01);
+    // Zeroed, so the 4 GiB are only reserved, never touched
+    let too_long = vec![0u8; u32::MAX as usize + 1];
+    let mut fb = FlowBuilder::empty();
+
+    assert_eq!(
+        fb.try_call(addr, &too_long, U256::ZERO).unwrap_err(),
+        FlowError::DataTooLong {
+            len: u32::MAX as usize + 1
+        }
+    );
+    assert!(fb.try_delegatecall(addr, &too_long).is_err());
+    assert!(fb.try_create(addr, &too_long, U256::ZERO).is_err());
+    // Failed helpers push nothing
+    assert!(fb.is_empty());
+
+    assert_eq!(
+        fb.try_set_data_op(0, &[1]).unwrap_err(),
+        FlowError::DataBeforeClear
+    );
+    fb.set_cleardata_op(36);
+    fb.try_set_data_op(4, &[0xff; 32]).unwrap();
+    assert!(fb.try_set_data_op(5, &[0xff; 32]).is_err());
+    fb.try_set_extcodecopy_op(addr, 4, 0, 32).unwrap();
+    assert_eq!(
+        fb.try_set_extcodecopy_op(addr, 0xffff, 0, 0xffff)
+            .unwrap_err(),
+        FlowError::ExtCodeCopyOutOfBounds {
+            data_offset: 65535,
+            size: 65535,
+            buffer_size: 36
+        }
+    );
+    assert_eq!(fb.try_build().unwrap(), fb.build());
+
+    // Raw pushers stay unchecked, but try_build catches the overflow
+    fb.set_data_op(30, &[0xff; 8]);
+    assert!(matches!(
+        fb.try_build_raw(),
+        Err(FlowError::WriteOutOfBounds { offset: 30, .. })
+    ));
+}
+
+#[test]
+#[should_panic(expected = "data length 4294967296 exceeds u32::MAX")]
+fn test_flow_builder_delegatecall_too_long() {
+    let data = vec![0u8; u32::MAX as usize + 1];
+    FlowBuilder::empty().delegatecall(Address::ZERO, &data);
+}
+
+#[test]
+fn test_flow_builder_wide_data() {
+    let executor = Address::repeat_byte(0xee);
+    let addr = Address::repeat_byte(0x01);
+    let narrow = vec![0xabu8; u16::MAX as usize];
+    let wide = vec![0xcdu8; u16::MAX as usize + 1];
+
+    // Payloads past u16::MAX switch to the 32-bit opcodes
+    let mut fb = FlowBuilder::empty();
+    fb.call(addr, &narrow, U256::ZERO);
+    let ops: Vec<u8> = fb.iter().map(|a| a.encode()[0]).collect();
+    assert_eq!(
+        ops,
+        [OP_SETADDR, OP_SETVALUE, OP_CLEARDATA, OP_SETDATA, OP_CALL]
+    );
+    let mut fb = FlowBuilder::empty();
+    fb.call(addr, &wide, U256::ZERO).delegatecall(addr, &wide);
+    assert_eq!(
+        fb.get(2).unwrap().encode()[..5],
+        [OP_CLEARDATA32, 0, 1, 0, 0]
+    );
+    assert_eq!(
+        fb.get(3).unwrap().encode()[..9],
+        [OP_SETDATA32, 0, 0, 0, 0, 0, 1, 0, 0]
+    );
+    assert_eq!(fb.validate(), Ok(()));
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    assert_eq!(asm::parse(&asm::print(&fb)).unwrap().build(), fb.build());
+
+    let effects = Interpreter::new(executor, |_: &Request<'_>| Response::success())
+        .execute(&fb.build())
+        .unwrap();
+    let Effect::Call { calldata, .. } = &effects[0] else {
+        panic!("expected a call, got {:?}", effects[0]);
+    };
+    assert_eq!(*calldata, wide);
+
+    // The 32-bit pushers keep the wide encoding whatever their operands
+    let mut fb = FlowBuilder::empty();
+    fb.set_cleardata32_op(70_032)
+        .set_extcodecopy32_op(addr, 0, 70_000, 32)
+        .set_data32_op(70_000, &[1; 32]);
+    assert_eq!(fb.validate(), Ok(()));
+    let encoded = fb.get(1).unwrap().encode();
+    assert_eq!(encoded[0], OP_EXTCODECOPY32);
+    assert_eq!(encoded.len(), 1 + 20 + 3 * 4);
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    assert_eq!(
+        fb.try_set_data32_op(70_001, &[1; 32]).unwrap_err(),
+        FlowError::WriteOutOfBounds {
+            offset: 70_001,
+            len: 32,
+            buffer_size: 70_032
+        }
+    );
+    assert_eq!(
+        Action::ClearData32 { size: 32 }.encode(),
+        [OP_CLEARDATA32, 0, 0, 0, 32]
+    );
+    // Plain mnemonics pick the width, suffixed ones always assemble wide
+    assert_eq!(
+        asm::parse("CLEARDATA 0x10000; CLEARDATA 32; CLEARDATA32 32")
+            .unwrap()
+            .actions(),
+        [
+            Action::ClearData32 { size: 0x10000 },
+            Action::ClearData { size: 32 },
+            Action::ClearData32 { size: 32 },
+        ]
+    );
+}
+
+#[test]
+fn test_decode_wide_small_operands() {
+    let addr = Address::repeat_byte(0x01);
+    // Wide opcodes holding small operands, then a raw jump over the SETFAIL
+    // at byte 51 that only lands right if every action keeps its length
+    let mut stream = vec![OP_CLEARDATA32, 0, 0, 0, 32];
+    stream.extend([OP_SETDATA32, 0, 0, 0, 0, 0, 0, 0, 1, 0xaa]);
+    stream.push(OP_EXTCODECOPY32);
+    stream.extend(addr.as_slice());
+    stream.extend([0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2]);
+    stream.extend([OP_JUMP, 0, 52, OP_SETFAIL, OP_CLEARFAIL]);
+
+    let actions = decode_raw(&stream).unwrap();
+    assert_eq!(
+        actions[..4],
+        [
+            Action::ClearData32 { size: 32 },
+            Action::SetData32 {
+                offset: 0,
+                data: vec![0xaa]
+            },
+            Action::ExtCodeCopy32 {
+                source: addr,
+                data_offset: 1,
+                code_offset: 0,
+                size: 2
+            },
+            Action::Jump {
+                dest: JumpDest::Offset(52)
+            },
+        ]
+    );
+    let fb = FlowBuilder::from(actions);
+    assert_eq!(fb.build_raw(), stream);
+    assert_eq!(asm::parse(&asm::print(&fb)).unwrap().build_raw(), stream);
+    assert_eq!(fb.action_index(51), Some(4));
+    assert_eq!(fb.action_index(52), Some(5));
+}
+
+#[test]
+fn test_validate() {
+    let addr = Address::repeat_byte(0x01);
+    let mut fb = FlowBuilder::empty();
+    fb.create(addr, &[0x60; 10], U256::ZERO)
+        .call(addr, &[1, 2, 3], U256::ZERO)
+        .delegatecall(addr, &[4])
+        .set_callback(addr)
+        .set_fail();
+    assert_eq!(fb.validate(), Ok(()));
+
+    let mut fb = FlowBuilder::empty();
+    fb.set_data_op(0, &[1])
+        .call_op()
+        .set_cleardata_op(32)
+        .set_data_op(16, &[0xff; 32])
+        .set_extcodecopy_op(addr, 0, 0, 33)
+        .set_addr_op(addr)
+        .delegatecall_op();
+    let errors = fb.validate().unwrap_err();
+    assert_eq!(
+        errors,
+        vec![
+            ValidationError {
+                index: 0,
+                error: FlowError::DataBeforeClear
+            },
+            ValidationError {
+                index: 1,
+                error: FlowError::MissingTarget
+            },
+            ValidationError {
+                index: 3,
+                error: FlowError::WriteOutOfBounds {
+                    offset: 16,
+                    len: 32,
+                    buffer_size: 32
+                }
+            },
+            ValidationError {
+                index: 4,
+                error: FlowError::ExtCodeCopyOutOfBounds {
+                    data_offset: 0,
+                    size: 33,
+                    buffer_size: 32
+                }
+            },
+        ]
+    );
+    assert_eq!(fb.try_build(), Err(FlowError::DataBeforeClear));
+}
+
+#[test]
+fn test_flow_builder_create() {
+    let calldata = FlowBuilder::empty()
+        .create(Address::ZERO, "LALA".as_bytes(), U256::from(10))
+        .optimize()
+        .build();
+    assert_eq!(
+        calldata,
+        hex!("c94f554d04000000000000000000000000000000000000000000000000000000000000000a01000402000000044c414c4107")
+    );
+}
+
+#[test]
+fn test_flow_builder_create2() {
+    let executor = Address::repeat_byte(0xee);
+    let salt = b256!("0000000000000000000000000000000000000000000000000000000000000042");
+    let initcode = b"LALA";
+    let predicted = executor.create2_from_code(salt, initcode);
+
+    // The predicted address lets the optimizer drop the SETADDR
+    let mut fb = FlowBuilder::for_executor(executor);
+    assert_eq!(fb.create2_address(salt, initcode), Some(predicted));
+    fb.create2(salt, initcode, U256::from(10))
+        .set_addr_op(predicted)
+        .call_op()
+        .optimize();
+    assert_eq!(
+        fb.actions()[3],
+        Action::Create2 {
+            salt,
+            created_address: Some(predicted),
+        }
+    );
+    assert_eq!(fb.len(), 5);
+    assert_eq!(
+        fb.build(),
+        hex!("c94f554d04000000000000000000000000000000000000000000000000000000000000000a01000402000000044c414c4113000000000000000000000000000000000000000000000000000000000000004206")
+    );
+    let text = asm::print(&fb);
+    assert!(text.contains(&format!("CREATE2 {salt} {predicted}")));
+    assert_eq!(asm::parse(&text).unwrap().actions(), fb.actions());
+
+    // Without the executor address nothing is predicted
+    let mut blind = FlowBuilder::empty();
+    blind
+        .create2(salt, initcode, U256::from(10))
+        .set_addr_op(predicted)
+        .call_op()
+        .optimize();
+    assert_eq!(blind.len(), 6);
+    assert_eq!(decode(&fb.build()).unwrap()[3], blind.actions()[3]);
+
+    // The interpreter deploys at the predicted address
+    let mut interpreter = Interpreter::new(executor, |request: &Request<'_>| {
+        if let Request::Create { address, .. } = request {
+            assert_eq!(*address, predicted);
+        }
+        Response::success()
+    });
+    let effects = interpreter.execute(&fb.build()).unwrap();
+    assert!(matches!(
+        effects[..],
+        [
+            Effect::Create {
+                address: Some(created),
+                ..
+            },
+            Effect::Call { target, .. },
+        ] if created == predicted && target == predicted
+    ));
+
+    // A failed deployment reverts with the init code's revert data under
+    // SETFAIL, and only clears the success flag without it
+    let mut interpreter = Interpreter::new(executor, |_: &Request<'_>| {
+        Response::failure().with_return_data(vec![0xde, 0xad])
+    });
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail().create2(salt, initcode, U256::ZERO);
+    let create_offset = fb.build_raw().len() - 33;
+    assert_eq!(
+        interpreter.execute(&fb.build()),
+        Err(Revert::ActionFailed {
+            offset: create_offset,
+            reason: vec![0xde, 0xad],
+        })
+    );
+    let mut lenient = FlowBuilder::empty();
+    lenient.create2(salt, initcode, U256::ZERO);
+    let effects = interpreter.execute(&lenient.build()).unwrap();
+    assert!(matches!(
+        effects[..],
+        [Effect::Create { address: None, .. }]
+    ));
+}
+
+#[test]
+fn test_flow_builder_call() {
+    let addr_a = Address::repeat_byte(0x41);
+    let calldata = FlowBuilder::empty()
+        .call(addr_a, &vec![98, 99], U256::ZERO)
+        .optimize()
+        .build();
+    assert_eq!(
+        calldata,
+        hex!("c94f554d0341414141414141414141414141414141414141410100020200000002626306")
+    );
+}
+
+#[test]
+fn test_flow_builder_delegatecall() {
+    let addr_b = Address::repeat_byte(0x42);
+    let calldata = FlowBuilder::empty()
+        .delegatecall(addr_b, &vec![70, 71])
+        .optimize()
+        .build();
+    assert_eq!(
+        calldata,
+        hex!("c94f554d0342424242424242424242424242424242424242420100020200000002464708")
+    );
+}
+
+#[test]
+fn test_flow_builder_combined_operations() {
+    let addr_a = Address::repeat_byte(0x41);
+    let addr_b = Address::repeat_byte(0x42);
+    let calldata = FlowBuilder::empty()
+        .create(Address::ZERO, "LALA".as_bytes(), U256::from(10))
+        .call(addr_a, &vec![98, 99], U256::ZERO)
+        .delegatecall(addr_b, &vec![70, 71])
+        .optimize()
+        .build();
+    assert_eq!(
+        calldata,
+        hex!("c94f554d04000000000000000000000000000000000000000000000000000000000000000a01000402000000044c414c410703414141414141414141414141414141414141414101000202000000026263060342424242424242424242424242424242424242420200000002464708")
+    );
+}
+
+#[test]
+fn test_decode_roundtrip() {
+    let addr_a = Address::repeat_byte(0x41);
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .set_callback(addr_a)
+        .create(Address::ZERO, "LALA".as_bytes(), U256::from(10))
+        .call(addr_a, &[0x11; 40], U256::from(1))
+        .set_extcodecopy_op(addr_a, 4, 0, 16)
+        .delegatecall(addr_a, &[70, 71])
+        .clear_fail();
+
+    let raw = fb.build_raw();
+    let decoded = decode(&raw).unwrap();
+    assert_eq!(decoded.len(), 17);
+    assert_eq!(
+        decoded[5],
+        Action::Create {
+            created_address: None
+        }
+    );
+    assert_eq!(
+        decoded.iter().flat_map(|a| a.encode()).collect::<Vec<_>>(),
+        raw
+    );
+
+    // The selector prefix is optional
+    assert_eq!(decode(&fb.build()).unwrap(), decoded);
+    assert_eq!(
+        FlowBuilder::from_bytecode(&raw).unwrap().build(),
+        fb.build()
+    );
+}
+
+#[test]
+fn test_decode_stops_at_eof() {
+    // SETFAIL, EOF, then bytes the executor never reads
+    let decoded = decode(&hex!("0a00ffff")).unwrap();
+    assert_eq!(decoded, vec![Action::SetFail]);
+    assert_eq!(decode(&[]).unwrap(), vec![]);
+}
+
+#[test]
+fn test_decode_errors() {
+    // SETDATA claims 4 bytes of payload but only 2 follow
+    assert_eq!(
+        decode(&hex!("0a02000000046263")),
+        Err(DecodeError::Truncated {
+            offset: 6,
+            opcode: 0x02
+        })
+    );
+    // SETADDR with a short address
+    assert_eq!(
+        decode(&hex!("c94f554d034141")),
+        Err(DecodeError::Truncated {
+            offset: 1,
+            opcode: 0x03
+        })
+    );
+    assert_eq!(
+        decode(&hex!("0aee")),
+        Err(DecodeError::UnknownOpcode {
+            offset: 1,
+            opcode: 0xee
+        })
+    );
+}
+
+#[test]
+fn test_asm_parse() {
+    let fb: FlowBuilder = "
+        # WETH9.transfer(0x4141..41, 1)
+        SETFAIL
+        SETADDR 0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2; SETVALUE 1.5e18
+        CLEARDATA 0x44
+        SETDATA 0 0xa9059cbb
+        SETDATA 4 0x0000000000000000000000004141414141414141414141414141414141414141 0x0000000000000000000000000000000000000000000000000000000000000001
+        call
+    "
+    .parse()
+    .unwrap();
+
+    let mut transfer = hex!("a9059cbb").to_vec();
+    transfer.extend([0u8; 12]);
+    transfer.extend([0x41u8; 20]);
+    transfer.extend(U256::from(1).to_be_bytes::<32>());
+    let expected = FlowBuilder::empty()
+        .set_fail()
+        .set_addr_op(WETH9)
+        .set_value_op(uint!(1500000000000000000_U256))
+        .set_cleardata_op(68)
+        .set_data_op(0, &transfer[..4])
+        .set_data_op(4, &transfer[4..])
+        .call_op()
+        .build();
+    assert_eq!(fb.build(), expected);
+}
+
+#[test]
+fn test_asm_errors() {
+    let err = asm::parse("SETFAIL\nSETADDR 0x1234").unwrap_err();
+    assert_eq!(err.line, 2);
+    assert!(asm::parse("RETURN 3").is_err());
+    assert!(asm::parse("CLEARDATA 4294967296").is_err());
+    assert!(asm::parse("COPYRETURN 0 0 65536").is_err());
+    assert!(asm::parse("SETDATA 0 0xabc").is_err());
+    assert!(asm::parse("SETDATA 0 0x {\nCALL").is_err());
+    assert!(asm::parse("CALL }").is_err());
+}
+
+#[test]
+fn test_asm_roundtrip_nested() {
+    let approve_calldata = IERC20::approveCall {
+        spender: MORPHO,
+        value: TEN_ETH,
+    }
+    .abi_encode();
+    let inner = FlowBuilder::empty()
+        .call(WETH9, &approve_calldata, U256::ZERO)
+        .build_raw();
+    let flashloan_calldata = IMorpho::flashLoanCall {
+        token: WETH9,
+        assets: TEN_ETH,
+        data: inner.into(),
+    }
+    .abi_encode();
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .set_callback(MORPHO)
+        .call(MORPHO, &flashloan_calldata, U256::ZERO)
+        .create(Address::ZERO, &[], TEN_ETH);
+
+    let text = fb.to_string();
+    assert!(text.contains("SETVALUE 1e19"));
+    assert!(text.contains("0000000083 {\n"));
+    assert!(text.contains("\n} 0x00"));
+    assert!(text.contains("    SETADDR 0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2\n"));
+
+    // text -> bytecode -> text is lossless
+    let reparsed = asm::parse(&text).unwrap();
+    assert_eq!(reparsed.build(), fb.build());
+    assert_eq!(reparsed.to_string(), text);
+    let decoded = FlowBuilder::from_bytecode(&fb.build()).unwrap();
+    assert_eq!(decoded.build(), fb.build());
+}
+
+#[test]
+fn test_flow_builder_editing() {
+    let addr_a = Address::repeat_byte(0x41);
+    let addr_b = Address::repeat_byte(0x42);
+    let mut fb = FlowBuilder::empty();
+    fb.call(addr_a, &[98, 99], U256::ZERO);
+    assert_eq!(fb.len(), 5);
+    assert_eq!(fb[0], Action::SetAddr { addr: addr_a });
+    assert_eq!(fb.iter().filter(|a| **a == Action::Call).count(), 1);
+
+    // Retarget the call and make it must-succeed
+    let old = fb.replace(0, Action::SetAddr { addr: addr_b });
+    assert_eq!(old, Action::SetAddr { addr: addr_a });
+    fb.insert(0, Action::SetFail);
+    assert_eq!(fb.remove(2), Action::SetValue { value: U256::ZERO });
+
+    // Swap the payload for a longer one
+    let removed = fb.splice(
+        2..4,
+        [
+            Action::ClearData { size: 3 },
+            Action::SetData {
+                offset: 0,
+                data: vec![1, 2, 3],
+            },
+        ],
+    );
+    assert_eq!(removed.len(), 2);
+    if let Action::SetData { data, .. } = &mut fb[3] {
+        data[2] = 4;
+    }
+
+    let rebuilt = FlowBuilder::from(fb.clone().into_actions());
+    assert_eq!(rebuilt.build(), fb.build());
+    assert_eq!(
+        fb.build_raw(),
+        hex!("0a034242424242424242424242424242424242424242010003020000000301020406")
+    );
+}
+
+#[test]
+fn test_optimizer_keeps_cleardata_over_dirty_buffer() {
+    // The second CLEARDATA has the same size as the buffer but must still
+    // zero byte 0, which the first call left non-zero.
+    let addr = Address::repeat_byte(0x41);
+    let mut fb = FlowBuilder::empty();
+    fb.set_addr_op(addr)
+        .set_cleardata_op(4)
+        .set_data_op(0, &[1])
+        .call_op()
+        .set_cleardata_op(4)
+        .set_data_op(1, &[2])
+        .call_op();
+    let original = fb.actions().to_vec();
+    fb.optimize();
+    assert_eq!(fb.actions(), &original[..]);
+
+    // Fully overwriting the dirty bytes makes the second CLEARDATA dead, and
+    // the trailing ones are never read.
+    let mut fb = FlowBuilder::empty();
+    fb.call(addr, &[98, 99], U256::ZERO)
+        .call(addr, &[70, 0], U256::ZERO)
+        .set_cleardata_op(2)
+        .set_cleardata_op(2)
+        .optimize();
+    assert_eq!(
+        fb.build_raw(),
+        hex!("03414141414141414141414141414141414141414101000202000000026263060200000002460006")
+    );
+}
+
+#[test]
+fn test_optimizer_out_of_bounds_write() {
+    // Used to panic while splicing past the end of the tracked buffer
+    let mut fb = FlowBuilder::empty();
+    fb.set_cleardata_op(2)
+        .set_data_op(1, &[1, 2, 3])
+        .set_data_op(1, &[1, 2, 3])
+        .set_addr_op(Address::repeat_byte(0x41))
+        .call_op();
+    let original = fb.actions().to_vec();
+    fb.optimize();
+    assert_eq!(fb.actions(), &original[..]);
+}
+
+#[test]
+fn test_optimizer_callbacks_and_equivalence() {
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .set_callback(MORPHO)
+        .set_callback(MORPHO)
+        .call(MORPHO, &[1], U256::ZERO)
+        // The first call may have consumed the callback
+        .set_callback(MORPHO)
+        .call(MORPHO, &[1], U256::ZERO)
+        .set_fail();
+    let original = fb.actions().to_vec();
+    fb.optimize();
+    assert_eq!(fb.len(), original.len() - 7);
+    assert_eq!(check_equivalence(&original, fb.actions()), Ok(()));
+
+    // Dropping the second SETCALLBACK is observable
+    let mut broken = original.clone();
+    broken.remove(8);
+    let err = check_equivalence(&original, &broken).unwrap_err();
+    assert_eq!(err.index, 1);
+
+    // So is dropping SETFAIL, or a trailing SETCALLBACK
+    assert!(check_equivalence(&original, &original[1..]).is_err());
+    let mut trailing = original.clone();
+    trailing.push(Action::SetCallback {
+        callback_address: WETH9,
+    });
+    assert!(check_equivalence(&original, &trailing).is_err());
+}
+
+#[test]
+fn test_interpreter_effects() {
+    let executor = Address::repeat_byte(0xee);
+    let mut fb = FlowBuilder::empty();
+    fb.call(WETH9, &[1, 2], TWO_ETH)
+        // Value is reset after CALL
+        .call_op()
+        .create(create_address(executor, 1), &[0xfe], TEN_ETH)
+        .call_op()
+        .set_callback(MORPHO);
+
+    let mut interpreter = Interpreter::new(executor, |_: &Request<'_>| Response::success());
+    let effects = interpreter.execute(&fb.build()).unwrap();
+    assert_eq!(
+        effects,
+        vec![
+            Effect::Call {
+                depth: 0,
+                target: WETH9,
+                value: TWO_ETH,
+                gas: None,
+                calldata: vec![1, 2],
+                success: true,
+            },
+            Effect::Call {
+                depth: 0,
+                target: WETH9,
+                value: U256::ZERO,
+                gas: None,
+                calldata: vec![1, 2],
+                success: true,
+            },
+            Effect::Create {
+                depth: 0,
+                address: Some(create_address(executor, 1)),
+                value: TEN_ETH,
+                initcode: vec![0xfe],
+            },
+            // CREATE sets the target to the new contract
+            Effect::Call {
+                depth: 0,
+                target: create_address(executor, 1),
+                value: U256::ZERO,
+                gas: None,
+                calldata: vec![0xfe],
+                success: true,
+            },
+            Effect::SetCallback {
+                depth: 0,
+                address: MORPHO,
+            },
+        ]
+    );
+    assert_eq!(interpreter.callback(), MORPHO);
+    assert_eq!(
+        create_address(WALLET, 0),
+        address!("c088f75b5733d097f266010c1502399a53bdfdbd")
+    );
+
+    // Unknown opcodes panic like the contract
+    assert_eq!(
+        interpreter.execute(&[0xc9, 0x4f, 0x55, 0x4d, 0x0a, 0xff]),
+        Err(Revert::Panic {
+            offset: 1,
+            code: 0x21
+        })
+    );
+    // Writes past the end of the buffer revert, whether from the word loop,
+    // the tail or EXTCODECOPY
+    for data in [&[1u8, 2][..], &[1; 32]] {
+        let mut oob = FlowBuilder::empty();
+        oob.set_cleardata_op(1).set_data_op(0, data);
+        assert_eq!(
+            interpreter.execute(&oob.build()),
+            Err(Revert::TxDataOutOfBounds { offset: 3 })
+        );
+    }
+    let mut oob = FlowBuilder::empty();
+    oob.set_cleardata_op(4).set_extcodecopy_op(WETH9, 2, 0, 4);
+    assert_eq!(
+        interpreter.execute(&oob.build()),
+        Err(Revert::TxDataOutOfBounds { offset: 3 })
+    );
+}
+
+#[test]
+fn test_interpreter_txdata_bounds() {
+    let executor = Address::repeat_byte(0xee);
+    let mut interpreter = Interpreter::new(executor, |_: &Request<'_>| {
+        Response::success().with_return_data(vec![0; 64])
+    });
+    // Every word or slice that runs one byte past a 32-byte buffer reverts
+    // like _checkRange, after the call that fills the return data
+    let actions = [
+        Action::SetValueFromData { data_offset: 1 },
+        Action::CopyReturn {
+            ret_offset: 0,
+            data_offset: 1,
+            size: 32,
+        },
+        Action::SelfBalance { data_offset: 1 },
+        Action::TokenBalance {
+            token: WETH9,
+            holder: BOB,
+            data_offset: 1,
+        },
+        Action::JumpIfWord {
+            data_offset: 1,
+            cmp: Cmp::Eq,
+            value: U256::ZERO,
+            dest: JumpDest::Offset(0),
+        },
+        Action::Arith {
+            op: ArithOp::Add,
+            dst: 1,
+            lhs: 0,
+            rhs: 0,
+        },
+        Action::Arith {
+            op: ArithOp::Add,
+            dst: 0,
+            lhs: 1,
+            rhs: 0,
+        },
+        Action::Arith {
+            op: ArithOp::Add,
+            dst: 0,
+            lhs: 0,
+            rhs: 1,
+        },
+        Action::ArithImm {
+            op: ArithOp::Add,
+            dst: 0,
+            lhs: 1,
+            value: U256::from(1),
+        },
+        Action::Log {
+            tag: B256::ZERO,
+            data_offset: 1,
+            size: 32,
+        },
+        Action::StoreReg {
+            register: 0,
+            data_offset: 1,
+        },
+        Action::LoadReg {
+            register: 0,
+            data_offset: 1,
+        },
+        // The word is checked before the register index
+        Action::StoreReg {
+            register: 16,
+            data_offset: 1,
+        },
+    ];
+    for action in actions {
+        let fb = FlowBuilder::from(vec![
+            Action::SetAddr { addr: WETH9 },
+            Action::Call,
+            Action::ClearData { size: 32 },
+            action.clone(),
+        ]);
+        let revert = interpreter.execute(&fb.build()).unwrap_err();
+        assert_eq!(
+            revert,
+            Revert::TxDataOutOfBounds { offset: 25 },
+            "{action:?}"
+        );
+        assert_eq!(revert.reason(), RevertReason::Error("TXDATA_OOB".into()));
+    }
+}
+
+#[test]
+fn test_interpreter_flash_loan_callback() {
+    sol! {
+        interface IMorphoFlashLoanCallback {
+            function onMorphoFlashLoan(uint256 assets, bytes calldata data) external;
+        }
+    }
+
+    // Mock Morpho lends by calling back into the executor
+    let morpho = |request: &Request<'_>| match request {
+        Request::Call {
+            target, calldata, ..
+        } if *target == MORPHO => {
+            let call = IMorpho::flashLoanCall::abi_decode(calldata).unwrap();
+            let callback = IMorphoFlashLoanCallback::onMorphoFlashLoanCall {
+                assets: call.assets,
+                data: call.data,
+            };
+            Response::success().reenter(MORPHO, callback.abi_encode())
+        }
+        _ => Response::success(),
+    };
+
+    let mut inner = FlowBuilder::empty();
+    inner.call(WETH9, &[0xaa], U256::ZERO);
+    let flash_loan = IMorpho::flashLoanCall {
+        token: WETH9,
+        assets: TEN_ETH,
+        data: inner.build_raw().into(),
+    }
+    .abi_encode();
+
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .set_callback(MORPHO)
+        .call(MORPHO, &flash_loan, U256::ZERO);
+    let mut interpreter = Interpreter::new(Address::repeat_byte(0xee), morpho);
+    let effects = interpreter.execute(&fb.build()).unwrap();
+    assert_eq!(effects.len(), 4);
+    assert_eq!(
+        effects[2],
+        Effect::Callback {
+            depth: 1,
+            sender: MORPHO,
+        }
+    );
+    assert!(matches!(
+        &effects[3],
+        Effect::Call { depth: 1, target, calldata, .. } if *target == WETH9 && calldata == &[0xaa]
+    ));
+    // The callback consumed the registration
+    assert_eq!(interpreter.callback(), Address::ZERO);
+
+    // Without SETCALLBACK the callback is rejected and the loan fails
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail().call(MORPHO, &flash_loan, U256::ZERO);
+    assert_eq!(
+        interpreter.execute(&fb.build()),
+        Err(Revert::ActionFailed {
+            offset: fb.build_raw().len() - 1,
+            reason: RevertReason::Error("Invalid callback sender".into()).encode(),
+        })
+    );
+
+    // Without SETFAIL the failure is tolerated and its effects are dropped
+    fb.remove(0);
+    let effects = interpreter.execute(&fb.build()).unwrap();
+    assert!(matches!(
+        &effects[..],
+        [Effect::Call { success: false, .. }]
+    ));
+}
+
+#[test]
+fn test_copy_return() {
+    let target = Address::repeat_byte(0x11);
+    let transfer = IERC20::transferCall {
+        to: BOB,
+        value: U256::ZERO,
+    }
+    .abi_encode();
+
+    let mut fb = FlowBuilder::empty();
+    assert_eq!(
+        fb.try_copy_return(0, 0, 32).unwrap_err(),
+        FlowError::DataBeforeClear
+    );
+    fb.call(target, &[1], U256::ZERO)
+        .set_addr_op(WETH9)
+        .set_cleardata_op(transfer.len() as u16)
+        .set_data_op(0, &transfer);
+    assert_eq!(
+        fb.try_copy_return(0, 40, 32).unwrap_err(),
+        FlowError::CopyReturnOutOfBounds {
+            data_offset: 40,
+            size: 32,
+            buffer_size: 68,
+        }
+    );
+    // Patch the second returned word into the transfer amount
+    fb.copy_return(32, 36, 32).call_op().optimize();
+    assert_eq!(
+        fb[fb.len() - 2].encode(),
+        vec![0x0c, 0x00, 0x20, 0x00, 0x24, 0x00, 0x20]
+    );
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    let text = asm::print(&fb);
+    assert!(text.contains("COPYRETURN 32 36 32"));
+    assert_eq!(asm::parse(&text).unwrap().build(), fb.build());
+
+    let returned = (U256::from(7), TWO_ETH).abi_encode();
+    let mut interpreter = Interpreter::new(Address::repeat_byte(0xee), |r: &Request<'_>| match r {
+        Request::Call { target: t, .. } if *t == target => {
+            Response::success().with_return_data(returned.clone())
+        }
+        _ => Response::success(),
+    });
+    let effects = interpreter.execute(&fb.build()).unwrap();
+    let Effect::Call { calldata, .. } = &effects[1] else {
+        panic!("expected a call, got {:?}", effects[1]);
+    };
+    assert_eq!(
+        IERC20::transferCall::abi_decode(calldata).unwrap().value,
+        TWO_ETH
+    );
+
+    // Reading past the end of the return data reverts
+    let mut fb = FlowBuilder::empty();
+    fb.call(target, &[1], U256::ZERO).copy_return(64, 0, 1);
+    assert_eq!(
+        interpreter.execute(&fb.build()),
+        Err(Revert::ReturnDataOutOfBounds {
+            offset: fb.build_raw().len() - 7
+        })
+    );
+}
+
+#[test]
+fn test_balance_opcodes() {
+    let executor = Address::repeat_byte(0xee);
+    let transfer = IERC20::transferCall {
+        to: BOB,
+        value: U256::ZERO,
+    }
+    .abi_encode();
+
+    let mut fb = FlowBuilder::empty();
+    assert_eq!(
+        fb.try_write_balance(0).unwrap_err(),
+        FlowError::DataBeforeClear
+    );
+    fb.set_addr_op(WETH9)
+        .set_cleardata_op(transfer.len() as u16)
+        .set_data_op(0, &transfer);
+    assert_eq!(
+        fb.try_write_token_balance(WETH9, executor, 40).unwrap_err(),
+        FlowError::WordOutOfBounds {
+            data_offset: 40,
+            buffer_size: 68,
+        }
+    );
+    // Send the full WETH balance, then report the ETH balance to BOB
+    fb.write_token_balance(WETH9, executor, 36)
+        .call_op()
+        .set_addr_op(BOB)
+        .set_cleardata_op(32)
+        .write_balance(0)
+        .call_op()
+        .optimize();
+    assert_eq!(fb.validate(), Ok(()));
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    let text = asm::print(&fb);
+    assert!(text.contains(&format!("TOKENBALANCE {WETH9} {executor} 36")));
+    assert!(text.contains("SELFBALANCE 0"));
+    assert_eq!(asm::parse(&text).unwrap().build(), fb.build());
+
+    struct Chain;
+    impl Responder for Chain {
+        fn respond(&mut self, request: &Request<'_>) -> Response {
+            match request {
+                Request::StaticCall {
+                    target, calldata, ..
+                } if *target == WETH9 => {
+                    assert_eq!(calldata[..4], hex!("70a08231"));
+                    Response::success().with_return_data(TWO_ETH.to_be_bytes::<32>())
+                }
+                Request::StaticCall { .. } => Response::failure(),
+                _ => Response::success(),
+            }
+        }
+
+        fn balance(&mut self, _address: Address) -> U256 {
+            TEN_ETH
+        }
+    }
+
+    let mut interpreter = Interpreter::new(executor, Chain);
+    let effects = interpreter.execute(&fb.build()).unwrap();
+    let [Effect::Call {
+        calldata: first, ..
+    }, Effect::Call {
+        calldata: second, ..
+    }] = &effects[..]
+    else {
+        panic!("expected two calls, got {effects:?}");
+    };
+    assert_eq!(
+        IERC20::transferCall::abi_decode(first).unwrap().value,
+        TWO_ETH
+    );
+    assert_eq!(U256::from_be_slice(second), TEN_ETH);
+
+    // A failing balanceOf reverts the flow
+    let mut fb = FlowBuilder::empty();
+    fb.set_cleardata_op(32)
+        .write_token_balance(BOB, executor, 0);
+    assert_eq!(
+        interpreter.execute(&fb.build()),
+        Err(Revert::BalanceQueryFailed { offset: 3 })
+    );
+}
+
+#[test]
+fn test_jumps() {
+    let executor = Address::repeat_byte(0xee);
+    let primary = Address::repeat_byte(0x01);
+    let fallback = Address::repeat_byte(0x02);
+
+    // Try `primary`, fall back to `fallback` if it fails, then skip the
+    // payout to BOB unless `fallback` reported at least 2 ETH
+    let mut fb = FlowBuilder::empty();
+    fb.clear_fail()
+        .call(primary, &[], U256::ZERO)
+        .jump_if_failed("fallback")
+        .jump("done")
+        .label("fallback")
+        .set_fail()
+        .call(fallback, &[], U256::ZERO)
+        .set_cleardata_op(32)
+        .copy_return(0, 0, 32)
+        .jump_if_word(0, Cmp::Lt, TWO_ETH, "done")
+        .call(BOB, &[], TWO_ETH)
+        .label("done");
+    assert_eq!(fb.validate(), Ok(()));
+
+    let raw = fb.build_raw();
+    let decoded = decode(&raw).unwrap();
+    let dests: Vec<_> = decoded.iter().filter_map(|a| a.jump_dest()).collect();
+    let done = JumpDest::Offset(raw.len() as u16);
+    assert_eq!(dests[1], &done);
+    assert_eq!(dests[2], &done);
+    let JumpDest::Offset(fallback_at) = *dests[0] else {
+        panic!("decoder produced a label");
+    };
+    assert_eq!(raw[fallback_at as usize], OP_SETFAIL);
+    assert_eq!(FlowBuilder::from(decoded.clone()).build_raw(), raw);
+
+    // Decoded offsets print as synthesized labels and re-assemble to the
+    // same bytes
+    let text = asm::print(&FlowBuilder::from(decoded));
+    assert!(text.contains(&format!("L{fallback_at}:")));
+    assert!(text.contains(&format!("JUMPIFWORD 0 LT 2e18 L{}", raw.len())));
+    assert_eq!(asm::parse(&text).unwrap().build_raw(), raw);
+    assert_eq!(asm::parse(&asm::print(&fb)).unwrap().build_raw(), raw);
+
+    let run = |primary_ok: bool, reported: U256| {
+        let responder = move |request: &Request<'_>| match request {
+            Request::Call { target, .. } if *target == primary && !primary_ok => {
+                Response::failure()
+            }
+            Request::Call { target, .. } if *target == fallback => {
+                Response::success().with_return_data(reported.to_be_bytes::<32>())
+            }
+            _ => Response::success(),
+        };
+        let effects = Interpreter::new(executor, responder)
+            .execute(&fb.build())
+            .unwrap();
+        effects
+            .iter()
+            .map(|e| match e {
+                Effect::Call { target, .. } => *target,
+                e => panic!("unexpected effect {e:?}"),
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(run(true, TEN_ETH), [primary]);
+    assert_eq!(run(false, TEN_ETH), [primary, fallback, BOB]);
+    assert_eq!(run(false, U256::ZERO), [primary, fallback]);
+
+    // Label errors
+    let mut fb = FlowBuilder::empty();
+    fb.jump("nowhere");
+    assert_eq!(
+        fb.try_build(),
+        Err(FlowError::UnknownLabel {
+            name: "nowhere".into()
+        })
+    );
+    fb.label("again").label("again");
+    assert_eq!(
+        fb.validate().unwrap_err(),
+        [
+            ValidationError {
+                index: 0,
+                error: FlowError::UnknownLabel {
+                    name: "nowhere".into()
+                },
+            },
+            ValidationError {
+                index: 2,
+                error: FlowError::DuplicateLabel {
+                    name: "again".into()
+                },
+            },
+        ]
+    );
+    assert!(asm::parse("CLEARDATA 32\nSETDATA 0 0x {\nJUMP nowhere\n}").is_err());
+    assert!(asm::parse("1abc:").is_err());
+    assert_eq!(
+        decode(&[OP_JUMPIFWORD, 0, 0, 6]),
+        Err(DecodeError::InvalidOperand {
+            offset: 3,
+            opcode: OP_JUMPIFWORD
+        })
+    );
+
+    // The optimizer does not look past the first label
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .set_fail()
+        .label("loop")
+        .set_fail()
+        .jump("loop");
+    fb.optimize();
+    assert_eq!(fb.len(), 4);
+    let mut without = fb.actions().to_vec();
+    without.remove(2);
+    assert!(check_equivalence(fb.actions(), &without).is_err());
+
+    // An endless loop hits the step limit
+    assert_eq!(
+        Interpreter::new(executor, |_: &Request<'_>| Response::success())
+            .with_step_limit(100)
+            .execute(&fb.build()),
+        Err(Revert::StepLimitExceeded { offset: 2 })
+    );
+}
+
+#[test]
+fn test_staticcall() {
+    let executor = Address::repeat_byte(0xee);
+    let oracle = Address::repeat_byte(0x0a);
+    let probe = IERC20::balanceOfCall { account: executor }.abi_encode();
+
+    // Probe the oracle, then pay BOB the reported amount. The value set
+    // before the probe is still pending for the payout.
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .set_value_op(TWO_ETH)
+        .staticcall(oracle, &probe)
+        .set_value_op(TWO_ETH)
+        .set_addr_op(BOB)
+        .set_cleardata_op(32)
+        .copy_return(0, 0, 32)
+        .call_op()
+        .optimize();
+    assert_eq!(
+        fb.iter()
+            .filter(|a| matches!(a, Action::SetValue { .. }))
+            .count(),
+        1
+    );
+    assert_eq!(fb.validate(), Ok(()));
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    let text = asm::print(&fb);
+    assert!(text.contains("STATICCALL"));
+    assert_eq!(asm::parse(&text).unwrap().build(), fb.build());
+
+    let responder = |request: &Request<'_>| match request {
+        Request::StaticCall {
+            target, calldata, ..
+        } if *target == oracle => {
+            assert_eq!(*calldata, &probe[..]);
+            Response::success().with_return_data(TEN_ETH.to_be_bytes::<32>())
+        }
+        _ => Response::success(),
+    };
+    let effects = Interpreter::new(executor, responder)
+        .execute(&fb.build())
+        .unwrap();
+    assert_eq!(
+        effects,
+        [
+            Effect::StaticCall {
+                depth: 0,
+                target: oracle,
+                gas: None,
+                calldata: probe.clone(),
+                success: true,
+            },
+            Effect::Call {
+                depth: 0,
+                target: BOB,
+                value: TWO_ETH,
+                gas: None,
+                calldata: TEN_ETH.to_be_bytes::<32>().to_vec(),
+                success: true,
+            },
+        ]
+    );
+
+    // A failing probe reverts under SETFAIL
+    let mut interpreter = Interpreter::new(executor, |_: &Request<'_>| Response::failure());
+    assert_eq!(
+        interpreter.execute(&fb.build()),
+        Err(Revert::ActionFailed {
+            offset: 1 + 33 + 21 + 3 + 5 + probe.len(),
+            reason: Vec::new(),
+        })
+    );
+
+    // The executor cannot be re-entered with writes from a static call: the
+    // callback would clear its slot, so the probe fails
+    sol! {
+        interface IMorphoFlashLoanCallback {
+            function onMorphoFlashLoan(uint256 assets, bytes calldata data) external;
+        }
+    }
+    let mut fb = FlowBuilder::empty();
+    fb.set_callback(MORPHO).staticcall(MORPHO, &[]);
+    let reenter = |_: &Request<'_>| {
+        let callback = IMorphoFlashLoanCallback::onMorphoFlashLoanCall {
+            assets: U256::ZERO,
+            data: Default::default(),
+        };
+        Response::success().reenter(MORPHO, callback.abi_encode())
+    };
+    let mut interpreter = Interpreter::new(executor, reenter);
+    let effects = interpreter.execute(&fb.build()).unwrap();
+    assert!(matches!(
+        effects[..],
+        [
+            Effect::SetCallback { .. },
+            Effect::StaticCall { success: false, .. }
+        ]
+    ));
+    assert_eq!(interpreter.callback(), MORPHO);
+}
+
+#[test]
+fn test_word_arithmetic() {
+    let executor = Address::repeat_byte(0xee);
+    let oracle = Address::repeat_byte(0x0a);
+    let transfer = IERC20::transferCall {
+        to: BOB,
+        value: U256::ZERO,
+    }
+    .abi_encode();
+
+    // Transfer 95% of the quoted amount plus a fixed 7 wei
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .staticcall(oracle, &[])
+        .set_addr_op(WETH9)
+        .set_cleardata_op(transfer.len() as u16)
+        .set_data_op(0, &transfer)
+        .copy_return(0, 36, 32)
+        .scale_word(36, U256::from(95), U256::from(100))
+        .arith_imm(ArithOp::Add, 36, 36, U256::from(7))
+        .call_op();
+    assert_eq!(fb.validate(), Ok(()));
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    let text = asm::print(&fb);
+    assert!(text.contains("ARITHI MUL 36 36 95"));
+    assert_eq!(asm::parse(&text).unwrap().build(), fb.build());
+
+    let quote = |quoted: U256| {
+        move |request: &Request<'_>| match request {
+            Request::StaticCall { .. } => {
+                Response::success().with_return_data(quoted.to_be_bytes::<32>())
+            }
+            _ => Response::success(),
+        }
+    };
+    let effects = Interpreter::new(executor, quote(TEN_ETH))
+        .execute(&fb.build())
+        .unwrap();
+    let Some(Effect::Call { calldata, .. }) = effects.last() else {
+        panic!("expected a transfer, got {effects:?}");
+    };
+    assert_eq!(
+        IERC20::transferCall::abi_decode(calldata).unwrap().value,
+        TEN_ETH * U256::from(95) / U256::from(100) + U256::from(7)
+    );
+    assert_eq!(
+        Interpreter::new(executor, quote(U256::MAX)).execute(&fb.build()),
+        Err(Revert::Panic {
+            offset: fb.build_raw().len() - 1 - 3 * 38,
+            code: 0x11
+        })
+    );
+
+    // Word-to-word operations, including division by a zero word
+    let mut fb = FlowBuilder::empty();
+    fb.set_cleardata_op(96)
+        .set_data_op(0, &TEN_ETH.to_be_bytes::<32>())
+        .set_data_op(32, &TWO_ETH.to_be_bytes::<32>())
+        .arith(ArithOp::Sub, 64, 0, 32)
+        .arith(ArithOp::Div, 0, 64, 32)
+        .set_addr_op(BOB)
+        .call_op();
+    let effects = Interpreter::new(executor, quote(U256::ZERO))
+        .execute(&fb.build())
+        .unwrap();
+    let [Effect::Call { calldata, .. }] = &effects[..] else {
+        panic!("expected one call, got {effects:?}");
+    };
+    assert_eq!(U256::from_be_slice(&calldata[..32]), U256::from(4));
+    assert_eq!(U256::from_be_slice(&calldata[64..]), TEN_ETH - TWO_ETH);
+    fb.arith(ArithOp::Div, 0, 0, 32)
+        .arith(ArithOp::Div, 0, 32, 0)
+        .call_op();
+    assert!(matches!(
+        Interpreter::new(executor, quote(U256::ZERO)).execute(&fb.build()),
+        Err(Revert::Panic { code: 0x12, .. })
+    ));
+
+    // Errors
+    let mut fb = FlowBuilder::empty();
+    fb.set_cleardata_op(64);
+    assert_eq!(
+        fb.try_arith(ArithOp::Add, 0, 0, 40).unwrap_err(),
+        FlowError::WordOutOfBounds {
+            data_offset: 40,
+            buffer_size: 64,
+        }
+    );
+    assert_eq!(
+        decode(&[OP_ARITHI, 4, 0, 0, 0, 0]),
+        Err(DecodeError::InvalidOperand {
+            offset: 1,
+            opcode: OP_ARITHI
+        })
+    );
+    assert!(asm::parse("ARITH MOD 0 0 32").is_err());
+
+    // A CLEARDATA whose bytes are read by an arithmetic op is kept
+    let mut fb = FlowBuilder::empty();
+    fb.set_addr_op(BOB)
+        .set_cleardata_op(64)
+        .set_data_op(32, &[1; 32])
+        .call_op()
+        .set_cleardata_op(64)
+        .arith_imm(ArithOp::Add, 0, 32, U256::from(1))
+        .set_data_op(32, &[2; 32])
+        .call_op()
+        .optimize();
+    assert_eq!(
+        fb.iter()
+            .filter(|a| matches!(a, Action::ClearData { .. }))
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn test_call_gas_limit() {
+    let executor = Address::repeat_byte(0xee);
+
+    let mut fb = FlowBuilder::empty();
+    fb.clear_fail()
+        .call_with_gas(BOB, &[0x01], U256::ZERO, 50_000)
+        .call(BOB, &[0x02], U256::ZERO)
+        .delegatecall_op()
+        .set_gas_op(200_000)
+        .staticcall_op();
+    assert_eq!(fb.validate(), Ok(()));
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    let text = asm::print(&fb);
+    assert!(text.contains("SETGAS 50000"));
+    assert_eq!(asm::parse(&text).unwrap().build(), fb.build());
+    assert_eq!(
+        asm::parse("SETGAS 5e4").unwrap().actions(),
+        [Action::SetGas { gas: 50_000 }]
+    );
+    assert!(asm::parse("SETGAS 5e9").is_err());
+
+    // A target that needs more gas than the cap fails, and the cap is reset
+    // for the calls that follow
+    let responder = |request: &Request<'_>| match request {
+        Request::Call { gas: Some(gas), .. } if *gas < 100_000 => Response::failure(),
+        _ => Response::success(),
+    };
+    let effects = Interpreter::new(executor, responder)
+        .execute(&fb.build())
+        .unwrap();
+    let gas: Vec<_> = effects
+        .iter()
+        .map(|e| match e {
+            Effect::Call { gas, success, .. }
+            | Effect::DelegateCall { gas, success, .. }
+            | Effect::StaticCall { gas, success, .. } => (*gas, *success),
+            e => panic!("unexpected effect {e:?}"),
+        })
+        .collect();
+    assert_eq!(
+        gas,
+        [
+            (Some(50_000), false),
+            (None, true),
+            (None, true),
+            (Some(200_000), true)
+        ]
+    );
+
+    // Overridden and unused gas caps are dropped, caps a call consumed are not
+    let mut fb = FlowBuilder::empty();
+    fb.set_gas_op(0)
+        .set_gas_op(10_000)
+        .call_with_gas(BOB, &[], U256::ZERO, 50_000)
+        .set_gas_op(50_000)
+        .call_op()
+        .set_gas_op(50_000)
+        .optimize();
+    assert_eq!(
+        fb.iter()
+            .filter(|a| matches!(a, Action::SetGas { .. }))
+            .count(),
+        2
+    );
+    let uncapped: Vec<_> = fb
+        .iter()
+        .filter(|a| !matches!(a, Action::SetGas { .. }))
+        .cloned()
+        .collect();
+    assert!(check_equivalence(fb.actions(), &uncapped).is_err());
+}
+
+#[test]
+fn test_profit_guard() {
+    let executor = Address::repeat_byte(0xee);
+
+    let mut fb = FlowBuilder::empty();
+    assert_eq!(
+        fb.try_require_profit(WETH9, U256::ZERO).unwrap_err(),
+        FlowError::MissingSnapshot { token: WETH9 }
+    );
+    // Wrap 2 ETH: the WETH balance grows, the ETH balance shrinks
+    fb.begin_profit_check(WETH9)
+        .begin_profit_check(Address::ZERO)
+        .call(WETH9, &[], TWO_ETH)
+        .require_profit(WETH9, TWO_ETH)
+        .require_profit(Address::ZERO, U256::ZERO);
+    assert_eq!(fb.validate(), Ok(()));
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    let text = asm::print(&fb);
+    assert!(text.contains(&format!("SNAPSHOT {WETH9}")));
+    assert_eq!(asm::parse(&text).unwrap().build(), fb.build());
+
+    struct Chain {
+        eth: U256,
+        weth: U256,
+    }
+    impl Responder for Chain {
+        fn respond(&mut self, request: &Request<'_>) -> Response {
+            match request {
+                Request::Call { value, .. } => {
+                    self.eth -= *value;
+                    self.weth += *value;
+                    Response::success()
+                }
+                Request::StaticCall { .. } => {
+                    Response::success().with_return_data(self.weth.to_be_bytes::<32>())
+                }
+                _ => Response::success(),
+            }
+        }
+
+        fn balance(&mut self, _address: Address) -> U256 {
+            self.eth
+        }
+    }
+
+    let chain = || Chain {
+        eth: TEN_ETH,
+        weth: U256::ZERO,
+    };
+    let eth_check = fb.build_raw().len() - 1 - 20 - 32;
+    assert_eq!(
+        Interpreter::new(executor, chain()).execute(&fb.build()),
+        Err(Revert::InsufficientProfit {
+            offset: eth_check,
+            token: Address::ZERO,
+            balance_before: TEN_ETH,
+            balance_after: TEN_ETH - TWO_ETH,
+            min_profit: U256::ZERO,
+        })
+    );
+    fb.remove(fb.len() - 1);
+    let effects = Interpreter::new(executor, chain())
+        .execute(&fb.build())
+        .unwrap();
+    assert_eq!(effects.len(), 1);
+    fb.remove(fb.len() - 1);
+    fb.require_profit(WETH9, TWO_ETH + U256::from(1));
+    assert!(matches!(
+        Interpreter::new(executor, chain()).execute(&fb.build()),
+        Err(Revert::InsufficientProfit { token: WETH9, .. })
+    ));
+
+    // A later snapshot replaces the baseline
+    let mut fb = FlowBuilder::empty();
+    fb.begin_profit_check(WETH9)
+        .call(WETH9, &[], TWO_ETH)
+        .begin_profit_check(WETH9)
+        .require_profit(WETH9, U256::from(1));
+    assert!(matches!(
+        Interpreter::new(executor, chain()).execute(&fb.build()),
+        Err(Revert::InsufficientProfit {
+            balance_before: TWO_ETH,
+            balance_after: TWO_ETH,
+            ..
+        })
+    ));
+
+    // Checks without a snapshot, e.g. from decoded bytecode
+    let fb = FlowBuilder::from(vec![
+        Action::Snapshot { token: WETH9 },
+        Action::CheckProfit {
+            token: Address::ZERO,
+            min_profit: U256::ZERO,
+        },
+    ]);
+    assert_eq!(
+        fb.validate(),
+        Err(vec![ValidationError {
+            index: 1,
+            error: FlowError::MissingSnapshot {
+                token: Address::ZERO
+            },
+        }])
+    );
+    assert_eq!(
+        Interpreter::new(executor, chain()).execute(&fb.build()),
+        Err(Revert::MissingSnapshot { offset: 21 })
+    );
+
+    // The optimizer treats the checks as observable
+    let mut strict = fb.clone();
+    strict[1] = Action::CheckProfit {
+        token: Address::ZERO,
+        min_profit: TWO_ETH,
+    };
+    assert!(check_equivalence(fb.actions(), strict.actions()).is_err());
+}
+
+#[test]
+fn test_pay_coinbase() {
+    let executor = Address::repeat_byte(0xee);
+    let coinbase = Address::repeat_byte(0xcb);
+
+    let mut fb = FlowBuilder::empty();
+    assert_eq!(
+        fb.try_pay_coinbase(CoinbasePayment::BalanceBps(10_001))
+            .unwrap_err(),
+        FlowError::InvalidShare { bps: 10_001 }
+    );
+    fb.begin_profit_check(Address::ZERO)
+        .call(BOB, &[], U256::ZERO)
+        .pay_coinbase(CoinbasePayment::BalanceBps(500))
+        .pay_coinbase(CoinbasePayment::Amount(uint!(10_000_000_000_000_000_U256)));
+    assert_eq!(fb.validate(), Ok(()));
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    let text = asm::print(&fb);
+    assert!(text.contains("PAYCOINBASE 500 BPS"));
+    assert_eq!(asm::parse(&text).unwrap().build(), fb.build());
+    assert_eq!(
+        asm::parse("PAYCOINBASE 1e16").unwrap().actions(),
+        [Action::PayCoinbase {
+            payment: CoinbasePayment::Amount(uint!(10_000_000_000_000_000_U256))
+        }]
+    );
+    assert!(asm::parse("PAYCOINBASE 10001 BPS").is_err());
+    let mut data = vec![OP_PAYCOINBASE, 2];
+    data.extend([0; 32]);
+    assert_eq!(
+        decode(&data),
+        Err(DecodeError::InvalidOperand {
+            offset: 1,
+            opcode: OP_PAYCOINBASE
+        })
+    );
+
+    // The share is taken from the executor's balance at the time of payment
+    struct Chain {
+        accept: bool,
+    }
+    impl Responder for Chain {
+        fn respond(&mut self, request: &Request<'_>) -> Response {
+            match request {
+                Request::Call { value, .. } if !value.is_zero() && !self.accept => {
+                    Response::failure()
+                }
+                _ => Response::success(),
+            }
+        }
+
+        fn balance(&mut self, _address: Address) -> U256 {
+            TWO_ETH
+        }
+    }
+
+    let effects = Interpreter::new(executor, Chain { accept: true })
+        .with_coinbase(coinbase)
+        .execute(&fb.build())
+        .unwrap();
+    let paid: Vec<_> = effects[1..]
+        .iter()
+        .map(|e| match e {
+            Effect::Call {
+                target,
+                value,
+                calldata,
+                success: true,
+                ..
+            } if calldata.is_empty() => (*target, *value),
+            e => panic!("unexpected effect {e:?}"),
+        })
+        .collect();
+    assert_eq!(
+        paid,
+        [
+            (coinbase, TWO_ETH / U256::from(20)),
+            (coinbase, uint!(10_000_000_000_000_000_U256))
+        ]
+    );
+
+    // A rejected payment reverts the flow
+    let payment = fb.build_raw().len() - 2 * 34;
+    assert_eq!(
+        Interpreter::new(executor, Chain { accept: false })
+            .with_coinbase(coinbase)
+            .execute(&fb.build()),
+        Err(Revert::ActionFailed {
+            offset: payment,
+            reason: Vec::new(),
+        })
+    );
+    let fb = FlowBuilder::from(vec![Action::PayCoinbase {
+        payment: CoinbasePayment::BalanceBps(20_000),
+    }]);
+    assert_eq!(
+        fb.validate(),
+        Err(vec![ValidationError {
+            index: 0,
+            error: FlowError::InvalidShare { bps: 20_000 },
+        }])
+    );
+    assert_eq!(
+        Interpreter::new(executor, |_: &Request<'_>| Response::success()).execute(&fb.build()),
+        Err(Revert::InvalidPayment { offset: 0 })
+    );
+}
+
+#[test]
+fn test_block_guards() {
+    let executor = Address::repeat_byte(0xee);
+    let gwei = U256::from(1_000_000_000u64);
+
+    let mut fb = FlowBuilder::empty();
+    fb.call(BOB, &[0x01], U256::ZERO)
+        .require_block(20_000_002)
+        .require_deadline(1_700_000_024)
+        .require_max_basefee(gwei * U256::from(50));
+    // Guards go first, in front of everything built so far
+    assert_eq!(fb.actions()[3], Action::SetAddr { addr: BOB });
+    assert_eq!(
+        fb.actions()[..3],
+        [
+            Action::MaxBaseFee {
+                fee: gwei * U256::from(50)
+            },
+            Action::Deadline {
+                timestamp: U256::from(1_700_000_024u64)
+            },
+            Action::MaxBlock {
+                number: U256::from(20_000_002u64)
+            },
+        ]
+    );
+    assert_eq!(fb.validate(), Ok(()));
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    let text = asm::print(&fb);
+    assert!(text.contains("MAXBLOCK 20000002"));
+    assert!(text.contains("DEADLINE 1700000024"));
+    assert_eq!(asm::parse(&text).unwrap().build(), fb.build());
+
+    // Bounds are inclusive
+    let run = |number: u64, timestamp: u64, basefee: U256| {
+        Interpreter::new(executor, |_: &Request<'_>| Response::success())
+            .with_block_number(number)
+            .with_timestamp(timestamp)
+            .with_basefee(basefee)
+            .execute(&fb.build())
+    };
+    let gas_price = gwei * U256::from(50);
+    assert_eq!(run(20_000_002, 1_700_000_024, gas_price).unwrap().len(), 1);
+    assert_eq!(
+        run(20_000_003, 1_700_000_024, gas_price),
+        Err(Revert::BlockExpired { offset: 66 })
+    );
+    assert_eq!(
+        run(20_000_000, 1_700_000_025, gas_price),
+        Err(Revert::DeadlineExpired { offset: 33 })
+    );
+    let revert = run(20_000_000, 1_700_000_000, gas_price + U256::from(1)).unwrap_err();
+    assert_eq!(revert, Revert::BaseFeeTooHigh { offset: 0 });
+    assert_eq!(
+        revert.reason(),
+        RevertReason::Error("BASEFEE_TOO_HIGH".to_owned())
+    );
+
+    // The optimizer keeps guards in place
+    let mut optimized = fb.clone();
+    optimized.optimize();
+    assert_eq!(optimized.actions()[..3], fb.actions()[..3]);
+    let unguarded = &fb.actions()[3..];
+    assert!(check_equivalence(fb.actions(), unguarded).is_err());
+}
+
+#[test]
+fn test_block_guard_with_offset_jump() {
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .call(BOB, &[0x01], U256::ZERO)
+        .jump_if_failed("end")
+        .call(BOB, &[0x02], U256::ZERO)
+        .label("end");
+
+    // A decoded flow jumps to raw offsets, which a guard would shift
+    let mut decoded = FlowBuilder::from_bytecode(&fb.build()).unwrap();
+    let before = decoded.actions().to_vec();
+    let Some(JumpDest::Offset(offset)) = before.iter().find_map(|a| a.jump_dest()).cloned() else {
+        panic!("decoded jump has no offset");
+    };
+    assert_eq!(
+        decoded.try_require_block(1).unwrap_err(),
+        FlowError::ShiftedJump { offset }
+    );
+    assert_eq!(
+        decoded.try_require_deadline(1).unwrap_err(),
+        FlowError::ShiftedJump { offset }
+    );
+    assert_eq!(
+        decoded.try_require_max_basefee(U256::ZERO).unwrap_err(),
+        FlowError::ShiftedJump { offset }
+    );
+    assert_eq!(decoded.actions(), before);
+
+    // Labels are resolved after the guard is inserted
+    fb.require_block(1);
+    let guarded = FlowBuilder::from_bytecode(&fb.build()).unwrap();
+    let Some(JumpDest::Offset(shifted)) = guarded.actions().iter().find_map(|a| a.jump_dest())
+    else {
+        panic!("decoded jump has no offset");
+    };
+    assert_eq!(*shifted, offset + 33);
+}
+
+#[test]
+#[should_panic(expected = "guard would shift the jump to byte offset")]
+fn test_block_guard_offset_jump_panics() {
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail().jump_if_failed("end").label("end");
+    FlowBuilder::from_bytecode(&fb.build())
+        .unwrap()
+        .require_block(1);
+}
+
+#[test]
+fn test_checkpoints() {
+    use alloy::primitives::{Log, LogData};
+
+    let executor = Address::repeat_byte(0xee);
+    let tag = B256::right_padding_from(b"leg 1 done");
+
+    let mut fb = FlowBuilder::empty();
+    assert_eq!(
+        fb.try_log(tag, 0, 32).unwrap_err(),
+        FlowError::DataBeforeClear
+    );
+    fb.set_cleardata_op(64)
+        .write_balance(0)
+        .write_token_balance(WETH9, executor, 32)
+        .log(tag, 0, 64);
+    assert_eq!(
+        fb.try_log(tag, 48, 32).unwrap_err(),
+        FlowError::LogOutOfBounds {
+            data_offset: 48,
+            size: 32,
+            buffer_size: 64,
+        }
+    );
+    assert_eq!(fb.validate(), Ok(()));
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    let text = asm::print(&fb);
+    assert!(text.contains(&format!("LOG {tag} 0 64")));
+    assert_eq!(asm::parse(&text).unwrap().build(), fb.build());
+
+    // The interpreter records the logged slice
+    struct Chain;
+    impl Responder for Chain {
+        fn respond(&mut self, _request: &Request<'_>) -> Response {
+            Response::success().with_return_data(TWO_ETH.to_be_bytes::<32>())
+        }
+
+        fn balance(&mut self, _address: Address) -> U256 {
+            TEN_ETH
+        }
+    }
+    let effects = Interpreter::new(executor, Chain)
+        .execute(&fb.build())
+        .unwrap();
+    let Some(Effect::Log {
+        depth: 0,
+        tag: logged,
+        data,
+    }) = effects.last()
+    else {
+        panic!("expected a log, got {effects:?}");
+    };
+    assert_eq!(*logged, tag);
+    assert_eq!(data[..32], TEN_ETH.to_be_bytes::<32>());
+    assert_eq!(data[32..], TWO_ETH.to_be_bytes::<32>());
+
+    // Receipt logs decode back to checkpoints; other logs are skipped
+    let checkpoint = |address: Address, topics: Vec<B256>| {
+        let data = alloy::primitives::Bytes::from(data.clone()).abi_encode();
+        Log {
+            address,
+            data: LogData::new_unchecked(topics, data.into()),
+        }
+    };
+    let logs = [
+        checkpoint(executor, vec![Checkpoint::topic(), tag]),
+        checkpoint(WETH9, vec![Checkpoint::topic(), tag]),
+        checkpoint(executor, vec![B256::ZERO, tag]),
+        checkpoint(executor, vec![Checkpoint::topic()]),
+    ];
+    let checkpoints = decode_checkpoints(executor, &logs);
+    assert_eq!(checkpoints.len(), 1);
+    assert_eq!(checkpoints[0].tag_str(), Some("leg 1 done"));
+    assert_eq!(checkpoints[0].word(0), Some(TEN_ETH));
+    assert_eq!(checkpoints[0].word(1), Some(TWO_ETH));
+    assert_eq!(checkpoints[0].word(2), None);
+
+    // Writes read only by a LOG are kept
+    let mut fb = FlowBuilder::empty();
+    fb.set_cleardata_op(32)
+        .set_data_op(0, &[1])
+        .log(tag, 0, 32)
+        .set_cleardata_op(32)
+        .optimize();
+    assert_eq!(fb.len(), 3);
+}
+
+#[test]
+fn test_sweep() {
+    let executor = Address::repeat_byte(0xee);
+    let usdt = Address::repeat_byte(0x07);
+
+    let mut fb = FlowBuilder::empty();
+    fb.sweep(&[WETH9, Address::ZERO, usdt], WALLET);
+    assert_eq!(
+        fb.actions(),
+        [WETH9, usdt, Address::ZERO].map(|token| Action::Sweep {
+            token,
+            recipient: WALLET
+        })
+    );
+    assert_eq!(fb.validate(), Ok(()));
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    let text = asm::print(&fb);
+    assert!(text.contains(&format!("SWEEP {usdt} {WALLET}")));
+    assert_eq!(asm::parse(&text).unwrap().build(), fb.build());
+
+    // WETH answers `transfer` with `true`, USDT with nothing; the balance
+    // queries are static calls
+    struct Chain {
+        weth: U256,
+        transfer_result: Vec<u8>,
+    }
+    impl Responder for Chain {
+        fn respond(&mut self, request: &Request<'_>) -> Response {
+            match request {
+                Request::StaticCall { target, .. } if *target == WETH9 => {
+                    Response::success().with_return_data(self.weth.to_be_bytes::<32>())
+                }
+                Request::StaticCall { .. } => {
+                    Response::success().with_return_data(TWO_ETH.to_be_bytes::<32>())
+                }
+                Request::Call { target, .. } if *target == WETH9 => {
+                    Response::success().with_return_data(self.transfer_result.clone())
+                }
+                _ => Response::success(),
+            }
+        }
+
+        fn balance(&mut self, _address: Address) -> U256 {
+            TEN_ETH
+        }
+    }
+    let chain = |weth: U256, transfer_result: Vec<u8>| Chain {
+        weth,
+        transfer_result,
+    };
+
+    let effects = Interpreter::new(executor, chain(TWO_ETH, U256::from(1).abi_encode()))
+        .execute(&fb.build())
+        .unwrap();
+    let transfer = |to: Address, value: U256| IERC20::transferCall { to, value }.abi_encode();
+    let sent: Vec<_> = effects
+        .iter()
+        .map(|e| match e {
+            Effect::Call {
+                target,
+                value,
+                calldata,
+                success: true,
+                ..
+            } => (*target, *value, calldata.clone()),
+            e => panic!("unexpected effect {e:?}"),
+        })
+        .collect();
+    assert_eq!(
+        sent,
+        [
+            (WETH9, U256::ZERO, transfer(WALLET, TWO_ETH)),
+            (usdt, U256::ZERO, transfer(WALLET, TWO_ETH)),
+            (WALLET, TEN_ETH, vec![]),
+        ]
+    );
+
+    // An empty balance is skipped
+    let effects = Interpreter::new(executor, chain(U256::ZERO, vec![]))
+        .execute(&fb.build())
+        .unwrap();
+    assert_eq!(effects.len(), 2);
+
+    // A token answering `false` reverts the flow
+    assert_eq!(
+        Interpreter::new(executor, chain(TWO_ETH, false.abi_encode())).execute(&fb.build()),
+        Err(Revert::ActionFailed {
+            offset: 0,
+            reason: false.abi_encode(),
+        })
+    );
+}
+
+#[test]
+fn test_registers() {
+    let executor = Address::repeat_byte(0xee);
+    let pool = Address::repeat_byte(0x01);
+    let amount = U256::from(1234);
+
+    let mut fb = FlowBuilder::empty();
+    assert_eq!(
+        fb.try_store_register(0, 0).unwrap_err(),
+        FlowError::DataBeforeClear
+    );
+    // Keep a swap's output across an unrelated call, then pass it on
+    let transfer = IERC20::transferCall {
+        to: BOB,
+        value: U256::ZERO,
+    }
+    .abi_encode();
+    fb.call(pool, &[0x01], U256::ZERO)
+        .store_return_word(3, 0)
+        .call(BOB, &[0x02], U256::ZERO)
+        .set_addr_op(WETH9)
+        .set_cleardata_op(transfer.len() as u16)
+        .set_data_op(0, &transfer)
+        .load_register(3, 36)
+        .call_op();
+    assert_eq!(
+        fb.try_load_register(REGISTER_COUNT, 0).unwrap_err(),
+        FlowError::InvalidRegister {
+            register: REGISTER_COUNT
+        }
+    );
+    assert_eq!(fb.validate(), Ok(()));
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    let text = asm::print(&fb);
+    assert!(text.contains("STOREREG R3 0"));
+    assert!(text.contains("LOADREG R3 36"));
+    assert_eq!(asm::parse(&text).unwrap().build(), fb.build());
+    assert!(asm::parse("LOADREG 3 0").is_err());
+
+    let responder = |request: &Request<'_>| match request {
+        Request::Call { target, .. } if *target == pool => {
+            Response::success().with_return_data(amount.to_be_bytes::<32>())
+        }
+        _ => Response::success(),
+    };
+    let effects = Interpreter::new(executor, responder)
+        .execute(&fb.build())
+        .unwrap();
+    let Some(Effect::Call { calldata, .. }) = effects.last() else {
+        panic!("expected a call, got {effects:?}");
+    };
+    assert_eq!(
+        IERC20::transferCall::abi_decode(calldata).unwrap().value,
+        amount
+    );
+
+    // Register writes are kept, and registers tell flows apart
+    let mut optimized = fb.clone();
+    optimized.optimize();
+    assert!(optimized
+        .iter()
+        .any(|a| matches!(a, Action::StoreReg { register: 3, .. })));
+    let mut other = FlowBuilder::from(fb.actions().to_vec());
+    let last = other.len() - 2;
+    other.replace(
+        last,
+        Action::LoadReg {
+            register: 4,
+            data_offset: 36,
+        },
+    );
+    assert!(check_equivalence(fb.actions(), other.actions()).is_err());
+
+    // Out of range registers panic on chain
+    let fb = FlowBuilder::from(vec![
+        Action::ClearData { size: 32 },
+        Action::StoreReg {
+            register: 16,
+            data_offset: 0,
+        },
+    ]);
+    assert_eq!(
+        fb.validate(),
+        Err(vec![ValidationError {
+            index: 1,
+            error: FlowError::InvalidRegister { register: 16 },
+        }])
+    );
+    assert_eq!(
+        Interpreter::new(executor, |_: &Request<'_>| Response::success()).execute(&fb.build()),
+        Err(Revert::Panic {
+            offset: 3,
+            code: 0x32
+        })
+    );
+}
+
+#[test]
+fn test_dynamic_value() {
+    let executor = Address::repeat_byte(0xee);
+    const POOL: Address = Address::repeat_byte(0x01);
+    const AMOUNT: U256 = uint!(1234_U256);
+
+    // Wrap all ETH but a reserve, then forward a swap's output as value
+    let mut fb = FlowBuilder::empty();
+    fb.set_addr_op(WETH9)
+        .set_value_from_balance_keeping(TWO_ETH)
+        .call_op()
+        .call(POOL, &[0x01], U256::ZERO)
+        .set_cleardata_op(32)
+        .copy_return(0, 0, 32)
+        .set_value_from_data(0);
+    assert_eq!(
+        fb.try_set_value_from_data(1).unwrap_err(),
+        FlowError::WordOutOfBounds {
+            data_offset: 1,
+            buffer_size: 32
+        }
+    );
+    fb.set_addr_op(BOB).set_cleardata_op(0).call_op();
+    assert_eq!(fb.validate(), Ok(()));
+    assert_eq!(decode(&fb.build()).unwrap(), fb.actions());
+    let text = asm::print(&fb);
+    assert!(text.contains("SETVALUEBAL 2e18"));
+    assert!(text.contains("SETVALUEDATA 0"));
+    assert_eq!(asm::parse(&text).unwrap().build(), fb.build());
+
+    struct Chain(U256);
+    impl Responder for Chain {
+        fn respond(&mut self, request: &Request<'_>) -> Response {
+            match request {
+                Request::Call { target, .. } if *target == POOL => {
+                    Response::success().with_return_data(AMOUNT.to_be_bytes::<32>())
+                }
+                _ => Response::success(),
+            }
+        }
+
+        fn balance(&mut self, _address: Address) -> U256 {
+            self.0
+        }
     }
-}
+    let effects = Interpreter::new(executor, Chain(TEN_ETH))
+        .execute(&fb.build())
+        .unwrap();
+    let values: Vec<_> = effects
+        .iter()
+        .map(|e| match e {
+            Effect::Call { target, value, .. } => (*target, *value),
+            e => panic!("unexpected effect {e:?}"),
+        })
+        .collect();
+    assert_eq!(
+        values,
+        [
+            (WETH9, TEN_ETH - TWO_ETH),
+            (POOL, U256::ZERO),
+            (BOB, AMOUNT)
+        ]
+    );
 
-#[test]
-fn test_execute_actions_selector() {
-    // Verify the hardcoded selector in FlowBuilder matches the keccak256 of the signature
-    use alloy::primitives::keccak256;
-    let hash = keccak256("executeActions()");
-    let expected_selector = &hash[..4];
-    let built = FlowBuilder::empty().build();
+    // A balance below the reserve reverts
+    let revert = Interpreter::new(executor, Chain(U256::from(1)))
+        .execute(&fb.build())
+        .unwrap_err();
+    assert_eq!(revert, Revert::BalanceBelowReserve { offset: 21 });
     assert_eq!(
-        &built[..4],
-        expected_selector,
-        "selector drift: update EXECUTE_ACTIONS_SELECTOR"
+        revert.reason(),
+        RevertReason::Error("BALANCE_BELOW_RESERVE".into())
     );
-}
 
-#[test]
-fn test_flow_builder_max_data_length() {
-    // Verify that data of exactly u16::MAX bytes is accepted (off-by-one regression guard)
-    let data = vec![0xABu8; u16::MAX as usize];
-    let addr = Address::repeat_byte(0x01);
-    // Should not panic
-    let _calldata = FlowBuilder::empty().call(addr, &data, U256::ZERO).build();
+    // So does a word read past the end of the buffer
+    let short = FlowBuilder::from(vec![
+        Action::ClearData { size: 16 },
+        Action::SetValueFromData { data_offset: 0 },
+    ]);
+    let revert = Interpreter::new(executor, Chain(TEN_ETH))
+        .execute(&short.build())
+        .unwrap_err();
+    assert_eq!(revert, Revert::TxDataOutOfBounds { offset: 3 });
+    assert_eq!(revert.reason(), RevertReason::Error("TXDATA_OOB".into()));
+
+    // A word of known bytes is the same value as a SETVALUE of it, a
+    // balance is not
+    let word = |action: Action| {
+        vec![
+            Action::ClearData { size: 32 },
+            Action::SetData {
+                offset: 0,
+                data: TWO_ETH.to_be_bytes::<32>().to_vec(),
+            },
+            action,
+            Action::SetAddr { addr: WETH9 },
+            Action::Call,
+        ]
+    };
+    let from_data = word(Action::SetValueFromData { data_offset: 0 });
+    assert_eq!(
+        check_equivalence(&from_data, &word(Action::SetValue { value: TWO_ETH })),
+        Ok(())
+    );
+    assert!(check_equivalence(
+        &from_data,
+        &word(Action::SetValueFromBalance {
+            reserve: U256::ZERO
+        })
+    )
+    .is_err());
+    let mut optimized = FlowBuilder::from(from_data.clone());
+    optimized.optimize();
+    assert_eq!(check_equivalence(&from_data, optimized.actions()), Ok(()));
 }
 
 #[test]
-fn test_flow_builder_create() {
-    let calldata = FlowBuilder::empty()
-        .create(Address::ZERO, "LALA".as_bytes(), U256::from(10))
-        .optimize()
-        .build();
+fn test_revert_reasons() {
+    sol! {
+        interface IMorphoFlashLoanCallback {
+            function onMorphoFlashLoan(uint256 assets, bytes calldata data) external;
+        }
+        error ActionFailed(uint256 offset, bytes reason);
+        error InsufficientProfit(address token, uint256 balanceBefore, uint256 balanceAfter, uint256 minProfit);
+        error Error(string message);
+    }
+
+    // Mock Morpho lends by calling back into the executor; BOB rejects calls
+    let responder = |request: &Request<'_>| match request {
+        Request::Call {
+            target, calldata, ..
+        } if *target == MORPHO => {
+            let call = IMorpho::flashLoanCall::abi_decode(calldata).unwrap();
+            let callback = IMorphoFlashLoanCallback::onMorphoFlashLoanCall {
+                assets: call.assets,
+                data: call.data,
+            };
+            Response::success().reenter(MORPHO, callback.abi_encode())
+        }
+        Request::Call { target, .. } if *target == BOB => {
+            Response::failure().with_return_data(RevertReason::Error("nope".into()).encode())
+        }
+        _ => Response::success(),
+    };
+
+    let mut inner = FlowBuilder::empty();
+    inner
+        .set_fail()
+        .call(WETH9, &[0xaa], U256::ZERO)
+        .label("unused")
+        .call(BOB, &[0xbb], U256::ZERO);
+    let flash_loan = IMorpho::flashLoanCall {
+        token: WETH9,
+        assets: TEN_ETH,
+        data: inner.build_raw().into(),
+    }
+    .abi_encode();
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .set_callback(MORPHO)
+        .call(MORPHO, &flash_loan, U256::ZERO);
+
+    let revert = Interpreter::new(Address::repeat_byte(0xee), responder)
+        .execute(&fb.build())
+        .unwrap_err();
+    let reason = revert.reason();
+    assert_eq!(decode_revert(&reason.encode()), reason);
+    assert_eq!(reason.root_cause(), &RevertReason::Error("nope".into()));
+    let [outer_offset, inner_offset] = reason.action_offsets()[..] else {
+        panic!("expected two failed actions, got {reason}");
+    };
+    assert_eq!(fb.action_index(outer_offset), Some(fb.len() - 1));
+    assert_eq!(inner.action_index(inner_offset), Some(inner.len() - 1));
+    assert_eq!(inner.action_index(inner_offset - 1), None);
+    assert_eq!(inner.action_index(inner.build_raw().len()), None);
     assert_eq!(
-        calldata,
-        hex!("c94f554d04000000000000000000000000000000000000000000000000000000000000000a01000402000000044c414c4107")
+        reason.to_string(),
+        format!(
+            "action at offset {outer_offset} failed: action at offset {inner_offset} failed: \
+             Error(\"nope\")"
+        )
     );
-}
 
-#[test]
-fn test_flow_builder_call() {
-    let addr_a = Address::repeat_byte(0x41);
-    let calldata = FlowBuilder::empty()
-        .call(addr_a, &vec![98, 99], U256::ZERO)
-        .optimize()
-        .build();
+    // The encoding matches the contract's errors
+    let action_failed = ActionFailed {
+        offset: U256::from(outer_offset),
+        reason: RevertReason::Panic(U256::from(0x11)).encode().into(),
+    };
     assert_eq!(
-        calldata,
-        hex!("c94f554d0341414141414141414141414141414141414141410100020200000002626306")
+        decode_revert(&action_failed.abi_encode()),
+        RevertReason::ActionFailed {
+            offset: outer_offset,
+            reason: Box::new(RevertReason::Panic(U256::from(0x11))),
+        }
+    );
+    assert_eq!(
+        RevertReason::Error("BAD_CMP".into()).encode(),
+        Error {
+            message: "BAD_CMP".into()
+        }
+        .abi_encode()
+    );
+    let insufficient_profit = InsufficientProfit {
+        token: WETH9,
+        balanceBefore: TEN_ETH,
+        balanceAfter: TWO_ETH,
+        minProfit: U256::ZERO,
+    };
+    assert_eq!(
+        decode_revert(&insufficient_profit.abi_encode()),
+        RevertReason::InsufficientProfit {
+            token: WETH9,
+            balance_before: TEN_ETH,
+            balance_after: TWO_ETH,
+            min_profit: U256::ZERO,
+        }
     );
-}
 
-#[test]
-fn test_flow_builder_delegatecall() {
-    let addr_b = Address::repeat_byte(0x42);
-    let calldata = FlowBuilder::empty()
-        .delegatecall(addr_b, &vec![70, 71])
-        .optimize()
-        .build();
+    // Anything else is kept as is
+    assert_eq!(decode_revert(&[]), RevertReason::Empty);
+    let truncated = &action_failed.abi_encode()[..60];
     assert_eq!(
-        calldata,
-        hex!("c94f554d0342424242424242424242424242424242424242420100020200000002464708")
+        decode_revert(truncated),
+        RevertReason::Unknown(truncated.to_vec())
+    );
+    assert_eq!(
+        decode_revert(&hex!("deadbeef")),
+        RevertReason::Unknown(hex!("deadbeef").to_vec())
     );
 }
 
+#[cfg(feature = "revm")]
 #[test]
-fn test_flow_builder_combined_operations() {
-    let addr_a = Address::repeat_byte(0x41);
-    let addr_b = Address::repeat_byte(0x42);
-    let calldata = FlowBuilder::empty()
-        .create(Address::ZERO, "LALA".as_bytes(), U256::from(10))
-        .call(addr_a, &vec![98, 99], U256::ZERO)
-        .delegatecall(addr_b, &vec![70, 71])
-        .optimize()
-        .build();
+fn test_simulator_transfer_and_revert() {
+    use crate::simulator::Simulator;
+
+    let mut sim = Simulator::new(WALLET);
+    sim.set_balance(WALLET, BUDGET);
+    let mut fb = FlowBuilder::empty();
+    fb.call(BOB, &[], TWO_ETH);
+
+    // Simulating leaves the state untouched
+    let res = sim.simulate(&fb, TWO_ETH).unwrap();
+    assert!(res.success);
+    assert_eq!(res.state_diff[&BOB].balance, (U256::ZERO, TWO_ETH));
+    let res = sim.execute(&fb, TWO_ETH).unwrap();
+    assert_eq!(res.state_diff[&BOB].balance, (U256::ZERO, TWO_ETH));
+    let res = sim.execute(&fb, TWO_ETH).unwrap();
     assert_eq!(
-        calldata,
-        hex!("c94f554d04000000000000000000000000000000000000000000000000000000000000000a01000402000000044c414c410703414141414141414141414141414141414141414101000202000000026263060342424242424242424242424242424242424242420200000002464708")
+        res.state_diff[&BOB].balance,
+        (TWO_ETH, TWO_ETH * U256::from(2))
+    );
+
+    // PUSH0 PUSH0 REVERT
+    let reverter = Address::repeat_byte(0x66);
+    sim.set_code(reverter, &[0x5f, 0x5f, 0xfd]);
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail().call(reverter, &[], U256::ZERO);
+    let res = sim.execute(&fb, U256::ZERO).unwrap();
+    assert!(!res.success);
+    // Only the sender's nonce changes
+    assert_eq!(res.state_diff.keys().collect::<Vec<_>>(), [&WALLET]);
+    assert_eq!(
+        decode_revert(&res.output),
+        RevertReason::ActionFailed {
+            offset: fb.build_raw().len() - 1,
+            reason: Box::new(RevertReason::Empty),
+        }
     );
+
+    // The same flows work through a delegate proxy
+    let proxy = sim.deploy_proxy().unwrap();
+    sim.set_executor(proxy);
+    let mut fb = FlowBuilder::empty();
+    fb.call(BOB, &[], TWO_ETH);
+    assert!(sim.execute(&fb, TWO_ETH).unwrap().success);
+}
+
+#[cfg(feature = "revm")]
+#[test]
+fn test_simulator_deploy_proxy_first() {
+    use crate::simulator::Simulator;
+
+    // The proxy must not be created over the preinstalled executor
+    let mut sim = Simulator::new(WALLET);
+    sim.set_balance(WALLET, BUDGET);
+    let executor = sim.executor();
+    let proxy = sim.deploy_proxy().unwrap();
+    assert_ne!(proxy, executor);
+
+    let mut fb = FlowBuilder::empty();
+    fb.call(BOB, &[], TWO_ETH);
+    assert!(sim.execute(&fb, TWO_ETH).unwrap().success);
+    sim.set_executor(proxy);
+    assert!(sim.execute(&fb, TWO_ETH).unwrap().success);
 }
 
 #[tokio::test]
@@ -1237,3 +3651,539 @@ async fn test_flashloan_aave3_success_with_callback() {
         .unwrap();
     assert!(receipt.status());
 }
+
+// Offline flash loan tests against the mock lenders
+
+#[tokio::test]
+async fn test_mock_morpho_flashloan() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+    let weth = IERC20::new(mocks.weth, provider.clone());
+
+    let mut repay = FlowBuilder::empty();
+    repay.call(
+        mocks.weth,
+        &IERC20::approveCall {
+            spender: mocks.morpho,
+            value: TEN_ETH,
+        }
+        .abi_encode(),
+        U256::ZERO,
+    );
+    let flashloan_calldata = morpho_flashloan(mocks.weth, TEN_ETH, &repay);
+
+    // The callback is rejected unless Morpho was registered first
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .call(mocks.morpho, &flashloan_calldata, U256::ZERO);
+    assert!(!execute_flow(&provider, executor, &fb, U256::ZERO).await);
+
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .set_callback(mocks.morpho)
+        .call(mocks.morpho, &flashloan_calldata, U256::ZERO);
+    assert!(execute_flow(&provider, executor, &fb, U256::ZERO).await);
+    assert_eq!(
+        weth.balanceOf(mocks.morpho).call().await.unwrap(),
+        FIFTY_ETH
+    );
+    assert_eq!(weth.balanceOf(executor).call().await.unwrap(), U256::ZERO);
+}
+
+#[tokio::test]
+async fn test_mock_morpho_callback_reset() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+
+    let mut repay = FlowBuilder::empty();
+    repay.call(
+        mocks.weth,
+        &IERC20::approveCall {
+            spender: mocks.morpho,
+            value: TEN_ETH,
+        }
+        .abi_encode(),
+        U256::ZERO,
+    );
+    let flashloan_calldata = morpho_flashloan(mocks.weth, TEN_ETH, &repay);
+
+    // Each callback consumes the registration
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .set_callback(mocks.morpho)
+        .call(mocks.morpho, &flashloan_calldata, U256::ZERO)
+        .call(mocks.morpho, &flashloan_calldata, U256::ZERO);
+    assert!(!execute_flow(&provider, executor, &fb, U256::ZERO).await);
+
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .set_callback(mocks.morpho)
+        .call(mocks.morpho, &flashloan_calldata, U256::ZERO)
+        .set_callback(mocks.morpho)
+        .call(mocks.morpho, &flashloan_calldata, U256::ZERO);
+    assert!(execute_flow(&provider, executor, &fb, U256::ZERO).await);
+}
+
+#[tokio::test]
+async fn test_mock_morpho_nested_flashloan() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+
+    let approve_calldata = IERC20::approveCall {
+        spender: mocks.morpho,
+        value: TEN_ETH,
+    }
+    .abi_encode();
+    let mut repay = FlowBuilder::empty();
+    repay.call(mocks.weth, &approve_calldata, U256::ZERO);
+    let inner_calldata = morpho_flashloan(mocks.weth, TEN_ETH, &repay);
+
+    for register_inner in [true, false] {
+        let mut inner = FlowBuilder::empty();
+        inner.set_fail();
+        if register_inner {
+            inner.set_callback(mocks.morpho);
+        }
+        // Allowances are overwritten, so re-approve for the outer repayment
+        inner.call(mocks.morpho, &inner_calldata, U256::ZERO).call(
+            mocks.weth,
+            &approve_calldata,
+            U256::ZERO,
+        );
+
+        let mut fb = FlowBuilder::empty();
+        fb.set_fail().set_callback(mocks.morpho).call(
+            mocks.morpho,
+            &morpho_flashloan(mocks.weth, TEN_ETH, &inner),
+            U256::ZERO,
+        );
+        assert_eq!(
+            execute_flow(&provider, executor, &fb, U256::ZERO).await,
+            register_inner
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_mock_aave_flashloan() {
+    sol! {
+        interface IAavePool {
+            function flashLoanSimple(
+                address receiverAddress,
+                address asset,
+                uint256 amount,
+                bytes calldata params,
+                uint16 referralCode
+            ) external;
+        }
+    }
+
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+    let weth = IERC20::new(mocks.weth, provider.clone());
+    // 0.05% of the loan
+    let premium = TEN_ETH * U256::from(5) / U256::from(10_000);
+
+    // The premium is sent along and wrapped inside executeOperation
+    let mut repay = FlowBuilder::empty();
+    repay.call(mocks.weth, &[], premium).call(
+        mocks.weth,
+        &IERC20::approveCall {
+            spender: mocks.aave_pool,
+            value: TEN_ETH + premium,
+        }
+        .abi_encode(),
+        U256::ZERO,
+    );
+    let flashloan_calldata = IAavePool::flashLoanSimpleCall {
+        receiverAddress: executor,
+        asset: mocks.weth,
+        amount: TEN_ETH,
+        params: repay.build_raw().into(),
+        referralCode: 0,
+    }
+    .abi_encode();
+
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail().set_callback(mocks.aave_pool).call(
+        mocks.aave_pool,
+        &flashloan_calldata,
+        U256::ZERO,
+    );
+    assert!(execute_flow(&provider, executor, &fb, premium).await);
+    assert_eq!(
+        weth.balanceOf(mocks.aave_pool).call().await.unwrap(),
+        FIFTY_ETH + premium
+    );
+}
+
+#[tokio::test]
+async fn test_mock_copy_return_chains_balance() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+    let weth = IERC20::new(mocks.weth, provider.clone());
+
+    // Transfer whatever balanceOf returns, resolved on-chain
+    let transfer = IERC20::transferCall {
+        to: BOB,
+        value: U256::ZERO,
+    }
+    .abi_encode();
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .call(mocks.weth, &[], TWO_ETH)
+        .call(
+            mocks.weth,
+            &IERC20::balanceOfCall { account: executor }.abi_encode(),
+            U256::ZERO,
+        )
+        .set_cleardata_op(transfer.len() as u16)
+        .set_data_op(0, &transfer)
+        .copy_return(0, 36, 32)
+        .call_op();
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    assert_eq!(weth.balanceOf(BOB).call().await.unwrap(), TWO_ETH);
+    assert_eq!(weth.balanceOf(executor).call().await.unwrap(), U256::ZERO);
+}
+
+#[tokio::test]
+async fn test_mock_staticcall() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+    let weth = IERC20::new(mocks.weth, provider.clone());
+
+    // Read the balance with a static call and transfer it
+    let transfer = IERC20::transferCall {
+        to: BOB,
+        value: U256::ZERO,
+    }
+    .abi_encode();
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .call(mocks.weth, &[], TWO_ETH)
+        .staticcall(
+            mocks.weth,
+            &IERC20::balanceOfCall { account: executor }.abi_encode(),
+        )
+        .set_addr_op(mocks.weth)
+        .set_cleardata_op(transfer.len() as u16)
+        .set_data_op(0, &transfer)
+        .copy_return(0, 36, 32)
+        .call_op();
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    assert_eq!(weth.balanceOf(BOB).call().await.unwrap(), TWO_ETH);
+
+    // A state-changing call fails when made static
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail().staticcall(mocks.weth, &transfer);
+    assert!(!execute_flow(&provider, executor, &fb, U256::ZERO).await);
+}
+
+#[tokio::test]
+async fn test_mock_create2_predicted_address() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let salt = b256!("00000000000000000000000000000000000000000000000000000000000000aa");
+
+    // Deploy a WETH mock and deposit into it at the predicted address
+    let mut fb = FlowBuilder::for_executor(executor);
+    let weth = fb.create2_address(salt, MOCK_WETH_INIT).unwrap();
+    fb.set_fail()
+        .create2(salt, MOCK_WETH_INIT, U256::ZERO)
+        .call(weth, &[], TWO_ETH)
+        .optimize();
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    assert!(!provider.get_code_at(weth).await.unwrap().is_empty());
+    let weth = IERC20::new(weth, provider.clone());
+    assert_eq!(weth.balanceOf(executor).call().await.unwrap(), TWO_ETH);
+
+    // Deploying again with the same salt collides and fails
+    assert!(!execute_flow(&provider, executor, &fb, TWO_ETH).await);
+}
+
+#[tokio::test]
+async fn test_mock_transfer_scaled_token_balance() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+    let weth = IERC20::new(mocks.weth, provider.clone());
+
+    // Transfer 95% of the balance, computed on-chain
+    let transfer = IERC20::transferCall {
+        to: BOB,
+        value: U256::ZERO,
+    }
+    .abi_encode();
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .call(mocks.weth, &[], TWO_ETH)
+        .set_cleardata_op(transfer.len() as u16)
+        .set_data_op(0, &transfer)
+        .write_token_balance(mocks.weth, executor, 36)
+        .scale_word(36, U256::from(95), U256::from(100))
+        .call_op();
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    let sent = TWO_ETH * U256::from(95) / U256::from(100);
+    assert_eq!(weth.balanceOf(BOB).call().await.unwrap(), sent);
+    assert_eq!(
+        weth.balanceOf(executor).call().await.unwrap(),
+        TWO_ETH - sent
+    );
+}
+
+#[tokio::test]
+async fn test_mock_profit_guard() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+    let weth = IERC20::new(mocks.weth, provider.clone());
+
+    // Wrapping is profitable in WETH...
+    let mut fb = FlowBuilder::empty();
+    fb.begin_profit_check(mocks.weth)
+        .call(mocks.weth, &[], TWO_ETH)
+        .require_profit(mocks.weth, TWO_ETH);
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    assert_eq!(weth.balanceOf(executor).call().await.unwrap(), TWO_ETH);
+
+    // ...but not by more than the amount wrapped...
+    let mut fb = FlowBuilder::empty();
+    fb.begin_profit_check(mocks.weth)
+        .call(mocks.weth, &[], TWO_ETH)
+        .require_profit(mocks.weth, TWO_ETH + U256::from(1));
+    assert!(!execute_flow(&provider, executor, &fb, TWO_ETH).await);
+
+    // ...and loses ETH
+    let mut fb = FlowBuilder::empty();
+    fb.begin_profit_check(Address::ZERO)
+        .call(mocks.weth, &[], TWO_ETH)
+        .require_profit(Address::ZERO, U256::ZERO);
+    assert!(!execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    assert_eq!(weth.balanceOf(executor).call().await.unwrap(), TWO_ETH);
+}
+
+#[tokio::test]
+async fn test_mock_block_guards() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+    let weth = IERC20::new(mocks.weth, provider.clone());
+
+    // The flow lands in the next block, so a guard on the latest one fails
+    let latest = provider.get_block_number().await.unwrap();
+    let mut fb = FlowBuilder::empty();
+    fb.call(mocks.weth, &[], TWO_ETH).require_block(latest);
+    assert!(!execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    let mut fb = FlowBuilder::empty();
+    fb.call(mocks.weth, &[], TWO_ETH)
+        .require_block(latest + 1)
+        .require_deadline(u64::MAX)
+        .require_max_basefee(U256::MAX);
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    assert_eq!(weth.balanceOf(executor).call().await.unwrap(), TWO_ETH);
+
+    let mut fb = FlowBuilder::empty();
+    fb.call(mocks.weth, &[], TWO_ETH).require_deadline(1);
+    assert!(!execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    let mut fb = FlowBuilder::empty();
+    fb.call(mocks.weth, &[], TWO_ETH)
+        .require_max_basefee(U256::ZERO);
+    assert!(!execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    assert_eq!(weth.balanceOf(executor).call().await.unwrap(), TWO_ETH);
+}
+
+#[tokio::test]
+async fn test_mock_checkpoints() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+
+    let mut fb = FlowBuilder::empty();
+    fb.call(mocks.weth, &[], TWO_ETH)
+        .set_cleardata_op(32)
+        .write_token_balance(mocks.weth, executor, 0)
+        .log(B256::right_padding_from(b"wrapped"), 0, 32);
+    let tx = TransactionRequest::default()
+        .with_from(WALLET)
+        .with_to(executor)
+        .with_value(TWO_ETH)
+        .with_input(fb.build());
+    let tx_hash = provider.eth_send_unsigned_transaction(tx).await.unwrap();
+    provider.evm_mine(None).await.unwrap();
+    let receipt = provider
+        .get_transaction_receipt(tx_hash)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(receipt.status());
+
+    let checkpoints =
+        decode_checkpoints(executor, receipt.inner.logs().iter().map(|log| &log.inner));
+    assert_eq!(checkpoints.len(), 1);
+    assert_eq!(checkpoints[0].tag_str(), Some("wrapped"));
+    assert_eq!(checkpoints[0].word(0), Some(TWO_ETH));
+}
+
+#[tokio::test]
+async fn test_mock_sweep() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+    let weth = IERC20::new(mocks.weth, provider.clone());
+
+    // Wrap half of the ETH sent along, then sweep both back
+    let wallet_before = provider.get_balance(WALLET).await.unwrap();
+    let mut fb = FlowBuilder::empty();
+    fb.call(mocks.weth, &[], TWO_ETH)
+        .sweep(&[mocks.weth], WALLET);
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH * U256::from(2)).await);
+    assert_eq!(weth.balanceOf(executor).call().await.unwrap(), U256::ZERO);
+    assert_eq!(weth.balanceOf(WALLET).call().await.unwrap(), TWO_ETH);
+    assert_eq!(provider.get_balance(executor).await.unwrap(), U256::ZERO);
+    // Only gas was spent on top of the wrapped ETH
+    let spent = wallet_before - provider.get_balance(WALLET).await.unwrap();
+    assert!(spent > TWO_ETH && spent < TWO_ETH * U256::from(2));
+}
+
+#[tokio::test]
+async fn test_mock_registers() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+    let weth = IERC20::new(mocks.weth, provider.clone());
+
+    // Remember the WETH balance after the first deposit, deposit again, then
+    // transfer only the remembered amount
+    let transfer = IERC20::transferCall {
+        to: BOB,
+        value: U256::ZERO,
+    }
+    .abi_encode();
+    let mut fb = FlowBuilder::empty();
+    fb.call(mocks.weth, &[], TWO_ETH)
+        .set_cleardata_op(32)
+        .write_token_balance(mocks.weth, executor, 0)
+        .store_register(0, 0)
+        .call(mocks.weth, &[], TWO_ETH)
+        .set_addr_op(mocks.weth)
+        .set_cleardata_op(transfer.len() as u16)
+        .set_data_op(0, &transfer)
+        .load_register(0, 36)
+        .call_op();
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH * U256::from(2)).await);
+    assert_eq!(weth.balanceOf(BOB).call().await.unwrap(), TWO_ETH);
+    assert_eq!(weth.balanceOf(executor).call().await.unwrap(), TWO_ETH);
+}
+
+#[tokio::test]
+async fn test_mock_dynamic_value() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+    let weth = IERC20::new(mocks.weth, provider.clone());
+
+    // Wrap whatever ETH the flow holds, without naming the amount
+    let mut fb = FlowBuilder::empty();
+    fb.set_addr_op(mocks.weth)
+        .set_value_from_balance()
+        .call_op();
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    assert_eq!(weth.balanceOf(executor).call().await.unwrap(), TWO_ETH);
+    assert_eq!(provider.get_balance(executor).await.unwrap(), U256::ZERO);
+
+    // Deposit as much again as the executor already holds in WETH
+    let mut fb = FlowBuilder::empty();
+    fb.set_cleardata_op(32)
+        .write_token_balance(mocks.weth, executor, 0)
+        .set_value_from_data(0)
+        .set_addr_op(mocks.weth)
+        .set_cleardata_op(0)
+        .call_op();
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    assert_eq!(
+        weth.balanceOf(executor).call().await.unwrap(),
+        TWO_ETH * U256::from(2)
+    );
+
+    // A reserve above the balance reverts
+    let mut fb = FlowBuilder::empty();
+    fb.set_addr_op(mocks.weth)
+        .set_value_from_balance_keeping(TEN_ETH)
+        .call_op();
+    assert!(!execute_flow(&provider, executor, &fb, TWO_ETH).await);
+}
+
+#[tokio::test]
+async fn test_mock_wide_calldata() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+    let weth = IERC20::new(mocks.weth, provider.clone());
+
+    // `deposit()` padded past 64 KiB, which only the 32-bit opcodes can write
+    let mut deposit = IWETH::depositCall {}.abi_encode();
+    deposit.resize(70_000, 0);
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail().call(mocks.weth, &deposit, TWO_ETH);
+    assert!(fb.iter().any(|a| a.encode()[0] == OP_SETDATA32));
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    assert_eq!(weth.balanceOf(executor).call().await.unwrap(), TWO_ETH);
+}
+
+#[tokio::test]
+async fn test_mock_call_with_gas() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+    let weth = IERC20::new(mocks.weth, provider.clone());
+
+    // Too little gas for the deposit: tolerated without SETFAIL...
+    let mut fb = FlowBuilder::empty();
+    fb.call_with_gas(mocks.weth, &[], TWO_ETH, 1_000)
+        .call(mocks.weth, &[], TWO_ETH);
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH * U256::from(2)).await);
+    assert_eq!(weth.balanceOf(executor).call().await.unwrap(), TWO_ETH);
+
+    // ...and reverts the flow with it
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail().call_with_gas(mocks.weth, &[], TWO_ETH, 1_000);
+    assert!(!execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .call_with_gas(mocks.weth, &[], TWO_ETH, 100_000);
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    assert_eq!(
+        weth.balanceOf(executor).call().await.unwrap(),
+        TWO_ETH * U256::from(2)
+    );
+}
+
+#[tokio::test]
+async fn test_mock_transfer_full_token_balance() {
+    let provider = setup_local_provider().await;
+    let executor = deploy_executor(&provider).await;
+    let mocks = deploy_mocks(&provider).await;
+    let weth = IERC20::new(mocks.weth, provider.clone());
+
+    let transfer = IERC20::transferCall {
+        to: BOB,
+        value: U256::ZERO,
+    }
+    .abi_encode();
+    let mut fb = FlowBuilder::empty();
+    fb.set_fail()
+        .call(mocks.weth, &[], TWO_ETH)
+        .set_cleardata_op(transfer.len() as u16)
+        .set_data_op(0, &transfer)
+        .write_token_balance(mocks.weth, executor, 36)
+        .call_op();
+    assert!(execute_flow(&provider, executor, &fb, TWO_ETH).await);
+    assert_eq!(weth.balanceOf(BOB).call().await.unwrap(), TWO_ETH);
+    assert_eq!(weth.balanceOf(executor).call().await.unwrap(), U256::ZERO);
+}
@@ -0,0 +1,156 @@
+//! Static checks over a flow, run against a model of the executor's state.
+//!
+//! The executor reverts the whole flow with `TXDATA_OOB` when an action
+//! reads or writes past the end of `txData`, and sends a call made before
+//! any target is set to the zero address. [`FlowBuilder::validate`] tracks
+//! the size of `txData` set by each `CLEARDATA` and whether a call target
+//! has been set, and reports every such action before the flow is sent.
+//!
+//! The model follows the actions in order, as if no jump were taken; labels
+//! and jump targets are checked separately.
+
+use std::{collections::HashSet, fmt};
+
+use crate::flow_builder::{Action, FlowBuilder, FlowError, JumpDest};
+
+/// A problem found by [`FlowBuilder::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Index of the offending action.
+    pub index: usize,
+    pub error: FlowError,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "action {}: {}", self.index, self.error)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl FlowBuilder {
+    /// Checks the flow against a model of the executor's `txData` buffer and
+    /// call target, returning every problem found.
+    ///
+    /// Flags buffer writes (`SETDATA`, `EXTCODECOPY`, `COPYRETURN` and the
+    /// balance queries) before any `CLEARDATA` or past the end of the buffer,
+    /// and `CALL`/`DELEGATECALL`/`STATICCALL` before any target has been set
+    /// by `SETADDR`, `CREATE` or `CREATE2`. Also flags `JUMPIFWORD`,
+    /// `SETVALUEDATA` and arithmetic words outside the buffer, profit checks
+    /// of a token that was never snapshotted, coinbase shares above 100%,
+    /// jumps to undefined labels and labels that are duplicated or out of a
+    /// jump's reach.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut buffer_size: Option<usize> = None;
+        let mut target_set = false;
+        let mut snapshots = HashSet::new();
+        let mut errors = Vec::new();
+
+        let mut labels = HashSet::new();
+        let mut pos = 0usize;
+        for (index, action) in self.iter().enumerate() {
+            if let Action::Label { name } = action {
+                let error = if !labels.insert(name.as_str()) {
+                    Some(FlowError::DuplicateLabel { name: name.clone() })
+                } else if pos > u16::MAX as usize {
+                    Some(FlowError::LabelOutOfRange {
+                        name: name.clone(),
+                        offset: pos,
+                    })
+                } else {
+                    None
+                };
+                errors.extend(error.map(|error| ValidationError { index, error }));
+            }
+            pos += action.encode_with(&mut |_| 0).len();
+        }
+
+        for (index, action) in self.iter().enumerate() {
+            if let Some(JumpDest::Label(name)) = action.jump_dest() {
+                if !labels.contains(name.as_str()) {
+                    errors.push(ValidationError {
+                        index,
+                        error: FlowError::UnknownLabel { name: name.clone() },
+                    });
+                }
+            }
+            let res = match action {
+                Action::ClearData { size } => {
+                    buffer_size = Some(*size as usize);
+                    Ok(())
+                }
+                Action::ClearData32 { size } => {
+                    buffer_size = Some(*size as usize);
+                    Ok(())
+                }
+                Action::SetData { offset, data } => {
+                    FlowError::check_set_data((*offset).into(), data, buffer_size)
+                }
+                Action::SetData32 { offset, data } => {
+                    FlowError::check_set_data(*offset, data, buffer_size)
+                }
+                Action::ExtCodeCopy {
+                    data_offset, size, ..
+                } => {
+                    FlowError::check_extcodecopy((*data_offset).into(), (*size).into(), buffer_size)
+                }
+                Action::ExtCodeCopy32 {
+                    data_offset, size, ..
+                } => FlowError::check_extcodecopy(*data_offset, *size, buffer_size),
+                Action::CopyReturn {
+                    data_offset, size, ..
+                } => FlowError::check_copy_return(*data_offset, *size, buffer_size),
+                Action::StoreReg {
+                    register,
+                    data_offset,
+                }
+                | Action::LoadReg {
+                    register,
+                    data_offset,
+                } => FlowError::check_register(*register, *data_offset, buffer_size),
+                Action::Log {
+                    data_offset, size, ..
+                } => FlowError::check_log(*data_offset, *size, buffer_size),
+                Action::SelfBalance { data_offset }
+                | Action::TokenBalance { data_offset, .. }
+                | Action::SetValueFromData { data_offset }
+                | Action::JumpIfWord { data_offset, .. } => {
+                    FlowError::check_word(*data_offset, buffer_size)
+                }
+                Action::Arith { dst, lhs, rhs, .. } => [*lhs, *rhs, *dst]
+                    .into_iter()
+                    .try_for_each(|offset| FlowError::check_word(offset, buffer_size)),
+                Action::ArithImm { dst, lhs, .. } => [*lhs, *dst]
+                    .into_iter()
+                    .try_for_each(|offset| FlowError::check_word(offset, buffer_size)),
+                Action::SetAddr { .. } | Action::Create { .. } | Action::Create2 { .. } => {
+                    target_set = true;
+                    Ok(())
+                }
+                Action::Call | Action::DelegateCall | Action::StaticCall if !target_set => {
+                    Err(FlowError::MissingTarget)
+                }
+                Action::PayCoinbase { payment } => FlowError::check_payment(*payment),
+                Action::Snapshot { token } => {
+                    snapshots.insert(*token);
+                    Ok(())
+                }
+                Action::CheckProfit { token, .. } if !snapshots.contains(token) => {
+                    Err(FlowError::MissingSnapshot { token: *token })
+                }
+                _ => Ok(()),
+            };
+            if let Err(error) = res {
+                errors.push(ValidationError { index, error });
+            }
+        }
+
+        errors.sort_by_key(|e| e.index);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}